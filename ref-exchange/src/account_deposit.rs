@@ -8,10 +8,10 @@ use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::{
     assert_one_yocto, env, near_bindgen, 
-    AccountId, Balance, PromiseResult, StorageUsage,
+    AccountId, Balance, Gas, PromiseResult, StorageUsage,
 };
 use crate::legacy::AccountV1;
-use crate::utils::{ext_self, GAS_FOR_FT_TRANSFER, GAS_FOR_RESOLVE_TRANSFER};
+use crate::utils::{ext_self, GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER, GAS_FOR_FT_TRANSFER_CALL, GAS_FOR_RESOLVE_TRANSFER};
 use crate::*;
 
 // [AUDIT_01]
@@ -171,6 +171,19 @@ impl Account {
             * env::storage_byte_cost()
     }
 
+    /// Pays for the contract storage taken since prev_storage from the storage deposit,
+    /// or returns the freed storage to it.
+    pub(crate) fn settle_storage(&mut self, prev_storage: StorageUsage) {
+        let storage_usage = env::storage_usage();
+        if storage_usage > prev_storage {
+            let storage_cost = (storage_usage - prev_storage) as Balance * env::storage_byte_cost();
+            assert!(self.storage_available() >= storage_cost, "{}", ERR11_INSUFFICIENT_STORAGE);
+            self.near_amount -= storage_cost;
+        } else {
+            self.near_amount += (prev_storage - storage_usage) as Balance * env::storage_byte_cost();
+        }
+    }
+
     /// Returns how much NEAR is available for storage.
     pub fn storage_available(&self) -> Balance {
         // [AUDIT_01] avoid math overflow
@@ -284,6 +297,107 @@ impl Contract {
         self.internal_send_tokens(&sender_id, &token_id, amount)
     }
 
+    /// Withdraws given token from the deposits of given user with `ft_transfer_call` to the receiver,
+    /// e.g. to deposit it into another protocol in one step.
+    /// A zero amount means to withdraw all in user's inner account.
+    /// Amount unused by the receiver is returned to the deposits.
+    #[payable]
+    pub fn withdraw_call(
+        &mut self,
+        token_id: ValidAccountId,
+        amount: U128,
+        receiver_id: ValidAccountId,
+        msg: String,
+    ) -> Promise {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let token_id: AccountId = token_id.into();
+        let sender_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&sender_id);
+        let mut amount: u128 = amount.into();
+        if amount == 0 {
+            amount = account.get_balance(&token_id).expect(ERR21_TOKEN_NOT_REG);
+        }
+        assert!(amount > 0, "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT);
+        account.withdraw(&token_id, amount);
+        self.internal_save_account(&sender_id, account);
+        ext_fungible_token::ft_transfer_call(
+            receiver_id.into(),
+            U128(amount),
+            None,
+            msg,
+            &token_id,
+            1,
+            env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL,
+        )
+        .then(ext_self::exchange_callback_post_withdraw_call(
+            token_id,
+            sender_id,
+            U128(amount),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Withdraws given tokens from the deposits of given user, each token is sent separately.
+    /// Withdraws all non-zero balances if no tokens given, a zero amount means to withdraw all of the token.
+    /// Optional unregister removes records of the emptied tokens.
+    /// If sending of a token fails, only that token is returned to the deposits.
+    /// Attached gas should cover the transfer and its callback for each withdrawn token.
+    #[payable]
+    pub fn withdraw_tokens(
+        &mut self,
+        token_amounts: Option<Vec<(ValidAccountId, U128)>>,
+        unregister: Option<bool>,
+    ) -> Promise {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let sender_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&sender_id);
+        let token_amounts: Vec<(AccountId, Balance)> = match token_amounts {
+            Some(token_amounts) => token_amounts
+                .into_iter()
+                .map(|(token_id, amount)| (token_id.into(), amount.0))
+                .collect(),
+            // Shares of the base pools in the deposits are not transferable tokens.
+            None => account
+                .get_tokens()
+                .into_iter()
+                .filter(|token_id| env::is_valid_account_id(token_id.as_bytes()))
+                .map(|token_id| (token_id, 0))
+                .collect(),
+        };
+
+        let mut withdrawn = vec![];
+        for (token_id, mut amount) in token_amounts {
+            let balance = account.get_balance(&token_id).expect(ERR21_TOKEN_NOT_REG);
+            if amount == 0 {
+                amount = balance;
+            }
+            if amount > 0 {
+                account.withdraw(&token_id, amount);
+                withdrawn.push((token_id.clone(), amount));
+            }
+            if unregister == Some(true) && balance == amount {
+                account.unregister(&token_id);
+            }
+        }
+        assert!(!withdrawn.is_empty(), "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT);
+        assert!(
+            env::prepaid_gas()
+                >= GAS_FOR_BASIC_OP + withdrawn.len() as Gas * (GAS_FOR_FT_TRANSFER + GAS_FOR_RESOLVE_TRANSFER),
+            "{}",
+            ERR20_NOT_ENOUGH_GAS_TO_WITHDRAW
+        );
+        self.internal_save_account(&sender_id, account);
+        withdrawn
+            .into_iter()
+            .map(|(token_id, amount)| self.internal_send_tokens(&sender_id, &token_id, amount))
+            .reduce(|joint, promise| joint.and(promise))
+            .unwrap()
+    }
+
     #[private]
     pub fn exchange_callback_post_withdraw(
         &mut self,
@@ -300,42 +414,91 @@ impl Contract {
         match env::promise_result(0) {
             PromiseResult::NotReady => unreachable!(),
             PromiseResult::Successful(_) => {}
-            PromiseResult::Failed => {
-                // This reverts the changes from withdraw function.
-                // If account doesn't exit, deposits to the owner's account as lostfound.
-                let mut failed = false;
-                if let Some(mut account) = self.internal_get_account(&sender_id) {
-                    if account.deposit_with_storage_check(&token_id, amount.0) {
-                        // cause storage already checked, here can directly save
-                        self.accounts.insert(&sender_id, &account.into());
-                    } else {
-                        // we can ensure that internal_get_account here would NOT cause a version upgrade, 
-                        // cause it is callback, the account must be the current version or non-exist,
-                        // so, here we can just leave it without insert, won't cause storage collection inconsistency.
-                        env::log(
-                            format!(
-                                "Account {} has not enough storage. Depositing to owner.",
-                                sender_id
-                            )
-                            .as_bytes(),
-                        );
-                        failed = true;
-                    }
-                } else {
-                    env::log(
-                        format!(
-                            "Account {} is not registered. Depositing to owner.",
-                            sender_id
-                        )
-                        .as_bytes(),
-                    );
-                    failed = true;
-                }
-                if failed {
-                    self.internal_lostfound(&token_id, amount.0);
+            // This reverts the changes from withdraw function.
+            PromiseResult::Failed => self.internal_deposit_or_lostfound(&token_id, &sender_id, amount.0),
+        };
+    }
+
+    /// Returns amount unused by the receiver of `withdraw_call` to the deposits of the sender.
+    /// Returns amount used by the receiver.
+    #[private]
+    pub fn exchange_callback_post_withdraw_call(
+        &mut self,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        let used_amount = match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) => {
+                match near_sdk::serde_json::from_slice::<U128>(&value) {
+                    Ok(used_amount) => std::cmp::min(amount.0, used_amount.0),
+                    Err(_) => amount.0,
                 }
             }
+            PromiseResult::Failed => 0,
         };
+        let unused_amount = amount.0 - used_amount;
+        if unused_amount > 0 {
+            self.internal_deposit_or_lostfound(&token_id, &sender_id, unused_amount);
+        }
+        U128(used_amount)
+    }
+
+    /// Keeps tokens, that failed to be sent after an instant swap, for the receiver.
+    #[private]
+    pub fn exchange_callback_post_send(
+        &mut self,
+        token_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {}
+            PromiseResult::Failed => {
+                self.internal_deposit_or_keep_unclaimed(&receiver_id, &token_id, amount.0);
+            }
+        };
+    }
+
+    /// Sends the sender its tokens, that are kept unclaimed.
+    /// Storage of the unclaimed tokens is fronted by the contract, so the sender should attach
+    /// the storage fee it took, extra deposit is refunded.
+    /// If sending fails again, they are deposited or kept unclaimed the same way.
+    #[payable]
+    pub fn claim_tokens(&mut self, token_id: ValidAccountId) -> Promise {
+        self.assert_contract_running();
+        let sender_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        let mut tokens = self.unclaimed_tokens.get(&sender_id).unwrap_or_default();
+        let amount = tokens.remove(token_id.as_ref()).unwrap_or(0);
+        assert!(amount > 0, "{}", ERR30_NO_UNCLAIMED_TOKENS);
+        if tokens.is_empty() {
+            self.unclaimed_tokens.remove(&sender_id);
+        } else {
+            self.unclaimed_tokens.insert(&sender_id, &tokens);
+        }
+        let storage_fee = prev_storage.saturating_sub(env::storage_usage()) as Balance * env::storage_byte_cost();
+        let refund = env::attached_deposit()
+            .checked_sub(std::cmp::max(storage_fee, 1))
+            .expect(ERR11_INSUFFICIENT_STORAGE);
+        if refund > 0 {
+            Promise::new(sender_id.clone()).transfer(refund);
+        }
+        self.internal_send_tokens_or_deposit(&sender_id, token_id.as_ref(), amount)
     }
 }
 
@@ -362,6 +525,69 @@ impl Contract {
     }
     
 
+    /// Deposits tokens in a callback, where they can't be returned otherwise.
+    pub(crate) fn internal_deposit_or_lostfound(
+        &mut self,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: Balance,
+    ) {
+        // If account doesn't exit, deposits to the owner's account as lostfound.
+        let mut failed = false;
+        if let Some(mut account) = self.internal_get_account(sender_id) {
+            if account.deposit_with_storage_check(token_id, amount) {
+                // cause storage already checked, here can directly save
+                self.accounts.insert(sender_id, &account.into());
+            } else {
+                // we can ensure that internal_get_account here would NOT cause a version upgrade, 
+                // cause it is callback, the account must be the current version or non-exist,
+                // so, here we can just leave it without insert, won't cause storage collection inconsistency.
+                env::log(
+                    format!(
+                        "Account {} has not enough storage. Depositing to owner.",
+                        sender_id
+                    )
+                    .as_bytes(),
+                );
+                failed = true;
+            }
+        } else {
+            env::log(
+                format!(
+                    "Account {} is not registered. Depositing to owner.",
+                    sender_id
+                )
+                .as_bytes(),
+            );
+            failed = true;
+        }
+        if failed {
+            self.internal_lostfound(token_id, amount);
+        }
+    }
+
+    /// Deposits tokens to the registered account if it has storage for them, otherwise keeps them unclaimed,
+    /// until the account claims them with `claim_tokens`, paying back the storage they take.
+    pub(crate) fn internal_deposit_or_keep_unclaimed(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        if let Some(mut account) = self.internal_get_account(account_id) {
+            if account.deposit_with_storage_check(token_id, amount) {
+                // As in internal_deposit_or_lostfound, storage is already checked and the account
+                // in a callback is of the current version, so here can directly save.
+                self.accounts.insert(account_id, &account.into());
+                return;
+            }
+        }
+        log!("Account {} can't take {} {}, keeping them unclaimed.", account_id, amount, token_id);
+        let mut tokens = self.unclaimed_tokens.get(account_id).unwrap_or_default();
+        *tokens.entry(token_id.clone()).or_insert(0) += amount;
+        self.unclaimed_tokens.insert(account_id, &tokens);
+    }
+
     /// Registers account in deposited amounts with given amount of $NEAR.
     /// If account already exists, adds amount to it.
     /// This should be used when it's known that storage is prepaid.
@@ -457,4 +683,30 @@ impl Contract {
             GAS_FOR_RESOLVE_TRANSFER,
         ))
     }
+
+    /// Sends given amount to given user and if it fails, deposits it to user's balance or keeps it unclaimed.
+    /// Tokens must already be subtracted from internal balance.
+    pub(crate) fn internal_send_tokens_or_deposit(
+        &self,
+        receiver_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> Promise {
+        ext_fungible_token::ft_transfer(
+            receiver_id.clone(),
+            U128(amount),
+            None,
+            token_id,
+            1,
+            GAS_FOR_FT_TRANSFER,
+        )
+        .then(ext_self::exchange_callback_post_send(
+            token_id.clone(),
+            receiver_id.clone(),
+            U128(amount),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
 }
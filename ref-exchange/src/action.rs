@@ -1,6 +1,7 @@
-use crate::errors::ERR41_WRONG_ACTION_RESULT;
+use crate::errors::{ERR41_WRONG_ACTION_RESULT, ERR74_DEADLINE_EXPIRED};
+use near_sdk::json_types::{WrappedTimestamp, U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{env, json_types::U128, AccountId, Balance};
+use near_sdk::{env, AccountId, Balance};
 
 /// Single swap action.
 #[derive(Serialize, Deserialize)]
@@ -20,25 +21,192 @@ pub struct SwapAction {
     pub min_amount_out: U128,
 }
 
+/// Single swap action, that receives exact amount of token_out.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapByOutputAction {
+    /// Pool which should be used for swapping.
+    pub pool_id: u64,
+    /// Token to swap from.
+    pub token_in: AccountId,
+    /// Amount to receive.
+    /// If amount_out is None, it will take amount_in of the next step.
+    /// Will fail if amount_out is None on the last step of the route.
+    pub amount_out: Option<U128>,
+    /// Token to swap into.
+    pub token_out: AccountId,
+    /// Allowed maximum amount of token_in.
+    pub max_amount_in: U128,
+}
+
+/// Swap split across several routes, that run one after another, each starting with its own amount_in.
+/// Only the total amount received from all the routes is checked against the minimum,
+/// so slippage of each route can be left unbounded with zero min_amount_out.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SplitSwapAction {
+    /// Routes of swap actions, the first action of each route should have amount_in.
+    pub routes: Vec<Vec<SwapAction>>,
+    /// Token received at the end of every route.
+    pub token_out: AccountId,
+    /// Required minimum of the total amount of token_out received from all the routes.
+    pub min_total_out: U128,
+}
+
+/// Adds liquidity to the simple or weighted pool from the deposits, same as `add_liquidity`.
+/// Result is the amount of minted shares.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AddLiquidityAction {
+    pub pool_id: u64,
+    /// Amounts of the pool tokens, in the pool tokens order.
+    /// A null amount takes the amount from the previous step, or the amount at the same position,
+    /// if the previous step received amounts of the pool tokens.
+    pub amounts: Vec<Option<U128>>,
+    pub min_amounts: Option<Vec<U128>>,
+}
+
+/// Adds liquidity to the stable or rated pool from the deposits, same as `add_stable_liquidity`.
+/// Result is the amount of minted shares.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AddStableLiquidityAction {
+    pub pool_id: u64,
+    /// Amounts of the pool tokens, in the pool tokens order.
+    /// A null amount takes the amount from the previous step, or the amount at the same position,
+    /// if the previous step received amounts of the pool tokens.
+    pub amounts: Vec<Option<U128>>,
+    pub min_shares: U128,
+}
+
+/// Removes liquidity from the pool into the deposits, same as `remove_liquidity`.
+/// Result is the amounts of the pool tokens received.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RemoveLiquidityAction {
+    pub pool_id: u64,
+    /// Shares to burn. If shares is None, it will take amount from the previous step.
+    pub shares: Option<U128>,
+    pub min_amounts: Vec<U128>,
+}
+
+/// Transfers deposited token, or shares of the pool given as `:pool_id`, to another account of the exchange.
+/// Result is the transferred amount.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferAction {
+    pub token_id: String,
+    pub receiver_id: AccountId,
+    /// If amount is None, it will take amount from the previous step.
+    pub amount: Option<U128>,
+}
+
+/// Withdraws deposited token to the account owner, same as `withdraw`.
+/// Result is the withdrawn amount.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawAction {
+    pub token_id: AccountId,
+    /// If amount is None, it will take amount from the previous step.
+    pub amount: Option<U128>,
+}
+
 /// Single action. Allows to execute sequence of various actions initiated by an account.
+/// Actions are told apart by their fields, in the order of variants,
+/// so a variant should come before the ones, which fields are a subset of its fields.
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 #[serde(untagged)]
 pub enum Action {
     Swap(SwapAction),
+    SwapByOutput(SwapByOutputAction),
+    SplitSwap(SplitSwapAction),
+    AddStableLiquidity(AddStableLiquidityAction),
+    AddLiquidity(AddLiquidityAction),
+    RemoveLiquidity(RemoveLiquidityAction),
+    Transfer(TransferAction),
+    Withdraw(WithdrawAction),
 }
 
 impl Action {
+    /// Returns pools used by this action.
+    pub fn pool_ids(&self) -> Vec<u64> {
+        match self {
+            Action::Swap(swap_action) => vec![swap_action.pool_id],
+            Action::SwapByOutput(swap_action) => vec![swap_action.pool_id],
+            Action::SplitSwap(split_action) => split_action
+                .routes
+                .iter()
+                .flatten()
+                .map(|swap_action| swap_action.pool_id)
+                .collect(),
+            Action::AddStableLiquidity(_)
+            | Action::AddLiquidity(_)
+            | Action::RemoveLiquidity(_) => self.liquidity_pool_id().into_iter().collect(),
+            Action::Transfer(_) | Action::Withdraw(_) => vec![],
+        }
+    }
+
+    /// Returns pool, which liquidity is changed by this action.
+    /// Tokens of this pool are involved in the action in addition to `tokens`.
+    pub fn liquidity_pool_id(&self) -> Option<u64> {
+        match self {
+            Action::AddStableLiquidity(action) => Some(action.pool_id),
+            Action::AddLiquidity(action) => Some(action.pool_id),
+            Action::RemoveLiquidity(action) => Some(action.pool_id),
+            _ => None,
+        }
+    }
+
     /// Returns involved tokens in this action. Useful for checking permissions and storage.
     pub fn tokens(&self) -> Vec<AccountId> {
         match self {
             Action::Swap(swap_action) => {
                 vec![swap_action.token_in.clone(), swap_action.token_out.clone()]
             }
+            Action::SwapByOutput(swap_action) => {
+                vec![swap_action.token_in.clone(), swap_action.token_out.clone()]
+            }
+            Action::SplitSwap(split_action) => split_action
+                .routes
+                .iter()
+                .flatten()
+                .flat_map(|swap_action| vec![swap_action.token_in.clone(), swap_action.token_out.clone()])
+                .collect(),
+            Action::AddStableLiquidity(_)
+            | Action::AddLiquidity(_)
+            | Action::RemoveLiquidity(_) => vec![],
+            // Pool shares are not deposited tokens.
+            Action::Transfer(action) if action.token_id.starts_with(':') => vec![],
+            Action::Transfer(action) => vec![action.token_id.clone()],
+            Action::Withdraw(action) => vec![action.token_id.clone()],
         }
     }
 }
 
+/// Deadline of the actions, after which they fail instead of being executed at a stale price.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Deadline {
+    /// Last block timestamp in nanoseconds, when the actions can be executed.
+    Timestamp(WrappedTimestamp),
+    /// Last block height, when the actions can be executed.
+    BlockHeight(U64),
+}
+
+impl Deadline {
+    pub fn is_expired(&self) -> bool {
+        match self {
+            Deadline::Timestamp(timestamp) => env::block_timestamp() > timestamp.0,
+            Deadline::BlockHeight(height) => env::block_index() > height.0,
+        }
+    }
+
+    pub fn assert_not_expired(&self) {
+        assert!(!self.is_expired(), "{}", ERR74_DEADLINE_EXPIRED);
+    }
+}
+
 /// Result from action execution.
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -48,6 +216,8 @@ pub enum ActionResult {
     /// Amount of token was received.
     /// [AUDIT_02]
     Amount(U128),
+    /// Amounts of the pool tokens were received.
+    Amounts(Vec<U128>),
 }
 
 impl ActionResult {
@@ -58,4 +228,22 @@ impl ActionResult {
             _ => env::panic(ERR41_WRONG_ACTION_RESULT.as_bytes()),
         }
     }
+
+    /// Resolves amounts of the action, taking the amount of this result for the missing ones.
+    /// Amounts of the pool tokens from this result fill the missing ones at the same positions.
+    pub fn resolve_amounts(self, amounts: &[Option<U128>]) -> Vec<Balance> {
+        if amounts.iter().all(Option::is_some) {
+            return amounts.iter().map(|amount| amount.unwrap().0).collect();
+        }
+        let prev_amounts = match self {
+            ActionResult::Amounts(prev_amounts) if prev_amounts.len() == amounts.len() => prev_amounts,
+            ActionResult::Amounts(_) => env::panic(ERR41_WRONG_ACTION_RESULT.as_bytes()),
+            result => vec![U128(result.to_amount()); amounts.len()],
+        };
+        amounts
+            .iter()
+            .zip(prev_amounts)
+            .map(|(amount, prev_amount)| amount.unwrap_or(prev_amount).0)
+            .collect()
+    }
 }
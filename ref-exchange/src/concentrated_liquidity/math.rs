@@ -0,0 +1,368 @@
+//! Tick and price math for the concentrated liquidity pool.
+//! Prices are kept as square roots in Q64.96 fixed point, same as in Uniswap V3:
+//! https://github.com/Uniswap/v3-core/tree/main/contracts/libraries
+use near_sdk::Balance;
+
+use crate::errors::*;
+use crate::utils::{FEE_DIVISOR, U256, U384};
+
+/// Min tick, price of `1.0001 ^ MIN_TICK` is about 2^-128.
+pub const MIN_TICK: i32 = -887272;
+/// Max tick, price of `1.0001 ^ MAX_TICK` is about 2^128.
+pub const MAX_TICK: i32 = 887272;
+/// Max distance between two usable ticks.
+pub const MAX_TICK_SPACING: u32 = 16384;
+/// Number of fractional bits in square root price.
+pub const RESOLUTION: usize = 96;
+
+/// Square root price at MIN_TICK.
+pub fn min_sqrt_price() -> U256 {
+    U256::from(4295128739u64)
+}
+
+/// Square root price at MAX_TICK.
+pub fn max_sqrt_price() -> U256 {
+    U256::from_dec_str("1461446703485210103287273052203988822378723970342").unwrap()
+}
+
+fn to_u256(value: U384) -> U256 {
+    assert!(value.0[4] == 0 && value.0[5] == 0, "{}", ERR143_PRICE_MATH_OVERFLOW);
+    U256([value.0[0], value.0[1], value.0[2], value.0[3]])
+}
+
+/// Returns `a * b / denominator` rounded down, without overflowing on the intermediate product.
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> U256 {
+    to_u256(U384::from(a) * U384::from(b) / U384::from(denominator))
+}
+
+/// Returns `a * b / denominator` rounded up.
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> U256 {
+    let product = U384::from(a) * U384::from(b);
+    let denominator = U384::from(denominator);
+    let mut result = product / denominator;
+    if !(product % denominator).is_zero() {
+        result += U384::one();
+    }
+    to_u256(result)
+}
+
+fn div_rounding_up(a: U256, b: U256) -> U256 {
+    let result = a / b;
+    if (a % b).is_zero() {
+        result
+    } else {
+        result + 1
+    }
+}
+
+/// Returns square root price `sqrt(1.0001 ^ tick)` as Q64.96.
+pub fn get_sqrt_price_at_tick(tick: i32) -> U256 {
+    assert!((MIN_TICK..=MAX_TICK).contains(&tick), "{}", ERR141_ILLEGAL_TICK_RANGE);
+    let abs_tick = tick.unsigned_abs();
+    let mut ratio = if abs_tick & 0x1 != 0 {
+        U256::from(0xfffcb933bd6fad37aa2d162d1a594001u128)
+    } else {
+        U256::one() << 128
+    };
+    // Multipliers are `2^128 / sqrt(1.0001) ^ (2^i)` for i-th bit of the tick.
+    let multipliers: [(u32, u128); 19] = [
+        (0x2, 0xfff97272373d413259a46990580e213a),
+        (0x4, 0xfff2e50f5f656932ef12357cf3c7fdcc),
+        (0x8, 0xffe5caca7e10e4e61c3624eaa0941cd0),
+        (0x10, 0xffcb9843d60f6159c9db58835c926644),
+        (0x20, 0xff973b41fa98c081472e6896dfb254c0),
+        (0x40, 0xff2ea16466c96a3843ec78b326b52861),
+        (0x80, 0xfe5dee046a99a2a811c461f1969c3053),
+        (0x100, 0xfcbe86c7900a88aedcffc83b479aa3a4),
+        (0x200, 0xf987a7253ac413176f2b074cf7815e54),
+        (0x400, 0xf3392b0822b70005940c7a398e4b70f3),
+        (0x800, 0xe7159475a2c29b7443b29c7fa6e889d9),
+        (0x1000, 0xd097f3bdfd2022b8845ad8f792aa5825),
+        (0x2000, 0xa9f746462d870fdf8a65dc1f90e061e5),
+        (0x4000, 0x70d869a156d2a1b890bb3df62baf32f7),
+        (0x8000, 0x31be135f97d08fd981231505542fcfa6),
+        (0x10000, 0x9aa508b5b7a84e1c677de54f3e99bc9),
+        (0x20000, 0x5d6af8dedb81196699c329225ee604),
+        (0x40000, 0x2216e584f5fa1ea926041bedfe98),
+        (0x80000, 0x48a170391f7dc42444e8fa2),
+    ];
+    for (bit, multiplier) in multipliers.iter() {
+        if abs_tick & bit != 0 {
+            ratio = (ratio * U256::from(*multiplier)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::max_value() / ratio;
+    }
+    // Convert from Q128.128 to Q64.96, rounding up.
+    let shift = 128 - RESOLUTION;
+    let rounding = if (ratio % (U256::one() << shift)).is_zero() { 0 } else { 1 };
+    (ratio >> shift) + rounding
+}
+
+/// Returns the greatest tick, which square root price is less or equal to the given one.
+pub fn get_tick_at_sqrt_price(sqrt_price: U256) -> i32 {
+    assert!(
+        sqrt_price >= min_sqrt_price() && sqrt_price < max_sqrt_price(),
+        "{}", ERR141_ILLEGAL_TICK_RANGE
+    );
+    let mut low = MIN_TICK;
+    let mut high = MAX_TICK;
+    while low < high {
+        let middle = low + (high - low + 1) / 2;
+        if get_sqrt_price_at_tick(middle) <= sqrt_price {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    low
+}
+
+/// Amount of token 0 between two prices for given liquidity: `L * (sqrt_b - sqrt_a) / (sqrt_a * sqrt_b)`.
+pub fn get_amount0_delta(sqrt_a: U256, sqrt_b: U256, liquidity: u128, round_up: bool) -> U256 {
+    let (sqrt_a, sqrt_b) = if sqrt_a > sqrt_b { (sqrt_b, sqrt_a) } else { (sqrt_a, sqrt_b) };
+    let numerator1 = U256::from(liquidity) << RESOLUTION;
+    let numerator2 = sqrt_b - sqrt_a;
+    if round_up {
+        div_rounding_up(mul_div_rounding_up(numerator1, numerator2, sqrt_b), sqrt_a)
+    } else {
+        mul_div(numerator1, numerator2, sqrt_b) / sqrt_a
+    }
+}
+
+/// Amount of token 1 between two prices for given liquidity: `L * (sqrt_b - sqrt_a)`.
+pub fn get_amount1_delta(sqrt_a: U256, sqrt_b: U256, liquidity: u128, round_up: bool) -> U256 {
+    let (sqrt_a, sqrt_b) = if sqrt_a > sqrt_b { (sqrt_b, sqrt_a) } else { (sqrt_a, sqrt_b) };
+    let q96 = U256::one() << RESOLUTION;
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), sqrt_b - sqrt_a, q96)
+    } else {
+        mul_div(U256::from(liquidity), sqrt_b - sqrt_a, q96)
+    }
+}
+
+/// Next square root price after adding `amount` of token 0, rounded up so price moves less.
+fn get_next_sqrt_price_from_amount0(sqrt_price: U256, liquidity: u128, amount: U256) -> U256 {
+    if amount.is_zero() {
+        return sqrt_price;
+    }
+    let numerator1 = U256::from(liquidity) << RESOLUTION;
+    let denominator = U384::from(numerator1) + U384::from(amount) * U384::from(sqrt_price);
+    let product = U384::from(numerator1) * U384::from(sqrt_price);
+    let mut result = product / denominator;
+    if !(product % denominator).is_zero() {
+        result += U384::one();
+    }
+    to_u256(result)
+}
+
+/// Next square root price after adding `amount` of token 1, rounded down so price moves less.
+fn get_next_sqrt_price_from_amount1(sqrt_price: U256, liquidity: u128, amount: U256) -> U256 {
+    sqrt_price + mul_div(amount, U256::one() << RESOLUTION, U256::from(liquidity))
+}
+
+/// Result of swapping within a single range of constant liquidity.
+pub struct SwapStep {
+    pub sqrt_price_next: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+}
+
+/// Computes swap of up to `amount_remaining` (including fee) within the price range from
+/// `sqrt_price_current` to `sqrt_price_target`, stopping at the target if the input is enough to reach it.
+pub fn compute_swap_step(
+    sqrt_price_current: U256,
+    sqrt_price_target: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    fee: u32,
+) -> SwapStep {
+    let zero_for_one = sqrt_price_current >= sqrt_price_target;
+    let amount_remaining_less_fee = mul_div(
+        amount_remaining,
+        U256::from(FEE_DIVISOR - fee),
+        U256::from(FEE_DIVISOR),
+    );
+    let amount_in_to_target = if zero_for_one {
+        get_amount0_delta(sqrt_price_target, sqrt_price_current, liquidity, true)
+    } else {
+        get_amount1_delta(sqrt_price_current, sqrt_price_target, liquidity, true)
+    };
+    let sqrt_price_next = if amount_remaining_less_fee >= amount_in_to_target {
+        sqrt_price_target
+    } else if zero_for_one {
+        get_next_sqrt_price_from_amount0(sqrt_price_current, liquidity, amount_remaining_less_fee)
+    } else {
+        get_next_sqrt_price_from_amount1(sqrt_price_current, liquidity, amount_remaining_less_fee)
+    };
+    let reached_target = sqrt_price_next == sqrt_price_target;
+    let (amount_in, amount_out) = if zero_for_one {
+        (
+            if reached_target {
+                amount_in_to_target
+            } else {
+                get_amount0_delta(sqrt_price_next, sqrt_price_current, liquidity, true)
+            },
+            get_amount1_delta(sqrt_price_next, sqrt_price_current, liquidity, false),
+        )
+    } else {
+        (
+            if reached_target {
+                amount_in_to_target
+            } else {
+                get_amount1_delta(sqrt_price_current, sqrt_price_next, liquidity, true)
+            },
+            get_amount0_delta(sqrt_price_current, sqrt_price_next, liquidity, false),
+        )
+    };
+    let fee_amount = if reached_target {
+        mul_div_rounding_up(amount_in, U256::from(fee), U256::from(FEE_DIVISOR - fee))
+    } else {
+        // The whole remaining input is taken, what is not swapped goes to fee.
+        amount_remaining - amount_in
+    };
+    SwapStep {
+        sqrt_price_next,
+        amount_in,
+        amount_out,
+        fee_amount,
+    }
+}
+
+/// Max liquidity that can be provided with given amounts within price range of `[sqrt_a, sqrt_b)`,
+/// when the current price is `sqrt_price`.
+pub fn get_liquidity_for_amounts(
+    sqrt_price: U256,
+    sqrt_a: U256,
+    sqrt_b: U256,
+    amount0: Balance,
+    amount1: Balance,
+) -> u128 {
+    let q96 = U256::one() << RESOLUTION;
+    let liquidity0 = |sqrt_a: U256, sqrt_b: U256| {
+        let intermediate = mul_div(sqrt_a, sqrt_b, q96);
+        mul_div(U256::from(amount0), intermediate, sqrt_b - sqrt_a)
+    };
+    let liquidity1 = |sqrt_a: U256, sqrt_b: U256| mul_div(U256::from(amount1), q96, sqrt_b - sqrt_a);
+    let liquidity = if sqrt_price <= sqrt_a {
+        liquidity0(sqrt_a, sqrt_b)
+    } else if sqrt_price < sqrt_b {
+        std::cmp::min(liquidity0(sqrt_price, sqrt_b), liquidity1(sqrt_a, sqrt_price))
+    } else {
+        liquidity1(sqrt_a, sqrt_b)
+    };
+    assert!(liquidity <= U256::from(u128::MAX), "{}", ERR143_PRICE_MATH_OVERFLOW);
+    liquidity.as_u128()
+}
+
+/// Amounts of tokens backing given liquidity within price range of `[sqrt_a, sqrt_b)`,
+/// when the current price is `sqrt_price`.
+pub fn get_amounts_for_liquidity(
+    sqrt_price: U256,
+    sqrt_a: U256,
+    sqrt_b: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> (U256, U256) {
+    if sqrt_price <= sqrt_a {
+        (get_amount0_delta(sqrt_a, sqrt_b, liquidity, round_up), U256::zero())
+    } else if sqrt_price < sqrt_b {
+        (
+            get_amount0_delta(sqrt_price, sqrt_b, liquidity, round_up),
+            get_amount1_delta(sqrt_a, sqrt_price, liquidity, round_up),
+        )
+    } else {
+        (U256::zero(), get_amount1_delta(sqrt_a, sqrt_b, liquidity, round_up))
+    }
+}
+
+/// Adds signed delta to liquidity.
+pub fn add_liquidity_delta(liquidity: u128, delta: i128) -> u128 {
+    if delta < 0 {
+        liquidity
+            .checked_sub(delta.unsigned_abs())
+            .expect(ERR143_PRICE_MATH_OVERFLOW)
+    } else {
+        liquidity
+            .checked_add(delta as u128)
+            .expect(ERR143_PRICE_MATH_OVERFLOW)
+    }
+}
+
+/// Fees in token for given liquidity and growth of fees per unit of liquidity in Q128.128.
+pub fn fees_for_growth(liquidity: u128, fee_growth: U256) -> Balance {
+    let fees = (U384::from(fee_growth) * U384::from(liquidity)) >> 128;
+    to_u256(fees).as_u128()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_f64(value: U256) -> f64 {
+        value.to_string().parse::<f64>().unwrap()
+    }
+
+    #[test]
+    fn test_sqrt_price_at_tick() {
+        assert_eq!(get_sqrt_price_at_tick(MIN_TICK), min_sqrt_price());
+        assert_eq!(get_sqrt_price_at_tick(MAX_TICK), max_sqrt_price());
+        assert_eq!(get_sqrt_price_at_tick(0), U256::one() << RESOLUTION);
+        let q96 = 2f64.powi(RESOLUTION as i32);
+        for &tick in [-500000, -50000, -4321, -1, 1, 10, 4321, 50000, 500000].iter() {
+            let expected = 1.0001f64.powf(tick as f64 / 2.0) * q96;
+            let actual = to_f64(get_sqrt_price_at_tick(tick));
+            assert!((actual - expected).abs() / expected < 1e-10, "tick {}", tick);
+        }
+    }
+
+    #[test]
+    fn test_tick_at_sqrt_price() {
+        for &tick in [MIN_TICK, -500000, -1, 0, 1, 4321, 500000, MAX_TICK - 1].iter() {
+            let sqrt_price = get_sqrt_price_at_tick(tick);
+            assert_eq!(get_tick_at_sqrt_price(sqrt_price), tick);
+            assert_eq!(get_tick_at_sqrt_price(sqrt_price + 1), tick);
+            if tick > MIN_TICK {
+                assert_eq!(get_tick_at_sqrt_price(sqrt_price - 1), tick - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_swap_step() {
+        let liquidity = 10u128.pow(24);
+        let current = get_sqrt_price_at_tick(0);
+        let target = get_sqrt_price_at_tick(-1000);
+        // Small amount stays within the range.
+        let step = compute_swap_step(current, target, liquidity, U256::from(10u128.pow(18)), 30);
+        assert!(step.sqrt_price_next < current && step.sqrt_price_next > target);
+        assert_eq!(step.amount_in + step.fee_amount, U256::from(10u128.pow(18)));
+        assert!(step.amount_out < step.amount_in);
+        // Large amount reaches the target and leaves the rest.
+        let step = compute_swap_step(current, target, liquidity, U256::from(10u128.pow(26)), 30);
+        assert_eq!(step.sqrt_price_next, target);
+        assert_eq!(
+            step.amount_in,
+            get_amount0_delta(target, current, liquidity, true)
+        );
+        assert!(step.amount_in + step.fee_amount < U256::from(10u128.pow(26)));
+    }
+
+    #[test]
+    fn test_liquidity_amounts_roundtrip() {
+        let sqrt_price = get_sqrt_price_at_tick(100);
+        let sqrt_a = get_sqrt_price_at_tick(-600);
+        let sqrt_b = get_sqrt_price_at_tick(600);
+        let amount0 = 10u128.pow(24);
+        let amount1 = 10u128.pow(24);
+        let liquidity = get_liquidity_for_amounts(sqrt_price, sqrt_a, sqrt_b, amount0, amount1);
+        let (used0, used1) = get_amounts_for_liquidity(sqrt_price, sqrt_a, sqrt_b, liquidity, true);
+        assert!(used0 <= U256::from(amount0) && used1 <= U256::from(amount1));
+        // One of the tokens is fully used.
+        assert!(
+            U256::from(amount0) - used0 <= U256::one() || U256::from(amount1) - used1 <= U256::one()
+        );
+    }
+}
@@ -0,0 +1,742 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, TreeMap};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{env, AccountId, Balance};
+
+use crate::admin_fee::AdminFees;
+use crate::concentrated_liquidity::math::{
+    add_liquidity_delta, compute_swap_step, fees_for_growth, get_amounts_for_liquidity,
+    get_liquidity_for_amounts, get_sqrt_price_at_tick, get_tick_at_sqrt_price, max_sqrt_price,
+    min_sqrt_price, MAX_TICK, MAX_TICK_SPACING, MIN_TICK,
+};
+use crate::errors::*;
+use crate::utils::{SwapVolume, FEE_DIVISOR, U256};
+use crate::StorageKey;
+
+pub mod math;
+
+const NUM_TOKENS: usize = 2;
+
+/// State of an initialized tick, which is a boundary of at least one position.
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct TickInfo {
+    /// Total liquidity of positions, that use this tick as a boundary.
+    pub liquidity_gross: u128,
+    /// Liquidity to add when price crosses this tick from left to right.
+    pub liquidity_net: i128,
+    /// Fee growth per unit of liquidity on the other side of this tick from the current price, in Q128.128.
+    pub fee_growth_outside: [U256; NUM_TOKENS],
+}
+
+/// Liquidity provided by an account within price range of `[tick_lower, tick_upper)`.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Position {
+    pub owner_id: AccountId,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub liquidity: u128,
+    /// Fee growth inside of the range, as of the last update of the position, in Q128.128.
+    pub fee_growth_inside_last: [U256; NUM_TOKENS],
+    /// Fees and removed liquidity, not yet paid out to the owner.
+    pub tokens_owed: [Balance; NUM_TOKENS],
+}
+
+/// Implementation of concentrated liquidity pool of two tokens. Similar in design to "Uniswap V3".
+/// Liquidity providers open positions within chosen price ranges, so their liquidity is used only
+/// while the price is within that range. Fees are accrued to each position separately.
+/// Positions are not fungible, so there are no LP shares in this pool.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ConcentratedLiquidityPool {
+    /// List of tokens in the pool.
+    pub token_account_ids: Vec<AccountId>,
+    /// How much of each token is in the pool, including not collected fees.
+    pub amounts: Vec<Balance>,
+    /// Volumes accumulated by this pool.
+    pub volumes: Vec<SwapVolume>,
+    /// Fee charged for swap (gets divided by FEE_DIVISOR).
+    pub total_fee: u32,
+    /// Positions can only use ticks, that are multiples of the spacing.
+    pub tick_spacing: u32,
+    /// Square root of the current price of token 0 in token 1, in Q64.96.
+    pub sqrt_price: U256,
+    /// Current tick, the greatest one with price not above the current price.
+    pub tick: i32,
+    /// Liquidity of the positions, that are in range of the current price.
+    pub liquidity: u128,
+    /// Fee growth per unit of liquidity during the whole life of the pool, in Q128.128.
+    pub fee_growth_global: [U256; NUM_TOKENS],
+    /// Fees collected for the exchange, not yet withdrawn.
+    pub exchange_fee_amounts: [Balance; NUM_TOKENS],
+    /// Initialized ticks.
+    pub ticks: TreeMap<i32, TickInfo>,
+    /// Positions by id.
+    pub positions: LookupMap<u64, Position>,
+    /// Ids of the positions of each account.
+    pub account_positions: LookupMap<AccountId, Vec<u64>>,
+    /// Id of the next opened position.
+    pub next_position_id: u64,
+}
+
+/// State of the pool, while a swap is being computed.
+struct SwapState {
+    amount_out: Balance,
+    sqrt_price: U256,
+    tick: i32,
+    liquidity: u128,
+    fee_growth_global: U256,
+    exchange_fee_amount: Balance,
+    referral_fee_amount: Balance,
+    /// Ticks crossed by the swap, with fee growth of input token at the moment of crossing.
+    crossed_ticks: Vec<(i32, U256)>,
+}
+
+impl ConcentratedLiquidityPool {
+    pub fn new(
+        id: u32,
+        token_account_ids: Vec<ValidAccountId>,
+        total_fee: u32,
+        tick_spacing: u32,
+        init_tick: i32,
+    ) -> Self {
+        assert!(total_fee < FEE_DIVISOR, "{}", ERR90_FEE_TOO_LARGE);
+        assert_eq!(token_account_ids.len(), NUM_TOKENS, "{}", ERR89_WRONG_TOKEN_COUNT);
+        assert!(
+            tick_spacing > 0 && tick_spacing <= MAX_TICK_SPACING,
+            "{}", ERR140_ILLEGAL_TICK_SPACING
+        );
+        assert!((MIN_TICK..MAX_TICK).contains(&init_tick), "{}", ERR141_ILLEGAL_TICK_RANGE);
+        Self {
+            token_account_ids: token_account_ids.iter().map(|a| a.clone().into()).collect(),
+            amounts: vec![0u128; NUM_TOKENS],
+            volumes: vec![SwapVolume::default(); NUM_TOKENS],
+            total_fee,
+            tick_spacing,
+            sqrt_price: get_sqrt_price_at_tick(init_tick),
+            tick: init_tick,
+            liquidity: 0,
+            fee_growth_global: [U256::zero(); NUM_TOKENS],
+            exchange_fee_amounts: [0; NUM_TOKENS],
+            ticks: TreeMap::new(StorageKey::ConcentratedTicks { pool_id: id }),
+            positions: LookupMap::new(StorageKey::ConcentratedPositions { pool_id: id }),
+            account_positions: LookupMap::new(StorageKey::ConcentratedAccountPositions {
+                pool_id: id,
+            }),
+            next_position_id: 0,
+        }
+    }
+
+    /// Returns list of tokens in this pool.
+    pub fn tokens(&self) -> &[AccountId] {
+        &self.token_account_ids
+    }
+
+    /// Returns given pool's total fee.
+    pub fn get_fee(&self) -> u32 {
+        self.total_fee
+    }
+
+    /// Returns volumes of the given pool.
+    pub fn get_volumes(&self) -> Vec<SwapVolume> {
+        self.volumes.clone()
+    }
+
+    /// Returns token index for given pool.
+    fn token_index(&self, token_id: &AccountId) -> usize {
+        self.token_account_ids
+            .iter()
+            .position(|id| id == token_id)
+            .expect(ERR102_INVALID_TOKEN_ID)
+    }
+
+    /// Returns position with given id, checking that it belongs to the given account.
+    fn get_owned_position(&self, account_id: &AccountId, position_id: u64) -> Position {
+        let position = self.positions.get(&position_id).expect(ERR145_NO_POSITION);
+        assert_eq!(&position.owner_id, account_id, "{}", ERR142_NOT_POSITION_OWNER);
+        position
+    }
+
+    /// Returns position with given id.
+    pub fn get_position(&self, position_id: u64) -> Option<Position> {
+        self.positions.get(&position_id)
+    }
+
+    /// Returns ids of the positions opened by given account.
+    pub fn get_account_positions(&self, account_id: &AccountId) -> Vec<u64> {
+        self.account_positions.get(account_id).unwrap_or_default()
+    }
+
+    /// Fee growth per unit of liquidity within given range of ticks.
+    fn fee_growth_inside(&self, tick_lower: i32, tick_upper: i32) -> [U256; NUM_TOKENS] {
+        let lower = self.ticks.get(&tick_lower).unwrap_or_default();
+        let upper = self.ticks.get(&tick_upper).unwrap_or_default();
+        let mut result = [U256::zero(); NUM_TOKENS];
+        for (i, growth) in result.iter_mut().enumerate() {
+            let global = self.fee_growth_global[i];
+            let below = if self.tick >= tick_lower {
+                lower.fee_growth_outside[i]
+            } else {
+                global.overflowing_sub(lower.fee_growth_outside[i]).0
+            };
+            let above = if self.tick < tick_upper {
+                upper.fee_growth_outside[i]
+            } else {
+                global.overflowing_sub(upper.fee_growth_outside[i]).0
+            };
+            // Fee growth accumulators are allowed to overflow, only differences between them matter.
+            *growth = global.overflowing_sub(below).0.overflowing_sub(above).0;
+        }
+        result
+    }
+
+    /// Returns fees accrued to the position, but not yet paid out, including not collected liquidity.
+    pub fn get_position_tokens_owed(&self, position_id: u64) -> Vec<Balance> {
+        let position = self.positions.get(&position_id).expect(ERR145_NO_POSITION);
+        let fee_growth_inside = self.fee_growth_inside(position.tick_lower, position.tick_upper);
+        (0..NUM_TOKENS)
+            .map(|i| {
+                position.tokens_owed[i]
+                    + fees_for_growth(
+                        position.liquidity,
+                        fee_growth_inside[i]
+                            .overflowing_sub(position.fee_growth_inside_last[i])
+                            .0,
+                    )
+            })
+            .collect()
+    }
+
+    /// Adds liquidity delta to the tick, initializing it if needed.
+    fn update_tick(&mut self, tick: i32, liquidity_delta: i128, upper: bool) {
+        let mut info = self.ticks.get(&tick).unwrap_or_default();
+        if info.liquidity_gross == 0 && tick <= self.tick {
+            // By convention all the fee growth before the tick initialization happened below it.
+            info.fee_growth_outside = self.fee_growth_global;
+        }
+        info.liquidity_gross = add_liquidity_delta(info.liquidity_gross, liquidity_delta);
+        info.liquidity_net = if upper {
+            info.liquidity_net.checked_sub(liquidity_delta)
+        } else {
+            info.liquidity_net.checked_add(liquidity_delta)
+        }
+        .expect(ERR143_PRICE_MATH_OVERFLOW);
+        self.ticks.insert(&tick, &info);
+    }
+
+    /// Removes tick from initialized, if no position uses it anymore.
+    fn clear_tick_if_unused(&mut self, tick: i32) {
+        if self.ticks.get(&tick).map(|info| info.liquidity_gross) == Some(0) {
+            self.ticks.remove(&tick);
+        }
+    }
+
+    /// Changes liquidity of the position by given delta, accruing fees for it.
+    /// Returns amounts of tokens, that are backing the liquidity delta.
+    fn modify_position(&mut self, position_id: u64, position: &mut Position, liquidity_delta: i128) -> [Balance; NUM_TOKENS] {
+        if liquidity_delta != 0 {
+            self.update_tick(position.tick_lower, liquidity_delta, false);
+            self.update_tick(position.tick_upper, liquidity_delta, true);
+        }
+        let fee_growth_inside = self.fee_growth_inside(position.tick_lower, position.tick_upper);
+        for (i, growth_inside) in fee_growth_inside.iter().enumerate() {
+            let growth = growth_inside
+                .overflowing_sub(position.fee_growth_inside_last[i])
+                .0;
+            position.tokens_owed[i] += fees_for_growth(position.liquidity, growth);
+        }
+        position.fee_growth_inside_last = fee_growth_inside;
+        position.liquidity = add_liquidity_delta(position.liquidity, liquidity_delta);
+        if liquidity_delta < 0 {
+            self.clear_tick_if_unused(position.tick_lower);
+            self.clear_tick_if_unused(position.tick_upper);
+        }
+        if self.tick >= position.tick_lower && self.tick < position.tick_upper {
+            self.liquidity = add_liquidity_delta(self.liquidity, liquidity_delta);
+        }
+        self.positions.insert(&position_id, position);
+
+        let (amount0, amount1) = get_amounts_for_liquidity(
+            self.sqrt_price,
+            get_sqrt_price_at_tick(position.tick_lower),
+            get_sqrt_price_at_tick(position.tick_upper),
+            liquidity_delta.unsigned_abs(),
+            liquidity_delta > 0,
+        );
+        [amount0.as_u128(), amount1.as_u128()]
+    }
+
+    /// Opens empty position for given account within price range of `[tick_lower, tick_upper)`.
+    /// Storage payment should be checked by caller.
+    pub fn open_position(&mut self, account_id: &AccountId, tick_lower: i32, tick_upper: i32) -> u64 {
+        assert!(
+            tick_lower < tick_upper
+                && tick_lower >= MIN_TICK
+                && tick_upper <= MAX_TICK
+                && tick_lower % self.tick_spacing as i32 == 0
+                && tick_upper % self.tick_spacing as i32 == 0,
+            "{}", ERR141_ILLEGAL_TICK_RANGE
+        );
+        let position_id = self.next_position_id;
+        self.next_position_id += 1;
+        self.positions.insert(
+            &position_id,
+            &Position {
+                owner_id: account_id.clone(),
+                tick_lower,
+                tick_upper,
+                liquidity: 0,
+                fee_growth_inside_last: self.fee_growth_inside(tick_lower, tick_upper),
+                tokens_owed: [0; NUM_TOKENS],
+            },
+        );
+        let mut account_positions = self.get_account_positions(account_id);
+        account_positions.push(position_id);
+        self.account_positions.insert(account_id, &account_positions);
+        env::log(
+            format!(
+                "{} opened position {} in range [{}, {})",
+                account_id, position_id, tick_lower, tick_upper
+            )
+            .as_bytes(),
+        );
+        position_id
+    }
+
+    /// Adds max liquidity, that given amounts allow, to the position.
+    /// Updates amounts to amounts kept in the pool and returns liquidity added.
+    pub fn add_liquidity(
+        &mut self,
+        account_id: &AccountId,
+        position_id: u64,
+        amounts: &mut [Balance],
+    ) -> u128 {
+        assert_eq!(amounts.len(), NUM_TOKENS, "{}", ERR89_WRONG_AMOUNT_COUNT);
+        let mut position = self.get_owned_position(account_id, position_id);
+        let liquidity = get_liquidity_for_amounts(
+            self.sqrt_price,
+            get_sqrt_price_at_tick(position.tick_lower),
+            get_sqrt_price_at_tick(position.tick_upper),
+            amounts[0],
+            amounts[1],
+        );
+        assert!(liquidity > 0, "{}", ERR148_ZERO_LIQUIDITY);
+        let used = self.modify_position(position_id, &mut position, liquidity as i128);
+        for i in 0..NUM_TOKENS {
+            assert!(used[i] <= amounts[i], "{}", ERR143_PRICE_MATH_OVERFLOW);
+            amounts[i] = used[i];
+            self.amounts[i] += used[i];
+        }
+        env::log(
+            format!(
+                "Liquidity {} added to position {}: {} {}, {} {}",
+                liquidity,
+                position_id,
+                amounts[0],
+                self.token_account_ids[0],
+                amounts[1],
+                self.token_account_ids[1]
+            )
+            .as_bytes(),
+        );
+        liquidity
+    }
+
+    /// Pays out all the tokens owed to the position.
+    fn collect(&mut self, position_id: u64, position: &mut Position) -> Vec<Balance> {
+        let result = position.tokens_owed.to_vec();
+        for (pool_amount, amount) in self.amounts.iter_mut().zip(result.iter()) {
+            *pool_amount -= amount;
+        }
+        position.tokens_owed = [0; NUM_TOKENS];
+        self.positions.insert(&position_id, position);
+        result
+    }
+
+    /// Removes given liquidity from the position.
+    /// Returns amounts backing removed liquidity together with all the fees accrued by the position.
+    pub fn remove_liquidity(
+        &mut self,
+        account_id: &AccountId,
+        position_id: u64,
+        liquidity: u128,
+        min_amounts: Vec<Balance>,
+    ) -> Vec<Balance> {
+        assert_eq!(min_amounts.len(), NUM_TOKENS, "{}", ERR89_WRONG_AMOUNT_COUNT);
+        let mut position = self.get_owned_position(account_id, position_id);
+        assert!(liquidity > 0, "{}", ERR148_ZERO_LIQUIDITY);
+        assert!(position.liquidity >= liquidity, "{}", ERR91_NOT_ENOUGH_SHARES);
+        let amounts = self.modify_position(position_id, &mut position, -(liquidity as i128));
+        for i in 0..NUM_TOKENS {
+            assert!(amounts[i] >= min_amounts[i], "{}", ERR68_SLIPPAGE);
+            position.tokens_owed[i] += amounts[i];
+        }
+        let result = self.collect(position_id, &mut position);
+        env::log(
+            format!(
+                "Liquidity {} removed from position {}: receive back {} {}, {} {}",
+                liquidity,
+                position_id,
+                result[0],
+                self.token_account_ids[0],
+                result[1],
+                self.token_account_ids[1]
+            )
+            .as_bytes(),
+        );
+        result
+    }
+
+    /// Pays out fees accrued by the position.
+    pub fn collect_fees(&mut self, account_id: &AccountId, position_id: u64) -> Vec<Balance> {
+        let mut position = self.get_owned_position(account_id, position_id);
+        self.modify_position(position_id, &mut position, 0);
+        let result = self.collect(position_id, &mut position);
+        env::log(
+            format!(
+                "Fees collected from position {}: {} {}, {} {}",
+                position_id,
+                result[0],
+                self.token_account_ids[0],
+                result[1],
+                self.token_account_ids[1]
+            )
+            .as_bytes(),
+        );
+        result
+    }
+
+    /// Closes position without liquidity, releasing its storage. Returns not collected tokens.
+    pub fn close_position(&mut self, account_id: &AccountId, position_id: u64) -> Vec<Balance> {
+        let mut position = self.get_owned_position(account_id, position_id);
+        assert_eq!(position.liquidity, 0, "{}", ERR146_POSITION_NOT_EMPTY);
+        let result = self.collect(position_id, &mut position);
+        self.positions.remove(&position_id);
+        let mut account_positions = self.get_account_positions(account_id);
+        account_positions.retain(|id| *id != position_id);
+        if account_positions.is_empty() {
+            self.account_positions.remove(account_id);
+        } else {
+            self.account_positions.insert(account_id, &account_positions);
+        }
+        env::log(format!("{} closed position {}", account_id, position_id).as_bytes());
+        result
+    }
+
+    /// Withdraws fees collected for the exchange.
+    pub fn withdraw_exchange_fees(&mut self) -> Vec<Balance> {
+        let result = self.exchange_fee_amounts.to_vec();
+        for (pool_amount, amount) in self.amounts.iter_mut().zip(result.iter()) {
+            *pool_amount -= amount;
+        }
+        self.exchange_fee_amounts = [0; NUM_TOKENS];
+        result
+    }
+
+    /// Computes swap of `amount_in` of token 0 for token 1 if `zero_for_one`, or vice versa,
+    /// crossing initialized ticks until the whole input is used. Doesn't change the state.
+    fn compute_swap(
+        &self,
+        zero_for_one: bool,
+        amount_in: Balance,
+        exchange_fee: u32,
+        referral_fee: u32,
+    ) -> SwapState {
+        assert!(amount_in > 0, "{}", ERR76_INVALID_PARAMS);
+        let in_idx = if zero_for_one { 0 } else { 1 };
+        let price_limit = if zero_for_one {
+            min_sqrt_price() + 1
+        } else {
+            max_sqrt_price() - 1
+        };
+        let mut state = SwapState {
+            amount_out: 0,
+            sqrt_price: self.sqrt_price,
+            tick: self.tick,
+            liquidity: self.liquidity,
+            fee_growth_global: self.fee_growth_global[in_idx],
+            exchange_fee_amount: 0,
+            referral_fee_amount: 0,
+            crossed_ticks: vec![],
+        };
+        let mut amount_remaining = U256::from(amount_in);
+        while !amount_remaining.is_zero() {
+            assert_ne!(state.sqrt_price, price_limit, "{}", ERR144_NOT_ENOUGH_LIQUIDITY);
+            let next_tick = if zero_for_one {
+                self.ticks.floor_key(&state.tick).unwrap_or(MIN_TICK)
+            } else {
+                self.ticks.higher(&state.tick).unwrap_or(MAX_TICK)
+            };
+            let sqrt_price_next_tick = get_sqrt_price_at_tick(next_tick);
+            let sqrt_price_target = if zero_for_one {
+                std::cmp::max(sqrt_price_next_tick, price_limit)
+            } else {
+                std::cmp::min(sqrt_price_next_tick, price_limit)
+            };
+            let step = compute_swap_step(
+                state.sqrt_price,
+                sqrt_price_target,
+                state.liquidity,
+                amount_remaining,
+                self.total_fee,
+            );
+            let sqrt_price_prev = state.sqrt_price;
+            state.sqrt_price = step.sqrt_price_next;
+            amount_remaining = amount_remaining - step.amount_in - step.fee_amount;
+            state.amount_out += step.amount_out.as_u128();
+
+            let exchange_fee_amount =
+                step.fee_amount * U256::from(exchange_fee) / U256::from(FEE_DIVISOR);
+            state.exchange_fee_amount += exchange_fee_amount.as_u128();
+            let referral_fee_amount =
+                step.fee_amount * U256::from(referral_fee) / U256::from(FEE_DIVISOR);
+            state.referral_fee_amount += referral_fee_amount.as_u128();
+            if state.liquidity > 0 {
+                let growth = ((step.fee_amount - exchange_fee_amount - referral_fee_amount) << 128)
+                    / U256::from(state.liquidity);
+                state.fee_growth_global = state.fee_growth_global.overflowing_add(growth).0;
+            }
+
+            if state.sqrt_price == sqrt_price_next_tick {
+                if let Some(info) = self.ticks.get(&next_tick) {
+                    state.crossed_ticks.push((next_tick, state.fee_growth_global));
+                    let liquidity_net = if zero_for_one {
+                        -info.liquidity_net
+                    } else {
+                        info.liquidity_net
+                    };
+                    state.liquidity = add_liquidity_delta(state.liquidity, liquidity_net);
+                }
+                state.tick = if zero_for_one { next_tick - 1 } else { next_tick };
+            } else if state.sqrt_price != sqrt_price_prev {
+                state.tick = get_tick_at_sqrt_price(state.sqrt_price);
+            }
+        }
+        state
+    }
+
+    /// Returns how much token you will receive if swap `token_amount_in` of `token_in` for `token_out`.
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Balance {
+        assert_ne!(token_in, token_out, "{}", ERR73_SAME_TOKEN);
+        let zero_for_one = self.token_index(token_in) == 0;
+        self.token_index(token_out);
+        self.compute_swap(zero_for_one, amount_in, fees.exchange_fee, 0).amount_out
+    }
+
+    /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
+    /// Assuming that `token_amount_in` was already received from `sender_id`.
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        assert_ne!(token_in, token_out, "{}", ERR73_SAME_TOKEN);
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        let zero_for_one = in_idx == 0;
+        // There are no shares in this pool, so the referral is paid into its first position, if it has one.
+        let referral_position_id = admin_fee
+            .referral_id
+            .as_ref()
+            .and_then(|referral_id| self.get_account_positions(referral_id).first().copied());
+        let referral_fee = if referral_position_id.is_some() { admin_fee.referral_fee } else { 0 };
+        let state = self.compute_swap(zero_for_one, amount_in, admin_fee.exchange_fee, referral_fee);
+        let amount_out = state.amount_out;
+        assert!(amount_out >= min_amount_out, "{}", ERR68_SLIPPAGE);
+        env::log(
+            format!(
+                "Swapped {} {} for {} {}",
+                amount_in, token_in, amount_out, token_out
+            )
+            .as_bytes(),
+        );
+
+        for (tick, fee_growth_global) in state.crossed_ticks {
+            let mut info = self.ticks.get(&tick).expect(ERR141_ILLEGAL_TICK_RANGE);
+            info.fee_growth_outside[in_idx] =
+                fee_growth_global.overflowing_sub(info.fee_growth_outside[in_idx]).0;
+            info.fee_growth_outside[out_idx] = self.fee_growth_global[out_idx]
+                .overflowing_sub(info.fee_growth_outside[out_idx])
+                .0;
+            self.ticks.insert(&tick, &info);
+        }
+        self.sqrt_price = state.sqrt_price;
+        self.tick = state.tick;
+        self.liquidity = state.liquidity;
+        self.fee_growth_global[in_idx] = state.fee_growth_global;
+        self.exchange_fee_amounts[in_idx] += state.exchange_fee_amount;
+        if let Some(position_id) = referral_position_id {
+            let mut position = self.positions.get(&position_id).expect(ERR145_NO_POSITION);
+            position.tokens_owed[in_idx] += state.referral_fee_amount;
+            self.positions.insert(&position_id, &position);
+        }
+        self.amounts[in_idx] += amount_in;
+        self.amounts[out_idx] -= amount_out;
+
+        // Keeping track of volume per each input traded separately.
+        // Reported volume with fees will be sum of `input`, without fees will be sum of `output`.
+        self.volumes[in_idx].input.0 += amount_in;
+        self.volumes[in_idx].output.0 += amount_out;
+
+        amount_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+    use near_sdk_sim::to_yocto;
+
+    use super::*;
+
+    fn setup_pool(fee: u32) -> ConcentratedLiquidityPool {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        ConcentratedLiquidityPool::new(0, vec![accounts(1), accounts(2)], fee, 10, 0)
+    }
+
+    fn admin_fees(exchange_fee: u32) -> AdminFees {
+        AdminFees {
+            exchange_fee,
+            exchange_id: accounts(3).as_ref().clone(),
+            referral_fee: 0,
+            referral_id: None,
+        }
+    }
+
+    #[test]
+    fn test_position_in_range() {
+        let mut pool = setup_pool(30);
+        let owner = accounts(0).to_string();
+        let position_id = pool.open_position(&owner, -1000, 1000);
+        let mut amounts = vec![to_yocto("10"), to_yocto("20")];
+        let liquidity = pool.add_liquidity(&owner, position_id, &mut amounts);
+        // At price 1 symmetric range takes equal amounts.
+        assert_eq!(amounts[0], amounts[1]);
+        assert!(to_yocto("10") - amounts[0] <= 1);
+        assert_eq!(pool.liquidity, liquidity);
+        assert_eq!(pool.amounts, amounts);
+
+        let expected = pool.get_return(&accounts(1).to_string(), to_yocto("1"), &accounts(2).to_string(), &admin_fees(0));
+        let out = pool.swap(&accounts(1).to_string(), to_yocto("1"), &accounts(2).to_string(), 1, &admin_fees(0));
+        assert_eq!(out, expected);
+        assert!(out < to_yocto("1") && out > to_yocto("0.99") * 997 / 1000);
+        assert!(pool.tick < 0);
+
+        // All the fees go to the only position.
+        let owed = pool.get_position_tokens_owed(position_id);
+        assert!(to_yocto("0.003") - owed[0] <= 1);
+        assert_eq!(owed[1], 0);
+
+        let result = pool.remove_liquidity(&owner, position_id, liquidity, vec![0, 0]);
+        assert!(amounts[0] + to_yocto("1") - result[0] <= 2);
+        assert!(amounts[1] - out - result[1] <= 2);
+        assert_eq!(pool.liquidity, 0);
+        assert_eq!(pool.ticks.len(), 0);
+        pool.close_position(&owner, position_id);
+        assert!(pool.get_position(position_id).is_none());
+        assert!(pool.get_account_positions(&owner).is_empty());
+    }
+
+    #[test]
+    fn test_position_out_of_range() {
+        let mut pool = setup_pool(0);
+        let owner = accounts(0).to_string();
+        // Above the current price, only token 0 is needed.
+        let above = pool.open_position(&owner, 100, 200);
+        let mut amounts = vec![to_yocto("10"), to_yocto("10")];
+        pool.add_liquidity(&owner, above, &mut amounts);
+        assert_eq!(amounts[1], 0);
+        assert!(to_yocto("10") - amounts[0] <= 1);
+        assert_eq!(pool.liquidity, 0);
+        // Below the current price, only token 1 is needed.
+        let below = pool.open_position(&owner, -200, -100);
+        let mut amounts = vec![to_yocto("10"), to_yocto("10")];
+        pool.add_liquidity(&owner, below, &mut amounts);
+        assert_eq!(amounts[0], 0);
+        assert_eq!(pool.liquidity, 0);
+
+        // Swap token 1 for token 0 moves price up, crosses into the upper position.
+        let out = pool.swap(&accounts(2).to_string(), to_yocto("1"), &accounts(1).to_string(), 1, &admin_fees(0));
+        assert!(pool.tick >= 100 && pool.tick < 200);
+        assert!(out < to_yocto("1"));
+        assert_eq!(pool.liquidity, pool.get_position(above).unwrap().liquidity);
+    }
+
+    #[test]
+    fn test_fees_split_between_positions() {
+        let mut pool = setup_pool(100);
+        let alice = accounts(0).to_string();
+        let bob = accounts(4).to_string();
+        let wide = pool.open_position(&alice, -1000, 1000);
+        pool.add_liquidity(&alice, wide, &mut vec![to_yocto("10"), to_yocto("10")]);
+        let narrow = pool.open_position(&bob, -100, 100);
+        pool.add_liquidity(&bob, narrow, &mut vec![to_yocto("10"), to_yocto("10")]);
+
+        pool.swap(&accounts(1).to_string(), to_yocto("0.1"), &accounts(2).to_string(), 1, &admin_fees(2000));
+        let exchange = pool.withdraw_exchange_fees();
+        assert_eq!(exchange[0], to_yocto("0.0002"));
+        let fees_wide = pool.collect_fees(&alice, wide);
+        let fees_narrow = pool.collect_fees(&bob, narrow);
+        assert!(to_yocto("0.0008") - fees_wide[0] - fees_narrow[0] <= 2);
+        // Narrow position has more liquidity per token, so it earns more.
+        assert!(fees_narrow[0] > fees_wide[0]);
+    }
+
+    #[test]
+    fn test_referral_fees() {
+        let mut pool = setup_pool(100);
+        let alice = accounts(0).to_string();
+        let referral = accounts(4).to_string();
+        let position_id = pool.open_position(&alice, -1000, 1000);
+        pool.add_liquidity(&alice, position_id, &mut vec![to_yocto("10"), to_yocto("10")]);
+        let fees = AdminFees {
+            referral_fee: 1000,
+            referral_id: Some(referral.clone()),
+            ..admin_fees(2000)
+        };
+        // Referral without a position gets nothing.
+        let expected = pool.get_return(&accounts(1).to_string(), to_yocto("0.1"), &accounts(2).to_string(), &fees);
+        let out = pool.swap(&accounts(1).to_string(), to_yocto("0.1"), &accounts(2).to_string(), 1, &fees);
+        assert_eq!(out, expected);
+        assert!(to_yocto("0.0008") - pool.collect_fees(&alice, position_id)[0] <= 2);
+
+        let referral_position = pool.open_position(&referral, 2000, 3000);
+        pool.swap(&accounts(1).to_string(), to_yocto("0.1"), &accounts(2).to_string(), 1, &fees);
+        assert_eq!(pool.get_position_tokens_owed(referral_position)[0], to_yocto("0.0001"));
+        assert!(to_yocto("0.0007") - pool.collect_fees(&alice, position_id)[0] <= 2);
+        assert_eq!(pool.withdraw_exchange_fees()[0], to_yocto("0.0004"));
+    }
+
+    #[test]
+    #[should_panic(expected = "E142: not position owner")]
+    fn test_not_owner() {
+        let mut pool = setup_pool(30);
+        let position_id = pool.open_position(&accounts(0).to_string(), -1000, 1000);
+        pool.add_liquidity(&accounts(1).to_string(), position_id, &mut vec![to_yocto("10"), to_yocto("10")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E141: illegal tick range")]
+    fn test_tick_spacing() {
+        let mut pool = setup_pool(30);
+        pool.open_position(&accounts(0).to_string(), -1005, 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "E144: not enough liquidity")]
+    fn test_not_enough_liquidity() {
+        let mut pool = setup_pool(30);
+        let owner = accounts(0).to_string();
+        let position_id = pool.open_position(&owner, -100, 100);
+        pool.add_liquidity(&owner, position_id, &mut vec![to_yocto("1"), to_yocto("1")]);
+        pool.swap(&accounts(1).to_string(), to_yocto("10"), &accounts(2).to_string(), 1, &admin_fees(0));
+    }
+}
@@ -12,6 +12,7 @@ pub const ERR18_TOKENS_NOT_EMPTY: &str = "E18: storage unregister tokens not emp
 
 // Accounts.
 
+pub const ERR20_NOT_ENOUGH_GAS_TO_WITHDRAW: &str = "E20: not enough gas to withdraw all the tokens";
 pub const ERR21_TOKEN_NOT_REG: &str = "E21: token not registered";
 pub const ERR22_NOT_ENOUGH_TOKENS: &str = "E22: not enough tokens in deposit";
 // pub const ERR23_NOT_ENOUGH_NEAR: &str = "E23: not enough NEAR in deposit";
@@ -24,6 +25,7 @@ pub const ERR27_DEPOSIT_NEEDED: &str =
     "E27: attach 1yN to swap tokens not in whitelist";
 pub const ERR28_WRONG_MSG_FORMAT: &str = "E28: Illegal msg in ft_transfer_call";
 pub const ERR29_ILLEGAL_WITHDRAW_AMOUNT: &str = "E29: Illegal withdraw amount";
+pub const ERR30_NO_UNCLAIMED_TOKENS: &str = "E30: no unclaimed tokens";
 
 // Liquidity operations.
 
@@ -33,6 +35,8 @@ pub const ERR32_ZERO_SHARES: &str = "E32: minting zero shares";
 pub const ERR33_TRANSFER_TO_SELF: &str = "E33: transfer to self";
 pub const ERR34_INSUFFICIENT_LP_SHARES: &str = "E34: insufficient lp shares";
 pub const ERR35_AT_LEAST_ONE_YOCTO: &str = "E35: requires attached deposit of at least 1 yoctoNEAR";
+pub const ERR36_NO_PENDING_LIQUIDITY: &str = "E36: no pending liquidity";
+pub const ERR37_TRANSFERRED_LIQUIDITY_NOT_SUPPORTED: &str = "E37: pool doesn't support adding liquidity by transfers";
 
 // Action result.
 
@@ -40,6 +44,9 @@ pub const ERR41_WRONG_ACTION_RESULT: &str = "E41: wrong action result type";
 
 // Contract Level
 pub const ERR51_CONTRACT_PAUSED: &str = "E51: contract paused";
+pub const ERR52_POOL_SWAPS_PAUSED: &str = "E52: pool swaps paused";
+pub const ERR53_POOL_ADD_LIQUIDITY_PAUSED: &str = "E53: pool adding liquidity paused";
+pub const ERR54_POOL_FROZEN: &str = "E54: pool frozen";
 
 // Swap
 pub const ERR60_DECIMAL_ILLEGAL: &str = "E60: illegal decimal";
@@ -56,8 +63,13 @@ pub const ERR70_SWAP_OUT_CALC_ERR: &str = "E70: encounter err when calc swap out
 pub const ERR71_SWAP_DUP_TOKENS: &str = "E71: illegal swap with duplicated tokens";
 pub const ERR72_AT_LEAST_ONE_SWAP: &str = "E72: at least one swap";
 pub const ERR73_SAME_TOKEN: &str = "E73: same token swap";
+pub const ERR74_DEADLINE_EXPIRED: &str = "E74: deadline of the actions expired";
 pub const ERR75_INVARIANT_REDUCE: &str = "E75: invariant can not reduce ";
 pub const ERR76_INVALID_PARAMS: &str = "E76: invalid params";
+pub const ERR77_ROUTE_WITHOUT_AMOUNT_OUT: &str = "E77: exact output route should end with amount_out";
+pub const ERR78_ILLEGAL_SPLIT_ROUTE: &str = "E78: split route should start with amount_in and end with token_out";
+pub const ERR79_ACTION_NEEDS_ACCOUNT: &str = "E79: action is only allowed on the deposits of an account";
+pub const ERR80_NOT_SUPPORTED_BY_POOL: &str = "E80: operation is not supported by this pool kind";
 
 // pool manage
 pub const ERR81_AMP_IN_LOCK: &str = "E81: amp is currently in lock";
@@ -73,6 +85,10 @@ pub const ERR90_FEE_TOO_LARGE: &str = "E90: fee too large";
 pub const ERR91_NOT_ENOUGH_SHARES: &str = "E91: not enough shares";
 pub const ERR92_TOKEN_DUPLICATES: &str = "E92: token duplicated";
 pub const ERR89_WRONG_AMOUNT_COUNT: &str = "E89: wrong amount count";
+pub const ERR93_FEE_NOT_ADJUSTABLE: &str = "E93: fee of this pool can not be modified";
+pub const ERR94_ILLEGAL_FEE_RAMP_TIME: &str = "E94: fee ramp time should be in the future";
+pub const ERR95_NOT_RATED_POOL: &str = "E95: not rated pool";
+pub const ERR96_ILLEGAL_RATES_FRESHNESS: &str = "E96: rates freshness should limit age of rates";
 
 
 // owner
@@ -84,6 +100,8 @@ pub const ERR103_NOT_INITIALIZED: &str = "E103: contract is not initialized";
 
 //mft
 pub const ERR110_INVALID_REGISTER: &str = "E110: Invalid register";
+pub const ERR111_NO_TOKEN_METADATA: &str = "E111: token metadata is not cached";
+pub const ERR112_TOKEN_METADATA_TOO_LARGE: &str = "E112: token metadata is too large";
 
 // rated pool
 pub const ERR120_RATES_EXPIRED: &str = "E120: Rates expired";
@@ -93,3 +111,38 @@ pub const ERR123_ONE_PROMISE_RESULT: &str = "E123: Cross-contract call should ha
 pub const ERR124_CROSS_CALL_FAILED: &str = "E124: Cross-contract call failed";
 pub const ERR125_FAILED_TO_APPLY_RATES: &str = "E125: Failed to apply new rates";
 pub const ERR126_FAILED_TO_PARSE_RESULT: &str = "E126: Failed to parse cross-contract call result";
+pub const ERR127_ILLEGAL_RATES_CONFIG: &str = "E127: Unknown rates type or missing config for it";
+pub const ERR128_NO_RATE_SOURCES: &str = "E128: At least one rate source is required";
+pub const ERR129_WRONG_PROMISE_RESULTS_COUNT: &str = "E129: Cross-contract call results don't match rate sources";
+
+// weighted pool
+pub const ERR130_ILLEGAL_WEIGHTS: &str = "E130: illegal weights";
+pub const ERR131_MAX_IN_RATIO: &str = "E131: swap amount exceeds half of the pool balance";
+
+// concentrated liquidity pool
+pub const ERR140_ILLEGAL_TICK_SPACING: &str = "E140: illegal tick spacing";
+pub const ERR141_ILLEGAL_TICK_RANGE: &str = "E141: illegal tick range";
+pub const ERR142_NOT_POSITION_OWNER: &str = "E142: not position owner";
+pub const ERR143_PRICE_MATH_OVERFLOW: &str = "E143: price math overflow";
+pub const ERR144_NOT_ENOUGH_LIQUIDITY: &str = "E144: not enough liquidity";
+pub const ERR145_NO_POSITION: &str = "E145: position not found";
+pub const ERR146_POSITION_NOT_EMPTY: &str = "E146: position still has liquidity";
+pub const ERR147_NO_SHARES: &str = "E147: pool has no shares";
+pub const ERR148_ZERO_LIQUIDITY: &str = "E148: zero liquidity";
+pub const ERR149_NOT_CONCENTRATED_POOL: &str = "E149: not concentrated liquidity pool";
+
+// price oracle
+pub const ERR150_NO_PRICE_OBSERVATIONS: &str = "E150: no price observations for the pool";
+pub const ERR151_TWAP_WINDOW_TOO_LONG: &str = "E151: twap window exceeds recorded observations";
+pub const ERR152_PRICE_OBSERVATIONS_ALLOCATED: &str = "E152: price observations of the pool are already allocated";
+
+// meta pool
+pub const ERR160_ILLEGAL_BASE_POOL: &str = "E160: base pool should be stable or rated pool";
+pub const ERR161_NOT_BASE_POOL: &str = "E161: not a base pool of meta pools";
+
+// route
+pub const ERR170_ILLEGAL_MAX_HOPS: &str = "E170: max hops should be from 1 to 3";
+
+// wrap near
+pub const ERR180_WNEAR_NOT_SET: &str = "E180: wNEAR contract is not set";
+pub const ERR181_CALLBACK_POST_WRAP_NEAR_INVALID: &str = "E181: expected 1 promise result from wNEAR contract";
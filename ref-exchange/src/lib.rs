@@ -1,29 +1,37 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt;
 
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
 use near_contract_standards::storage_management::{
     StorageBalance, StorageBalanceBounds, StorageManagement,
 };
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedSet, Vector};
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet, Vector};
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::{
     assert_one_yocto, env, log, near_bindgen, AccountId, Balance, PanicOnDefault, Promise,
-    PromiseResult, StorageUsage, BorshStorageKey, PromiseOrValue, ext_contract
+    PromiseResult, StorageUsage, BorshStorageKey, PromiseOrValue, ext_contract, Timestamp
 };
 use utils::{NO_DEPOSIT, GAS_FOR_BASIC_OP};
 
 use crate::account_deposit::{VAccount, Account};
-pub use crate::action::SwapAction;
+pub use crate::action::{Deadline, SplitSwapAction, SwapAction, SwapByOutputAction};
 use crate::action::{Action, ActionResult};
 use crate::errors::*;
 use crate::admin_fee::AdminFees;
-use crate::pool::Pool;
+use crate::pool::{FeeRamp, Pool};
 use crate::simple_pool::SimplePool;
 use crate::stable_swap::StableSwapPool;
 use crate::rated_swap::RatedSwapPool;
+use crate::rated_swap::rates::{Rates, RatesConfig, RatesFreshness};
+use crate::weighted_pool::WeightedPool;
+use crate::concentrated_liquidity::ConcentratedLiquidityPool;
+use crate::twap::PoolObservations;
+use crate::token_receiver::PendingLiquidity;
 use crate::utils::check_token_duplicates;
+pub use crate::token_receiver::SwapFailurePolicy;
 pub use crate::views::{PoolInfo, ContractMetadata};
 
 mod account_deposit;
@@ -41,6 +49,13 @@ mod storage_impl;
 mod token_receiver;
 mod utils;
 mod views;
+mod wrap_near;
+mod weighted_pool;
+mod concentrated_liquidity;
+mod twap;
+mod rates_update;
+mod meta_pool;
+mod route;
 
 near_sdk::setup_alloc!();
 
@@ -52,6 +67,17 @@ pub(crate) enum StorageKey {
     Whitelist,
     Guardian,
     AccountTokens {account_id: AccountId},
+    ConcentratedTicks { pool_id: u32 },
+    ConcentratedPositions { pool_id: u32 },
+    ConcentratedAccountPositions { pool_id: u32 },
+    PoolObservations,
+    PoolFeeRamps,
+    PoolStates,
+    PoolRatesFreshness,
+    PoolRatesUpdatedAt,
+    UnclaimedTokens,
+    PendingLiquidity,
+    TokenMetadata,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Eq, PartialEq, Clone)]
@@ -70,11 +96,87 @@ impl fmt::Display for RunningState {
     }
 }
 
-#[ext_contract(ext_self)]
-pub trait SelfCallbacks {
-    fn update_pool_rates_callback(&mut self, pool_id: u64) -> bool;
+/// State of a single pool, limits operations on it while the rest of the exchange keeps running.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum PoolState {
+    /// All operations are allowed.
+    Running,
+    /// Liquidity can be added and removed, but no swaps.
+    SwapsPaused,
+    /// Liquidity can only be removed.
+    WithdrawOnly,
+    /// No operations are allowed.
+    Frozen,
+}
+
+impl fmt::Display for PoolState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolState::Running => write!(f, "Running"),
+            PoolState::SwapsPaused => write!(f, "SwapsPaused"),
+            PoolState::WithdrawOnly => write!(f, "WithdrawOnly"),
+            PoolState::Frozen => write!(f, "Frozen"),
+        }
+    }
+}
+
+/// Callbacks of this contract, resuming actions that waited for cross-contract calls.
+/// Generated functions take the receiver, deposit and gas on top of the arguments.
+#[allow(clippy::too_many_arguments)]
+mod self_callbacks {
+    use super::*;
+
+    #[ext_contract(ext_self)]
+    pub trait SelfCallbacks {
+        fn update_pool_rates_callback(&mut self, pool_id: u64) -> bool;
+        fn callback_instant_swap_with_rates(
+            &mut self,
+            sender_id: AccountId,
+            token_in: AccountId,
+            amount_in: U128,
+            referral_id: Option<AccountId>,
+            actions: Vec<Action>,
+            deadline: Option<Deadline>,
+            failure_policy: Option<SwapFailurePolicy>,
+            rate_updates: Vec<(u64, u32)>,
+        ) -> U128;
+        fn callback_refund_failed_swap(
+            &mut self,
+            sender_id: AccountId,
+            token_in: AccountId,
+            amount_in: U128,
+        ) -> U128;
+        fn callback_execute_actions_with_rates(
+            &mut self,
+            sender_id: AccountId,
+            actions: Vec<Action>,
+            referral_id: Option<AccountId>,
+            deadline: Option<Deadline>,
+            rate_updates: Vec<(u64, u32)>,
+        ) -> ActionResult;
+        fn callback_swap_with_rates(
+            &mut self,
+            sender_id: AccountId,
+            actions: Vec<Action>,
+            referral_id: Option<AccountId>,
+            deadline: Option<Deadline>,
+            rate_updates: Vec<(u64, u32)>,
+        ) -> U128;
+        fn callback_add_rated_liquidity(
+            &mut self,
+            sender_id: AccountId,
+            pool_id: u64,
+            amounts: Vec<U128>,
+            min_shares: U128,
+            rate_updates: Vec<(u64, u32)>,
+        ) -> U128;
+    }
 }
 
+pub(crate) use self_callbacks::ext_self;
+
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
 pub struct Contract {
@@ -94,6 +196,24 @@ pub struct Contract {
     guardians: UnorderedSet<AccountId>,
     /// Running state
     state: RunningState,
+    /// Price accumulators of the pools, used for time-weighted average prices.
+    pool_observations: LookupMap<u64, PoolObservations>,
+    /// Ongoing total fee changes of the pools.
+    pool_fee_ramps: LookupMap<u64, FeeRamp>,
+    /// States of the pools, that are not running. Pools missing here are running.
+    pool_states: UnorderedMap<u64, PoolState>,
+    /// Limits on the age of the rates of the rated pools, if differ from the default.
+    pool_rates_freshness: LookupMap<u64, RatesFreshness>,
+    /// Time of the last update of the rates of the rated pools.
+    pool_rates_updated_at: LookupMap<u64, Timestamp>,
+    /// Tokens, that failed to be sent to the accounts after instant swaps and can't be deposited to them.
+    unclaimed_tokens: LookupMap<AccountId, HashMap<AccountId, Balance>>,
+    /// Tokens transferred by the accounts to add liquidity to the pools, until every token of the pool arrives.
+    pending_liquidity: LookupMap<AccountId, HashMap<u64, PendingLiquidity>>,
+    /// wNEAR contract, that wraps native NEAR deposits.
+    wnear_id: Option<AccountId>,
+    /// Metadata of the tokens, cached from their contracts by `refresh_token_metadata`.
+    token_metadata: LookupMap<AccountId, FungibleTokenMetadata>,
 }
 
 #[near_bindgen]
@@ -109,6 +229,15 @@ impl Contract {
             whitelisted_tokens: UnorderedSet::new(StorageKey::Whitelist),
             guardians: UnorderedSet::new(StorageKey::Guardian),
             state: RunningState::Running,
+            pool_observations: LookupMap::new(StorageKey::PoolObservations),
+            pool_fee_ramps: LookupMap::new(StorageKey::PoolFeeRamps),
+            pool_states: UnorderedMap::new(StorageKey::PoolStates),
+            pool_rates_freshness: LookupMap::new(StorageKey::PoolRatesFreshness),
+            pool_rates_updated_at: LookupMap::new(StorageKey::PoolRatesUpdatedAt),
+            unclaimed_tokens: LookupMap::new(StorageKey::UnclaimedTokens),
+            pending_liquidity: LookupMap::new(StorageKey::PendingLiquidity),
+            wnear_id: None,
+            token_metadata: LookupMap::new(StorageKey::TokenMetadata),
         }
     }
 
@@ -127,6 +256,44 @@ impl Contract {
         )))
     }
 
+    /// Adds new "Weighted Pool" with given tokens, weights and fee.
+    /// weights: weight of each token in basis points, should sum up to 10000, each at least 100.
+    /// Attached NEAR should be enough to cover the added storage.
+    #[payable]
+    pub fn add_weighted_pool(&mut self, tokens: Vec<ValidAccountId>, weights: Vec<u32>, fee: u32) -> u64 {
+        self.assert_contract_running();
+        check_token_duplicates(&tokens);
+        self.internal_add_pool(Pool::WeightedPool(WeightedPool::new(
+            self.pools.len() as u32,
+            tokens,
+            weights,
+            fee,
+        )))
+    }
+
+    /// Adds new "Concentrated Liquidity Pool" with given two tokens, fee and tick spacing.
+    /// tick_spacing: positions can only be bounded by ticks, that are multiples of it.
+    /// init_tick: tick of the initial price, which is `1.0001^init_tick` of token 1 per token 0.
+    /// Attached NEAR should be enough to cover the added storage.
+    #[payable]
+    pub fn add_concentrated_liquidity_pool(
+        &mut self,
+        tokens: Vec<ValidAccountId>,
+        fee: u32,
+        tick_spacing: u32,
+        init_tick: i32,
+    ) -> u64 {
+        self.assert_contract_running();
+        check_token_duplicates(&tokens);
+        self.internal_add_pool(Pool::ConcentratedLiquidityPool(ConcentratedLiquidityPool::new(
+            self.pools.len() as u32,
+            tokens,
+            fee,
+            tick_spacing,
+            init_tick,
+        )))
+    }
+
     /// Adds new "Stable Pool" with given tokens, decimals, fee and amp.
     /// It is limited to owner or guardians, cause a complex and correct config is needed.
     /// tokens: pool tokens in this stable swap.
@@ -152,8 +319,12 @@ impl Contract {
         )))
     }
 
-    ///
+    /// Adds new "Rated Pool", a stable pool with tokens priced by rates from another contract.
+    /// It is limited to owner or guardians, cause a complex and correct config is needed.
+    /// rates_type: "STNEAR", "GENERIC" or "MULTI", the latter two require `rates_config`.
+    /// contract_id: contract providing the rates, not used by "MULTI" where each token has its own source.
     #[payable]
+    #[allow(clippy::too_many_arguments)]
     pub fn add_rated_swap_pool(
         &mut self,
         tokens: Vec<ValidAccountId>,
@@ -162,6 +333,7 @@ impl Contract {
         amp_factor: u64,
         rates_type: String,
         contract_id: ValidAccountId,
+        rates_config: Option<RatesConfig>,
     ) -> u64 {
         assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
         check_token_duplicates(&tokens);
@@ -173,6 +345,7 @@ impl Contract {
             fee,
             rates_type,
             contract_id.as_ref().clone(),
+            rates_config,
         )))
     }
 
@@ -181,53 +354,75 @@ impl Contract {
     /// Executes generic set of actions.
     /// If referrer provided, pays referral_fee to it.
     /// If no attached deposit, outgoing tokens used in swaps must be whitelisted.
+    /// If rates of the used rated pools are stale, fetches them first and executes the actions in the callback.
     #[payable]
     pub fn execute_actions(
         &mut self,
         actions: Vec<Action>,
         referral_id: Option<ValidAccountId>,
-    ) -> ActionResult {
-        self.assert_contract_running();
-        let sender_id = env::predecessor_account_id();
-        let mut account = self.internal_unwrap_account(&sender_id);
-        // Validate that all tokens are whitelisted if no deposit (e.g. trade with access key).
-        if env::attached_deposit() == 0 {
-            for action in &actions {
-                for token in action.tokens() {
-                    assert!(
-                        account.get_balance(&token).is_some() 
-                            || self.whitelisted_tokens.contains(&token),
-                        "{}",
-                        // [AUDIT_05]
-                        ERR27_DEPOSIT_NEEDED
-                    );
-                }
-            }
+        deadline: Option<Deadline>,
+    ) -> PromiseOrValue<ActionResult> {
+        match self.internal_check_actions(&actions, &deadline) {
+            Some((promise, rate_updates)) => promise
+                .then(ext_self::callback_execute_actions_with_rates(
+                    env::predecessor_account_id(),
+                    actions,
+                    referral_id.map(|r| r.into()),
+                    deadline,
+                    rate_updates.clone(),
+                    &env::current_account_id(),
+                    // Passes the deposit on, as it's checked by the actions.
+                    env::attached_deposit(),
+                    self.internal_callback_gas(&rate_updates),
+                ))
+                .into(),
+            None => PromiseOrValue::Value(self.internal_execute_deposit_actions(
+                &env::predecessor_account_id(),
+                &referral_id.map(|r| r.into()),
+                &actions,
+            )),
         }
-        let referral_id = referral_id.map(|r| r.into());
-        let result =
-            self.internal_execute_actions(&mut account, &referral_id, &actions, ActionResult::None);
-        self.internal_save_account(&sender_id, account);
-        result
     }
 
     /// Execute set of swap actions between pools.
     /// If referrer provided, pays referral_fee to it.
     /// If no attached deposit, outgoing tokens used in swaps must be whitelisted.
+    /// If rates of the used rated pools are stale, fetches them first and swaps in the callback.
     #[payable]
-    pub fn swap(&mut self, actions: Vec<SwapAction>, referral_id: Option<ValidAccountId>) -> U128 {
-        self.assert_contract_running();
+    pub fn swap(
+        &mut self,
+        actions: Vec<SwapAction>,
+        referral_id: Option<ValidAccountId>,
+        deadline: Option<Deadline>,
+    ) -> PromiseOrValue<U128> {
         assert_ne!(actions.len(), 0, "{}", ERR72_AT_LEAST_ONE_SWAP);
-        U128(
-            self.execute_actions(
-                actions
-                    .into_iter()
-                    .map(|swap_action| Action::Swap(swap_action))
-                    .collect(),
-                referral_id,
-            )
-            .to_amount(),
-        )
+        let actions: Vec<Action> = actions
+            .into_iter()
+            .map(|swap_action| Action::Swap(swap_action))
+            .collect();
+        match self.internal_check_actions(&actions, &deadline) {
+            Some((promise, rate_updates)) => promise
+                .then(ext_self::callback_swap_with_rates(
+                    env::predecessor_account_id(),
+                    actions,
+                    referral_id.map(|r| r.into()),
+                    deadline,
+                    rate_updates.clone(),
+                    &env::current_account_id(),
+                    // Passes the deposit on, as it's checked by the actions.
+                    env::attached_deposit(),
+                    self.internal_callback_gas(&rate_updates),
+                ))
+                .into(),
+            None => PromiseOrValue::Value(U128(
+                self.internal_execute_deposit_actions(
+                    &env::predecessor_account_id(),
+                    &referral_id.map(|r| r.into()),
+                    &actions,
+                )
+                .to_amount(),
+            )),
+        }
     }
 
     /// Add liquidity from already deposited amounts to given pool.
@@ -246,7 +441,8 @@ impl Contract {
         let prev_storage = env::storage_usage();
         let sender_id = env::predecessor_account_id();
         let mut amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
-        let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        self.assert_pool_add_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
         // Add amounts given to liquidity first. It will return the balanced amounts.
         let shares = pool.add_liquidity(
             &sender_id,
@@ -267,6 +463,7 @@ impl Contract {
         self.internal_save_account(&sender_id, deposits);
         self.pools.replace(pool_id, &pool);
         self.internal_check_storage(prev_storage);
+        self.internal_update_pool_observations(pool_id, &pool);
 
         U128(shares)
     }
@@ -290,36 +487,93 @@ impl Contract {
         );
         let prev_storage = env::storage_usage();
         let sender_id = env::predecessor_account_id();
-        let amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
-        let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
-        // Add amounts given to liquidity first. It will return the balanced amounts.
-        let mint_shares = pool.add_stable_liquidity(
-            &sender_id,
-            &amounts,
-            min_shares.into(),
-            AdminFees::new(self.exchange_fee),
-        );
-        let mut deposits = self.internal_unwrap_or_default_account(&sender_id);
-        let tokens = pool.tokens();
-        // Subtract amounts from deposits. This will fail if there is not enough funds for any of the tokens.
-        for i in 0..tokens.len() {
-            deposits.withdraw(&tokens[i], amounts[i]);
-        }
-        self.internal_save_account(&sender_id, deposits);
-        self.pools.replace(pool_id, &pool);
+        let mint_shares = self.internal_add_stable_liquidity(&sender_id, pool_id, amounts, min_shares.into());
         self.internal_check_storage(prev_storage);
+        self.internal_update_pool_observations(pool_id, &self.internal_get_pool(pool_id));
 
         mint_shares.into()
     }
 
+    /// Same as `add_stable_liquidity` for rated pool.
+    /// If rates of the pool are stale, fetches them first and adds liquidity in the callback.
+    /// In this case attached NEAR goes to the storage deposit of the sender and pays for the shares from there,
+    /// amounts stay in the deposits until the callback, which fails without adding liquidity if the rates can't be fetched.
     #[payable]
     pub fn add_rated_liquidity(
         &mut self,
         pool_id: u64,
         amounts: Vec<U128>,
         min_shares: U128,
+    ) -> PromiseOrValue<U128> {
+        match self.internal_update_stale_rates(std::iter::once(pool_id)) {
+            Some((promise, rate_updates)) => {
+                self.assert_contract_running();
+                assert!(
+                    env::attached_deposit() > 0,
+                    "{}", ERR35_AT_LEAST_ONE_YOCTO
+                );
+                let sender_id = env::predecessor_account_id();
+                let mut account = self.internal_unwrap_account(&sender_id);
+                account.near_amount += env::attached_deposit();
+                self.internal_save_account(&sender_id, account);
+                let callback_gas = self.internal_callback_gas(&rate_updates);
+                promise
+                    .then(ext_self::callback_add_rated_liquidity(
+                        sender_id,
+                        pool_id,
+                        amounts,
+                        min_shares,
+                        rate_updates,
+                        &env::current_account_id(),
+                        NO_DEPOSIT,
+                        callback_gas,
+                    ))
+                    .into()
+            }
+            None => PromiseOrValue::Value(self.add_stable_liquidity(pool_id, amounts, min_shares)),
+        }
+    }
+
+    /// Adds liquidity to a simple pool of two tokens from already deposited amount of one of its tokens.
+    /// Part of the amount is swapped through the pool into the other token and both are added as liquidity,
+    /// leftovers that don't fit into the pool ratio are returned to the deposits.
+    /// min_shares: Slippage, if shares minted are less than it, panic with ERR68_SLIPPAGE
+    #[payable]
+    pub fn zap_liquidity(
+        &mut self,
+        pool_id: u64,
+        token_id: ValidAccountId,
+        amount: U128,
+        min_shares: U128,
     ) -> U128 {
-        self.add_stable_liquidity(pool_id, amounts, min_shares)
+        self.assert_contract_running();
+        assert!(
+            env::attached_deposit() > 0,
+            "{}", ERR35_AT_LEAST_ONE_YOCTO
+        );
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        let mut deposits = self.internal_unwrap_or_default_account(&sender_id);
+        deposits.withdraw(token_id.as_ref(), amount.into());
+        self.assert_pool_swap_allowed(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
+        let (shares, leftovers) = pool.zap_liquidity(
+            &sender_id,
+            token_id.as_ref(),
+            amount.into(),
+            min_shares.into(),
+            &AdminFees::new(self.exchange_fee),
+        );
+        let tokens = pool.tokens();
+        for i in 0..tokens.len() {
+            deposits.deposit(&tokens[i], leftovers[i]);
+        }
+        self.internal_save_account(&sender_id, deposits);
+        self.pools.replace(pool_id, &pool);
+        self.internal_check_storage(prev_storage);
+        self.internal_update_pool_observations(pool_id, &pool);
+
+        shares.into()
     }
 
     /// Remove liquidity from the pool into general pool of liquidity.
@@ -329,7 +583,8 @@ impl Contract {
         self.assert_contract_running();
         let prev_storage = env::storage_usage();
         let sender_id = env::predecessor_account_id();
-        let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        self.assert_pool_remove_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
         let amounts = pool.remove_liquidity(
             &sender_id,
             shares.into(),
@@ -350,6 +605,7 @@ impl Contract {
                 (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
         }
         self.internal_save_account(&sender_id, deposits);
+        self.internal_update_pool_observations(pool_id, &pool);
 
         amounts
             .into_iter()
@@ -371,7 +627,8 @@ impl Contract {
         self.assert_contract_running();
         let prev_storage = env::storage_usage();
         let sender_id = env::predecessor_account_id();
-        let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        self.assert_pool_remove_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
         let burn_shares = pool.remove_liquidity_by_tokens(
             &sender_id,
             amounts
@@ -394,14 +651,163 @@ impl Contract {
                 (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
         }
         self.internal_save_account(&sender_id, deposits);
+        self.internal_update_pool_observations(pool_id, &pool);
 
         burn_shares.into()
     }
 
+    /// For stable and rated swap pools, LP can use it to remove liquidity by given shares into a single token.
+    /// token_id: the token user wants to get, it should be one of the pool tokens.
+    /// min_amount: This is slippage protection, if user would get less of the token, panic with ERR68_SLIPPAGE
+    #[payable]
+    pub fn remove_liquidity_one_coin(
+        &mut self,
+        pool_id: u64,
+        shares: U128,
+        token_id: ValidAccountId,
+        min_amount: U128,
+    ) -> U128 {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        self.assert_pool_remove_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
+        let amount = pool.remove_liquidity_one_coin(
+            &sender_id,
+            shares.into(),
+            token_id.as_ref(),
+            min_amount.into(),
+            AdminFees::new(self.exchange_fee),
+        );
+        self.pools.replace(pool_id, &pool);
+        let mut deposits = self.internal_unwrap_or_default_account(&sender_id);
+        deposits.deposit(token_id.as_ref(), amount);
+        // Freed up storage balance from LP tokens will be returned to near_balance.
+        if prev_storage > env::storage_usage() {
+            deposits.near_amount +=
+                (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
+        }
+        self.internal_save_account(&sender_id, deposits);
+        self.internal_update_pool_observations(pool_id, &pool);
+
+        amount.into()
+    }
+
+    /// Opens new position in the concentrated liquidity pool within price range of `[tick_lower, tick_upper)`
+    /// and adds liquidity to it from already deposited amounts. Returns id of the position.
+    /// Attached NEAR should be enough to cover the storage of the position.
+    #[payable]
+    pub fn open_position(
+        &mut self,
+        pool_id: u64,
+        tick_lower: i32,
+        tick_upper: i32,
+        amounts: Vec<U128>,
+        min_amounts: Option<Vec<U128>>,
+    ) -> u64 {
+        self.assert_contract_running();
+        assert!(
+            env::attached_deposit() > 0,
+            "{}", ERR35_AT_LEAST_ONE_YOCTO
+        );
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        self.assert_pool_add_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_concentrated_pool(pool_id);
+        let position_id = pool.open_position(&sender_id, tick_lower, tick_upper);
+        self.internal_add_position_liquidity(&sender_id, &mut pool, position_id, amounts, min_amounts);
+        self.pools.replace(pool_id, &Pool::ConcentratedLiquidityPool(pool));
+        self.internal_check_storage(prev_storage);
+
+        position_id
+    }
+
+    /// Adds liquidity from already deposited amounts to the position in the concentrated liquidity pool.
+    /// Returns liquidity added.
+    #[payable]
+    pub fn add_position_liquidity(
+        &mut self,
+        pool_id: u64,
+        position_id: u64,
+        amounts: Vec<U128>,
+        min_amounts: Option<Vec<U128>>,
+    ) -> U128 {
+        self.assert_contract_running();
+        assert!(
+            env::attached_deposit() > 0,
+            "{}", ERR35_AT_LEAST_ONE_YOCTO
+        );
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        self.assert_pool_add_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_concentrated_pool(pool_id);
+        let liquidity = self.internal_add_position_liquidity(&sender_id, &mut pool, position_id, amounts, min_amounts);
+        self.pools.replace(pool_id, &Pool::ConcentratedLiquidityPool(pool));
+        self.internal_check_storage(prev_storage);
+
+        U128(liquidity)
+    }
+
+    /// Removes liquidity from the position in the concentrated liquidity pool into general pool of liquidity.
+    /// Fees accrued by the position are paid out together with removed liquidity.
+    #[payable]
+    pub fn remove_position_liquidity(
+        &mut self,
+        pool_id: u64,
+        position_id: u64,
+        liquidity: U128,
+        min_amounts: Vec<U128>,
+    ) -> Vec<U128> {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        self.assert_pool_remove_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_concentrated_pool(pool_id);
+        let amounts = pool.remove_liquidity(
+            &sender_id,
+            position_id,
+            liquidity.into(),
+            min_amounts
+                .into_iter()
+                .map(|amount| amount.into())
+                .collect(),
+        );
+        self.internal_withdraw_from_concentrated_pool(&sender_id, pool_id, pool, amounts, prev_storage)
+    }
+
+    /// Pays out fees accrued by the position in the concentrated liquidity pool into general pool of liquidity.
+    #[payable]
+    pub fn collect_position_fees(&mut self, pool_id: u64, position_id: u64) -> Vec<U128> {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        self.assert_pool_remove_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_concentrated_pool(pool_id);
+        let amounts = pool.collect_fees(&sender_id, position_id);
+        self.internal_withdraw_from_concentrated_pool(&sender_id, pool_id, pool, amounts, prev_storage)
+    }
+
+    /// Closes position without liquidity in the concentrated liquidity pool.
+    /// Tokens not yet collected are paid out, freed storage is returned to near_balance.
+    #[payable]
+    pub fn close_position(&mut self, pool_id: u64, position_id: u64) -> Vec<U128> {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        self.assert_pool_remove_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_concentrated_pool(pool_id);
+        let amounts = pool.close_position(&sender_id, position_id);
+        self.internal_withdraw_from_concentrated_pool(&sender_id, pool_id, pool, amounts, prev_storage)
+    }
+
     ///
     #[payable]
     pub fn update_pool_rates(&mut self, pool_id: u64) -> PromiseOrValue<bool> {
-        let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let pool = self.internal_get_pool(pool_id);
         match pool.update_rates() {
             PromiseOrValue::Promise(promise) => {
                 promise.then(ext_self::update_pool_rates_callback(
@@ -418,18 +824,17 @@ impl Contract {
         
     }
 
-    ///
+    /// Applies results of the rate calls, cached rates are kept if any of the calls failed.
     #[private]
     pub fn update_pool_rates_callback(&mut self, pool_id: u64) -> bool {
-        assert_eq!(env::promise_results_count(), 1, "{}", ERR123_ONE_PROMISE_RESULT);
-        let cross_call_result = match env::promise_result(0) {
-            PromiseResult::Successful(result) => result,
-            _ => env::panic(ERR124_CROSS_CALL_FAILED.as_bytes()),
-        };
+        let cross_call_results: Vec<Vec<u8>> = (0..env::promise_results_count())
+            .map(|index| match env::promise_result(index) {
+                PromiseResult::Successful(result) => result,
+                _ => env::panic(ERR124_CROSS_CALL_FAILED.as_bytes()),
+            })
+            .collect();
 
-        let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
-        assert!(pool.update_callback(&cross_call_result), "{}", ERR125_FAILED_TO_APPLY_RATES);
-        self.pools.replace(pool_id, &pool);
+        self.internal_apply_pool_rates(pool_id, &cross_call_results);
         true
     }
 }
@@ -444,6 +849,32 @@ impl Contract {
         };
     }
 
+    fn internal_get_pool_state(&self, pool_id: u64) -> PoolState {
+        self.pool_states.get(&pool_id).unwrap_or(PoolState::Running)
+    }
+
+    /// Swaps are only allowed in running pools.
+    fn assert_pool_swap_allowed(&self, pool_id: u64) {
+        match self.internal_get_pool_state(pool_id) {
+            PoolState::Running => (),
+            _ => env::panic(ERR52_POOL_SWAPS_PAUSED.as_bytes()),
+        };
+    }
+
+    fn assert_pool_add_liquidity_allowed(&self, pool_id: u64) {
+        match self.internal_get_pool_state(pool_id) {
+            PoolState::Running | PoolState::SwapsPaused => (),
+            _ => env::panic(ERR53_POOL_ADD_LIQUIDITY_PAUSED.as_bytes()),
+        };
+    }
+
+    fn assert_pool_remove_liquidity_allowed(&self, pool_id: u64) {
+        assert!(
+            self.internal_get_pool_state(pool_id) != PoolState::Frozen,
+            "{}", ERR54_POOL_FROZEN
+        );
+    }
+
     /// Check how much storage taken costs and refund the left over back.
     fn internal_check_storage(&self, prev_storage: StorageUsage) {
         let storage_cost = env::storage_usage()
@@ -467,62 +898,427 @@ impl Contract {
     /// Adds given pool to the list and returns it's id.
     /// If there is not enough attached balance to cover storage, fails.
     /// If too much attached - refunds it back.
-    fn internal_add_pool(&mut self, mut pool: Pool) -> u64 {
+    fn internal_add_pool(&mut self, pool: Pool) -> u64 {
         let prev_storage = env::storage_usage();
+        let id = self.internal_push_pool(pool);
+        self.internal_check_storage(prev_storage);
+        id
+    }
+
+    /// Adds given pool to the list and returns it's id, storage should be paid by the caller.
+    fn internal_push_pool(&mut self, mut pool: Pool) -> u64 {
         let id = self.pools.len() as u64;
         // exchange share was registered at creation time
         pool.share_register(&env::current_account_id());
         self.pools.push(&pool);
-        self.internal_check_storage(prev_storage);
+        // Observations take all their storage upfront, as swaps don't charge anyone for storage.
+        self.pool_observations.insert(&id, &PoolObservations::new(pool.tokens().len()));
         id
     }
 
-    /// Execute sequence of actions on given account. Modifies passed account.
-    /// Returns result of the last action.
-    fn internal_execute_actions(
-        &mut self,
-        account: &mut Account,
-        referral_id: &Option<AccountId>,
-        actions: &[Action],
-        prev_result: ActionResult,
-    ) -> ActionResult {
-        let mut result = prev_result;
-        for action in actions {
-            result = self.internal_execute_action(account, referral_id, action, result);
+    /// Returns the pool with its fee brought up to date with an ongoing fee ramp.
+    /// Rated pool also gets the freshness policy and the update time of its rates,
+    /// meta pool gets the share price of its base pool.
+    fn internal_get_pool(&self, pool_id: u64) -> Pool {
+        let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        if let Some(fee_ramp) = self.pool_fee_ramps.get(&pool_id) {
+            pool.set_fee(fee_ramp.get_fee());
         }
-        result
+        if let Pool::RatedSwapPool(pool) = &mut pool {
+            pool.rates_freshness = self.pool_rates_freshness.get(&pool_id).unwrap_or_default();
+            pool.rates_updated_timestamp = self.pool_rates_updated_at.get(&pool_id).unwrap_or_default();
+            if let Rates::Meta(rates) = &mut pool.rates {
+                let base_pool = self.internal_get_pool(rates.base_pool_id);
+                if base_pool.share_total_balance() > 0 {
+                    rates.set_share_price(base_pool.get_share_price(None));
+                }
+                pool.rates_updated_timestamp = env::block_timestamp();
+            }
+        }
+        pool
     }
 
-    /// Executes single action on given account. Modifies passed account. Returns a result based on type of action.
-    fn internal_execute_action(
+    /// Returns concentrated liquidity pool with given id, fails if the pool is of other kind.
+    fn internal_get_concentrated_pool(&self, pool_id: u64) -> ConcentratedLiquidityPool {
+        match self.internal_get_pool(pool_id) {
+            Pool::ConcentratedLiquidityPool(pool) => pool,
+            _ => env::panic(ERR149_NOT_CONCENTRATED_POOL.as_bytes()),
+        }
+    }
+
+    /// Adds liquidity to the stable or rated pool, withdrawing amounts from the deposits of the sender.
+    /// Storage of the shares should be paid by the caller.
+    fn internal_add_stable_liquidity(
         &mut self,
-        account: &mut Account,
-        referral_id: &Option<AccountId>,
-        action: &Action,
-        prev_result: ActionResult,
-    ) -> ActionResult {
-        match action {
-            Action::Swap(swap_action) => {
-                let amount_in = swap_action
-                    .amount_in
-                    .map(|value| value.0)
-                    .unwrap_or_else(|| prev_result.to_amount());
-                account.withdraw(&swap_action.token_in, amount_in);
-                let amount_out = self.internal_pool_swap(
-                    swap_action.pool_id,
-                    &swap_action.token_in,
-                    amount_in,
+        sender_id: &AccountId,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        min_shares: Balance,
+    ) -> Balance {
+        let amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
+        self.assert_pool_add_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
+        // Add amounts given to liquidity first. It will return the balanced amounts.
+        let mint_shares = pool.add_stable_liquidity(
+            sender_id,
+            &amounts,
+            min_shares,
+            AdminFees::new(self.exchange_fee),
+        );
+        let mut deposits = self.internal_unwrap_or_default_account(sender_id);
+        let tokens = pool.tokens();
+        // Subtract amounts from deposits. This will fail if there is not enough funds for any of the tokens.
+        for i in 0..tokens.len() {
+            deposits.withdraw(&tokens[i], amounts[i]);
+        }
+        self.internal_save_account(sender_id, deposits);
+        self.pools.replace(pool_id, &pool);
+        mint_shares
+    }
+
+    /// Adds liquidity to the position, withdrawing used amounts from the deposits of the sender.
+    fn internal_add_position_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        pool: &mut ConcentratedLiquidityPool,
+        position_id: u64,
+        amounts: Vec<U128>,
+        min_amounts: Option<Vec<U128>>,
+    ) -> u128 {
+        let mut amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
+        let liquidity = pool.add_liquidity(sender_id, position_id, &mut amounts);
+        if let Some(min_amounts) = min_amounts {
+            // Check that all amounts are above request min amounts in case of front running that changes the price.
+            for (amount, min_amount) in amounts.iter().zip(min_amounts.iter()) {
+                assert!(amount >= &min_amount.0, "{}", ERR86_MIN_AMOUNT);
+            }
+        }
+        let mut deposits = self.internal_unwrap_or_default_account(sender_id);
+        let tokens = pool.tokens();
+        // Subtract used amounts from deposits. This will fail if there is not enough funds for any of the tokens.
+        for i in 0..tokens.len() {
+            deposits.withdraw(&tokens[i], amounts[i]);
+        }
+        self.internal_save_account(sender_id, deposits);
+        liquidity
+    }
+
+    /// Saves the pool and deposits amounts paid out by it to the sender.
+    /// Freed up storage balance is returned to near_balance.
+    fn internal_withdraw_from_concentrated_pool(
+        &mut self,
+        sender_id: &AccountId,
+        pool_id: u64,
+        pool: ConcentratedLiquidityPool,
+        amounts: Vec<Balance>,
+        prev_storage: StorageUsage,
+    ) -> Vec<U128> {
+        let mut deposits = self.internal_unwrap_or_default_account(sender_id);
+        let tokens = pool.tokens();
+        for i in 0..tokens.len() {
+            deposits.deposit(&tokens[i], amounts[i]);
+        }
+        self.pools.replace(pool_id, &Pool::ConcentratedLiquidityPool(pool));
+        if prev_storage > env::storage_usage() {
+            deposits.near_amount +=
+                (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
+        }
+        self.internal_save_account(sender_id, deposits);
+
+        amounts
+            .into_iter()
+            .map(|amount| amount.into())
+            .collect()
+    }
+
+    /// Checks that the sender can execute given actions on its deposits.
+    /// Returns promise fetching the stale rates of the used rated pools, if there are any.
+    fn internal_check_actions(
+        &self,
+        actions: &[Action],
+        deadline: &Option<Deadline>,
+    ) -> Option<(Promise, Vec<(u64, u32)>)> {
+        self.assert_contract_running();
+        if let Some(deadline) = deadline {
+            deadline.assert_not_expired();
+        }
+        let account = self.internal_unwrap_account(&env::predecessor_account_id());
+        // Validate that all tokens are whitelisted if no deposit (e.g. trade with access key).
+        if env::attached_deposit() == 0 {
+            for action in actions {
+                let mut tokens = action.tokens();
+                if let Some(pool_id) = action.liquidity_pool_id() {
+                    tokens.extend(self.internal_get_pool(pool_id).tokens().iter().cloned());
+                }
+                for token in tokens {
+                    assert!(
+                        account.get_balance(&token).is_some() 
+                            || self.whitelisted_tokens.contains(&token),
+                        "{}",
+                        // [AUDIT_05]
+                        ERR27_DEPOSIT_NEEDED
+                    );
+                }
+            }
+        }
+        self.internal_update_stale_rates(actions.iter().flat_map(|action| action.pool_ids()))
+    }
+
+    /// Executes sequence of actions on the deposits of given account and saves it.
+    fn internal_execute_deposit_actions(
+        &mut self,
+        account_id: &AccountId,
+        referral_id: &Option<AccountId>,
+        actions: &[Action],
+    ) -> ActionResult {
+        let mut account = self.internal_unwrap_account(account_id);
+        let result = self.internal_execute_actions(&mut account, account_id, referral_id, actions, ActionResult::None);
+        self.internal_save_account(account_id, account);
+        result
+    }
+
+    /// Execute sequence of actions on given account. Modifies passed account.
+    /// Returns result of the last action.
+    fn internal_execute_actions(
+        &mut self,
+        account: &mut Account,
+        account_id: &AccountId,
+        referral_id: &Option<AccountId>,
+        actions: &[Action],
+        prev_result: ActionResult,
+    ) -> ActionResult {
+        let mut result = prev_result;
+        // Start of the exact output route, that is not yet executed.
+        let mut route_start = None;
+        for (i, action) in actions.iter().enumerate() {
+            if let Action::SwapByOutput(swap_action) = action {
+                let start = *route_start.get_or_insert(i);
+                if swap_action.amount_out.is_some() {
+                    result = self.internal_execute_swap_by_output_route(
+                        account,
+                        referral_id,
+                        &actions[start..=i],
+                    );
+                    route_start = None;
+                }
+            } else {
+                assert!(route_start.is_none(), "{}", ERR77_ROUTE_WITHOUT_AMOUNT_OUT);
+                result = self.internal_execute_action(account, account_id, referral_id, action, result);
+            }
+        }
+        assert!(route_start.is_none(), "{}", ERR77_ROUTE_WITHOUT_AMOUNT_OUT);
+        result
+    }
+
+    /// Executes exact output route on given account. Modifies passed account.
+    /// Route is resolved backwards: each step receives amount_in of the next one, the last step receives its amount_out.
+    /// Returns amount received on the last step.
+    fn internal_execute_swap_by_output_route(
+        &mut self,
+        account: &mut Account,
+        referral_id: &Option<AccountId>,
+        route: &[Action],
+    ) -> ActionResult {
+        let mut transfers = vec![];
+        let mut next_amount_in = None;
+        for action in route.iter().rev() {
+            if let Action::SwapByOutput(swap_action) = action {
+                let amount_out = swap_action
+                    .amount_out
+                    .map(|value| value.0)
+                    .or(next_amount_in)
+                    .expect(ERR77_ROUTE_WITHOUT_AMOUNT_OUT);
+                let amount_in = self.internal_pool_swap_by_output(
+                    swap_action.pool_id,
+                    &swap_action.token_in,
+                    amount_out,
                     &swap_action.token_out,
-                    swap_action.min_amount_out.0,
+                    swap_action.max_amount_in.0,
                     referral_id,
                 );
-                account.deposit(&swap_action.token_out, amount_out);
-                // [AUDIT_02]
-                ActionResult::Amount(U128(amount_out))
+                transfers.push((&swap_action.token_in, amount_in, &swap_action.token_out, amount_out));
+                next_amount_in = Some(amount_in);
+            }
+        }
+        // Deposit outputs first, so that intermediate tokens are available to pay for the previous steps.
+        for (_, _, token_out, amount_out) in transfers.iter() {
+            account.deposit(token_out, *amount_out);
+        }
+        for (token_in, amount_in, _, _) in transfers.iter() {
+            account.withdraw(token_in, *amount_in);
+        }
+        ActionResult::Amount(U128(transfers[0].3))
+    }
+
+    /// Executes single action on given account. Modifies passed account. Returns a result based on type of action.
+    fn internal_execute_action(
+        &mut self,
+        account: &mut Account,
+        account_id: &AccountId,
+        referral_id: &Option<AccountId>,
+        action: &Action,
+        prev_result: ActionResult,
+    ) -> ActionResult {
+        if !matches!(action, Action::Swap(_) | Action::SwapByOutput(_) | Action::SplitSwap(_)) {
+            // Shares and transfers would stay on the virtual account of the instant swap.
+            assert_ne!(account_id, token_receiver::VIRTUAL_ACC, "{}", ERR79_ACTION_NEEDS_ACCOUNT);
+        }
+        match action {
+            Action::Swap(swap_action) => {
+                self.internal_execute_swap_action(account, referral_id, swap_action, prev_result)
+            }
+            Action::SwapByOutput(_) => self.internal_execute_swap_by_output_route(
+                account,
+                referral_id,
+                std::slice::from_ref(action),
+            ),
+            Action::SplitSwap(split_action) => {
+                let mut total_out = 0;
+                for route in split_action.routes.iter() {
+                    assert!(
+                        matches!(route.first(), Some(swap_action) if swap_action.amount_in.is_some())
+                            && route.last().unwrap().token_out == split_action.token_out,
+                        "{}",
+                        ERR78_ILLEGAL_SPLIT_ROUTE
+                    );
+                    let mut result = ActionResult::None;
+                    for swap_action in route.iter() {
+                        result = self.internal_execute_swap_action(account, referral_id, swap_action, result);
+                    }
+                    total_out += result.to_amount();
+                }
+                assert!(total_out >= split_action.min_total_out.0, "{}", ERR68_SLIPPAGE);
+                ActionResult::Amount(U128(total_out))
+            }
+            Action::AddLiquidity(liquidity_action) => {
+                let pool_id = liquidity_action.pool_id;
+                let mut amounts = prev_result.resolve_amounts(&liquidity_action.amounts);
+                self.assert_pool_add_liquidity_allowed(pool_id);
+                let prev_storage = env::storage_usage();
+                let mut pool = self.internal_get_pool(pool_id);
+                let shares = pool.add_liquidity(account_id, &mut amounts);
+                if let Some(min_amounts) = &liquidity_action.min_amounts {
+                    for (amount, min_amount) in amounts.iter().zip(min_amounts.iter()) {
+                        assert!(amount >= &min_amount.0, "{}", ERR86_MIN_AMOUNT);
+                    }
+                }
+                self.pools.replace(pool_id, &pool);
+                account.settle_storage(prev_storage);
+                for (token_id, amount) in pool.tokens().iter().zip(amounts) {
+                    account.withdraw(token_id, amount);
+                }
+                self.internal_update_pool_observations(pool_id, &pool);
+                ActionResult::Amount(U128(shares))
+            }
+            Action::AddStableLiquidity(liquidity_action) => {
+                let pool_id = liquidity_action.pool_id;
+                let amounts = prev_result.resolve_amounts(&liquidity_action.amounts);
+                self.assert_pool_add_liquidity_allowed(pool_id);
+                let prev_storage = env::storage_usage();
+                let mut pool = self.internal_get_pool(pool_id);
+                let shares = pool.add_stable_liquidity(
+                    account_id,
+                    &amounts,
+                    liquidity_action.min_shares.0,
+                    AdminFees::new(self.exchange_fee),
+                );
+                self.pools.replace(pool_id, &pool);
+                account.settle_storage(prev_storage);
+                for (token_id, amount) in pool.tokens().iter().zip(amounts) {
+                    account.withdraw(token_id, amount);
+                }
+                self.internal_update_pool_observations(pool_id, &pool);
+                ActionResult::Amount(U128(shares))
+            }
+            Action::RemoveLiquidity(liquidity_action) => {
+                let pool_id = liquidity_action.pool_id;
+                let shares = liquidity_action
+                    .shares
+                    .map(|value| value.0)
+                    .unwrap_or_else(|| prev_result.to_amount());
+                self.assert_pool_remove_liquidity_allowed(pool_id);
+                let prev_storage = env::storage_usage();
+                let mut pool = self.internal_get_pool(pool_id);
+                let amounts = pool.remove_liquidity(
+                    account_id,
+                    shares,
+                    liquidity_action.min_amounts.iter().map(|amount| amount.0).collect(),
+                );
+                self.pools.replace(pool_id, &pool);
+                account.settle_storage(prev_storage);
+                for (token_id, amount) in pool.tokens().iter().zip(amounts.iter()) {
+                    account.deposit(token_id, *amount);
+                }
+                self.internal_update_pool_observations(pool_id, &pool);
+                ActionResult::Amounts(amounts.into_iter().map(U128).collect())
+            }
+            Action::Transfer(transfer_action) => {
+                let amount = transfer_action
+                    .amount
+                    .map(|value| value.0)
+                    .unwrap_or_else(|| prev_result.to_amount());
+                let receiver_id = &transfer_action.receiver_id;
+                assert_ne!(account_id, receiver_id, "{}", ERR33_TRANSFER_TO_SELF);
+                match multi_fungible_token::try_identify_pool_id(&transfer_action.token_id) {
+                    Ok(pool_id) => {
+                        let prev_storage = env::storage_usage();
+                        let mut pool = self.internal_get_pool(pool_id);
+                        pool.share_transfer(account_id, receiver_id, amount);
+                        self.pools.replace(pool_id, &pool);
+                        account.settle_storage(prev_storage);
+                        log!("Transfer shares {} pool: {} from {} to {}", pool_id, amount, account_id, receiver_id);
+                    }
+                    Err(_) => {
+                        let token_id = &transfer_action.token_id;
+                        let mut receiver_account = self.internal_unwrap_account(receiver_id);
+                        account.withdraw(token_id, amount);
+                        receiver_account.deposit(token_id, amount);
+                        self.internal_save_account(receiver_id, receiver_account);
+                        log!("Transfer {}: {} from {} to {}", token_id, amount, account_id, receiver_id);
+                    }
+                }
+                ActionResult::Amount(U128(amount))
+            }
+            Action::Withdraw(withdraw_action) => {
+                assert!(env::attached_deposit() > 0, "{}", ERR35_AT_LEAST_ONE_YOCTO);
+                let amount = withdraw_action
+                    .amount
+                    .map(|value| value.0)
+                    .unwrap_or_else(|| prev_result.to_amount());
+                assert!(amount > 0, "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT);
+                account.withdraw(&withdraw_action.token_id, amount);
+                self.internal_send_tokens(account_id, &withdraw_action.token_id, amount);
+                ActionResult::Amount(U128(amount))
             }
         }
     }
 
+    /// Executes single swap action on given account, taking amount_in from the previous result if it's not set.
+    fn internal_execute_swap_action(
+        &mut self,
+        account: &mut Account,
+        referral_id: &Option<AccountId>,
+        swap_action: &SwapAction,
+        prev_result: ActionResult,
+    ) -> ActionResult {
+        let amount_in = swap_action
+            .amount_in
+            .map(|value| value.0)
+            .unwrap_or_else(|| prev_result.to_amount());
+        account.withdraw(&swap_action.token_in, amount_in);
+        let amount_out = self.internal_pool_swap(
+            swap_action.pool_id,
+            &swap_action.token_in,
+            amount_in,
+            &swap_action.token_out,
+            swap_action.min_amount_out.0,
+            referral_id,
+        );
+        account.deposit(&swap_action.token_out, amount_out);
+        // [AUDIT_02]
+        ActionResult::Amount(U128(amount_out))
+    }
+
     /// Swaps given amount_in of token_in into token_out via given pool.
     /// Should be at least min_amount_out or swap will fail (prevents front running and other slippage issues).
     fn internal_pool_swap(
@@ -534,7 +1330,22 @@ impl Contract {
         min_amount_out: u128,
         referral_id: &Option<AccountId>,
     ) -> u128 {
-        let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        self.assert_pool_swap_allowed(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
+        if let Some(base_pool_id) = pool.meta_base_pool_id() {
+            if !pool.tokens().contains(token_in) || !pool.tokens().contains(token_out) {
+                return self.internal_meta_pool_swap(
+                    pool_id,
+                    pool,
+                    base_pool_id,
+                    token_in,
+                    amount_in,
+                    token_out,
+                    min_amount_out,
+                    referral_id,
+                );
+            }
+        }
         let amount_out = pool.swap(
             token_in,
             amount_in,
@@ -548,8 +1359,39 @@ impl Contract {
             },
         );
         self.pools.replace(pool_id, &pool);
+        self.internal_update_pool_observations(pool_id, &pool);
         amount_out
     }
+
+    /// Swaps token_in into given amount_out of token_out via given pool.
+    /// Should be at most max_amount_in or swap will fail (prevents front running and other slippage issues).
+    fn internal_pool_swap_by_output(
+        &mut self,
+        pool_id: u64,
+        token_in: &AccountId,
+        amount_out: u128,
+        token_out: &AccountId,
+        max_amount_in: u128,
+        referral_id: &Option<AccountId>,
+    ) -> u128 {
+        self.assert_pool_swap_allowed(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
+        let amount_in = pool.swap_by_output(
+            token_in,
+            amount_out,
+            token_out,
+            max_amount_in,
+            AdminFees {
+                exchange_fee: self.exchange_fee,
+                exchange_id: env::current_account_id(),
+                referral_fee: self.referral_fee,
+                referral_id: referral_id.clone(),
+            },
+        );
+        self.pools.replace(pool_id, &pool);
+        self.internal_update_pool_observations(pool_id, &pool);
+        amount_in
+    }
 }
 
 #[cfg(test)]
@@ -562,6 +1404,9 @@ mod tests {
     use near_sdk_sim::to_yocto;
 
     use super::*;
+    use crate::meta_pool::META_POOL_SHARES_HOLDER;
+    use crate::twap::{MAX_NUM_OBSERVATIONS, OBSERVATION_PERIOD};
+    use crate::utils::{PRICE_PRECISION, U256};
 
     /// Creates contract and a pool with tokens with 0.3% of total fee.
     fn setup_contract() -> (VMContextBuilder, Contract) {
@@ -571,6 +1416,13 @@ mod tests {
         (context, contract)
     }
 
+    fn unwrap_value<T>(result: PromiseOrValue<T>) -> T {
+        match result {
+            PromiseOrValue::Value(value) => value,
+            PromiseOrValue::Promise(_) => panic!("expected value"),
+        }
+    }
+
     fn deposit_tokens(
         context: &mut VMContextBuilder,
         contract: &mut Contract,
@@ -617,7 +1469,7 @@ mod tests {
         contract.extend_whitelisted_tokens(tokens.clone());
         testing_env!(context
             .predecessor_account_id(account_id.clone())
-            .attached_deposit(env::storage_byte_cost() * 300)
+            .attached_deposit(env::storage_byte_cost() * 3000)
             .build());
         let pool_id = contract.add_simple_pool(tokens, 25);
         testing_env!(context
@@ -645,18 +1497,18 @@ mod tests {
         amount_in: Balance,
         token_out: ValidAccountId,
     ) -> Balance {
-        contract
-            .swap(
-                vec![SwapAction {
-                    pool_id,
-                    token_in: token_in.into(),
-                    amount_in: Some(U128(amount_in)),
-                    token_out: token_out.into(),
-                    min_amount_out: U128(1),
-                }],
-                None,
-            )
-            .0
+        unwrap_value(contract.swap(
+            vec![SwapAction {
+                pool_id,
+                token_in: token_in.into(),
+                amount_in: Some(U128(amount_in)),
+                token_out: token_out.into(),
+                min_amount_out: U128(1),
+            }],
+            None,
+            None,
+        ))
+        .0
     }
 
     #[test]
@@ -781,63 +1633,184 @@ mod tests {
         assert_eq!(amounts[1].0 + deposit2, to_yocto("100"));
     }
 
-    /// Should deny creating a pool with duplicate tokens.
     #[test]
-    #[should_panic(expected = "E92: token duplicated")]
-    fn test_deny_duplicate_tokens_pool() {
+    fn test_weighted() {
         let (mut context, mut contract) = setup_contract();
-        create_pool_with_liquidity(
+        deposit_tokens(
             &mut context,
             &mut contract,
             accounts(3),
-            vec![(accounts(1), to_yocto("5")), (accounts(1), to_yocto("10"))],
+            vec![
+                (accounts(1), to_yocto("100")),
+                (accounts(2), to_yocto("100")),
+            ],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let id = contract.add_weighted_pool(vec![accounts(1), accounts(2)], vec![8000, 2000], 25);
+        assert_eq!(contract.get_pool(id).pool_kind, "WEIGHTED_POOL");
+        assert_eq!(contract.get_weighted_pool(id).weights, vec![8000, 2000]);
+        testing_env!(context.attached_deposit(to_yocto("0.0007")).build());
+        contract.add_liquidity(id, vec![U128(to_yocto("40")), U128(to_yocto("10"))], None);
+
+        let expected = contract.get_return(id, accounts(1), U128(to_yocto("1")), accounts(2)).0;
+        testing_env!(context.attached_deposit(1).build());
+        let out = swap(&mut contract, id, accounts(1), to_yocto("1"), accounts(2));
+        assert_eq!(out, expected);
+        assert_eq!(
+            contract.get_pool(id).amounts,
+            vec![U128(to_yocto("41")), U128(to_yocto("10") - out)]
         );
+        assert_eq!(contract.get_deposit(accounts(3), accounts(2)).0, to_yocto("90") + out);
+
+        contract.remove_liquidity(id, U128(to_yocto("1")), vec![U128(1), U128(1)]);
+        let amounts = contract.get_pool(id).amounts;
+        let deposit1 = contract.get_deposit(accounts(3), accounts(1)).0;
+        let deposit2 = contract.get_deposit(accounts(3), accounts(2)).0;
+        assert_eq!(amounts[0].0 + deposit1, to_yocto("100"));
+        assert_eq!(amounts[1].0 + deposit2, to_yocto("100"));
     }
 
-    /// Deny pool with a single token
     #[test]
-    #[should_panic(expected = "E89: wrong token count")]
-    fn test_deny_single_token_pool() {
+    #[should_panic(expected = "E80: operation is not supported by this pool kind")]
+    fn test_zap_liquidity_weighted() {
         let (mut context, mut contract) = setup_contract();
-        create_pool_with_liquidity(
+        deposit_tokens(
             &mut context,
             &mut contract,
             accounts(3),
-            vec![(accounts(1), to_yocto("5"))],
+            vec![
+                (accounts(1), to_yocto("100")),
+                (accounts(2), to_yocto("100")),
+            ],
         );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let id = contract.add_weighted_pool(vec![accounts(1), accounts(2)], vec![8000, 2000], 25);
+        testing_env!(context.attached_deposit(to_yocto("0.0007")).build());
+        contract.add_liquidity(id, vec![U128(to_yocto("40")), U128(to_yocto("10"))], None);
+        testing_env!(context.attached_deposit(to_yocto("0.01")).build());
+        contract.zap_liquidity(id, accounts(1), U128(to_yocto("1")), U128(1));
     }
 
-    /// Deny pool with a single token
     #[test]
-    #[should_panic(expected = "E89: wrong token count")]
-    fn test_deny_too_many_tokens_pool() {
+    fn test_concentrated() {
         let (mut context, mut contract) = setup_contract();
-        create_pool_with_liquidity(
+        deposit_tokens(
             &mut context,
             &mut contract,
             accounts(3),
             vec![
-                (accounts(1), to_yocto("5")),
-                (accounts(2), to_yocto("10")),
-                (accounts(3), to_yocto("10")),
+                (accounts(1), to_yocto("100")),
+                (accounts(2), to_yocto("100")),
             ],
         );
-    }
-
-    #[test]
-    #[should_panic(expected = "E12: token not whitelisted")]
-    fn test_deny_send_malicious_token() {
-        let (mut context, mut contract) = setup_contract();
-        let acc = ValidAccountId::try_from("test_user").unwrap();
         testing_env!(context
-            .predecessor_account_id(acc.clone())
+            .predecessor_account_id(accounts(3))
             .attached_deposit(to_yocto("1"))
             .build());
-        contract.storage_deposit(Some(acc.clone()), None);
+        let id = contract.add_concentrated_liquidity_pool(vec![accounts(1), accounts(2)], 30, 10, 0);
+        assert_eq!(contract.get_pool(id).pool_kind, "CONCENTRATED_LIQUIDITY");
+        let position_id = contract.open_position(
+            id,
+            -1000,
+            1000,
+            vec![U128(to_yocto("10")), U128(to_yocto("10"))],
+            None,
+        );
+        assert_eq!(contract.get_account_positions(id, accounts(3)), vec![position_id]);
+        assert_eq!(contract.get_pool_shares(id, accounts(3)).0, 0);
+        let position = contract.get_position(id, position_id).unwrap();
+        assert_eq!(contract.get_concentrated_pool(id).liquidity, position.liquidity);
+
+        let expected = contract.get_return(id, accounts(1), U128(to_yocto("1")), accounts(2)).0;
+        testing_env!(context.attached_deposit(1).build());
+        let out = swap(&mut contract, id, accounts(1), to_yocto("1"), accounts(2));
+        assert_eq!(out, expected);
+        assert!(contract.get_concentrated_pool(id).tick < 0);
+        let owed = contract.get_position(id, position_id).unwrap().tokens_owed;
+        assert!(owed[0].0 > 0);
+        assert_eq!(owed[1].0, 0);
+
+        contract.remove_position_liquidity(id, position_id, position.liquidity, vec![U128(1), U128(1)]);
+        contract.close_position(id, position_id);
+        assert!(contract.get_position(id, position_id).is_none());
+        // Exchange fee stays in the pool, everything else is back in deposits.
         testing_env!(context
-            .predecessor_account_id(ValidAccountId::try_from("malicious").unwrap())
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(to_yocto("1"))
             .build());
-        contract.ft_on_transfer(acc, U128(1_000), "".to_string());
+        contract.storage_deposit(None, None);
+        testing_env!(context.attached_deposit(1).build());
+        let exchange_fees = contract.claim_concentrated_exchange_fees(id);
+        assert!(exchange_fees[0].0 > 0);
+        let amounts = contract.get_pool(id).amounts;
+        assert!(amounts[0].0 <= 2 && amounts[1].0 <= 2);
+        let deposit1 = contract.get_deposit(accounts(3), accounts(1)).0;
+        let deposit2 = contract.get_deposit(accounts(3), accounts(2)).0;
+        assert_eq!(amounts[0].0 + deposit1 + exchange_fees[0].0, to_yocto("100"));
+        assert_eq!(amounts[1].0 + deposit2, to_yocto("100"));
+    }
+
+    /// Should deny creating a pool with duplicate tokens.
+    #[test]
+    #[should_panic(expected = "E92: token duplicated")]
+    fn test_deny_duplicate_tokens_pool() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(1), to_yocto("10"))],
+        );
+    }
+
+    /// Deny pool with a single token
+    #[test]
+    #[should_panic(expected = "E89: wrong token count")]
+    fn test_deny_single_token_pool() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5"))],
+        );
+    }
+
+    /// Deny pool with a single token
+    #[test]
+    #[should_panic(expected = "E89: wrong token count")]
+    fn test_deny_too_many_tokens_pool() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            (0..=simple_pool::MAX_NUM_TOKENS)
+                .map(|i| (ValidAccountId::try_from(format!("token{}", i)).unwrap(), to_yocto("5")))
+                .collect(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E12: token not whitelisted")]
+    fn test_deny_send_malicious_token() {
+        let (mut context, mut contract) = setup_contract();
+        let acc = ValidAccountId::try_from("test_user").unwrap();
+        testing_env!(context
+            .predecessor_account_id(acc.clone())
+            .attached_deposit(to_yocto("1"))
+            .build());
+        contract.storage_deposit(Some(acc.clone()), None);
+        testing_env!(context
+            .predecessor_account_id(ValidAccountId::try_from("malicious").unwrap())
+            .build());
+        contract.ft_on_transfer(acc, U128(1_000), "".to_string());
     }
 
     #[test]
@@ -897,6 +1870,7 @@ mod tests {
                 min_amount_out: U128(1_000_000),
             }],
             None,
+            None,
         );
     }
 
@@ -916,7 +1890,7 @@ mod tests {
         testing_env!(context.attached_deposit(to_yocto("1")).build());
         contract.storage_deposit(None, None);
         testing_env!(context.attached_deposit(1).build());
-        contract.swap(vec![], None);
+        contract.swap(vec![], None, None);
     }
 
     /// Check that can not swap non whitelisted tokens when attaching 0 deposit (access key).
@@ -982,210 +1956,359 @@ mod tests {
                 },
             ],
             None,
+            None,
         );
         // Roundtrip returns almost everything except 0.25% fee.
         assert_eq!(contract.get_deposit(acc, accounts(1)).0, 1_000_000 - 6);
     }
 
     #[test]
-    #[should_panic(expected = "E14: LP already registered")]
-    fn test_lpt_transfer() {
-        // account(0) -- swap contract
-        // account(1) -- token0 contract
-        // account(2) -- token1 contract
-        // account(3) -- user account
-        // account(4) -- another user account
+    fn test_swap_by_output_route() {
         let (mut context, mut contract) = setup_contract();
-        deposit_tokens(
+        create_pool_with_liquidity(
             &mut context,
             &mut contract,
             accounts(3),
-            vec![
-                (accounts(1), to_yocto("100")),
-                (accounts(2), to_yocto("100")),
-            ],
-        );
-        testing_env!(context
-            .predecessor_account_id(accounts(3))
-            .attached_deposit(to_yocto("1"))
-            .build());
-        let id = contract.add_simple_pool(vec![accounts(1), accounts(2)], 25);
-        testing_env!(context.attached_deposit(to_yocto("0.0007")).build());
-        contract.add_liquidity(id, vec![U128(to_yocto("50")), U128(to_yocto("10"))], None);
-        assert_eq!(
-            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
-            to_yocto("1")
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
         );
-        assert_eq!(contract.mft_total_supply(":0".to_string()).0, to_yocto("1"));
-        testing_env!(context.attached_deposit(1).build());
-        contract.add_liquidity(id, vec![U128(to_yocto("50")), U128(to_yocto("50"))], None);
-        assert_eq!(
-            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
-            to_yocto("2")
+        let acc = ValidAccountId::try_from("test_user").unwrap();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            acc.clone(),
+            vec![(accounts(1), 1_000_000)],
         );
-        assert_eq!(contract.mft_total_supply(":0".to_string()).0, to_yocto("2"));
-
-        // register another user
-        testing_env!(context
-            .predecessor_account_id(accounts(4))
-            .attached_deposit(to_yocto("0.00071"))
-            .build());
-        contract.mft_register(":0".to_string(), accounts(4));
-        // make transfer to him
         testing_env!(context
-            .predecessor_account_id(accounts(3))
+            .predecessor_account_id(acc.clone())
             .attached_deposit(1)
             .build());
-        contract.mft_transfer(":0".to_string(), accounts(4), U128(to_yocto("1")), None);
-        assert_eq!(
-            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
-            to_yocto("1")
+        let expected = contract.get_return_by_output(0, accounts(1), U128(10_000), accounts(2)).0;
+        let result = unwrap_value(contract.execute_actions(
+            vec![Action::SwapByOutput(SwapByOutputAction {
+                pool_id: 0,
+                token_in: accounts(1).into(),
+                amount_out: Some(U128(10_000)),
+                token_out: accounts(2).into(),
+                max_amount_in: U128(1_000_000),
+            })],
+            None,
+            None,
+        ));
+        assert_eq!(result.to_amount(), 10_000);
+        assert_eq!(contract.get_deposit(acc.clone(), accounts(1)).0, 1_000_000 - expected);
+        assert_eq!(contract.get_deposit(acc.clone(), accounts(2)).0, 10_000);
+
+        // Roundtrip route receiving exactly 1000 more, resolved from the last step.
+        let result = unwrap_value(contract.execute_actions(
+            vec![
+                Action::SwapByOutput(SwapByOutputAction {
+                    pool_id: 0,
+                    token_in: accounts(1).into(),
+                    amount_out: None,
+                    token_out: accounts(2).into(),
+                    max_amount_in: U128(1_000_000),
+                }),
+                Action::SwapByOutput(SwapByOutputAction {
+                    pool_id: 0,
+                    token_in: accounts(2).into(),
+                    amount_out: Some(U128(1_000)),
+                    token_out: accounts(1).into(),
+                    max_amount_in: U128(1_000_000),
+                }),
+            ],
+            None,
+            None,
+        ));
+        assert_eq!(result.to_amount(), 1_000);
+        let spent = 1_000_000 - expected + 1_000 - contract.get_deposit(acc.clone(), accounts(1)).0;
+        // Spends 1000 and 0.25% fee on each step.
+        assert!(spent > 1_004 && spent <= 1_007);
+        assert_eq!(contract.get_deposit(acc, accounts(2)).0, 10_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "E77: exact output route should end with amount_out")]
+    fn test_swap_by_output_route_without_amount_out() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
         );
-        assert_eq!(
-            contract.mft_balance_of(":0".to_string(), accounts(4)).0,
-            to_yocto("1")
+        let acc = ValidAccountId::try_from("test_user").unwrap();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            acc.clone(),
+            vec![(accounts(1), 1_000_000)],
         );
-        assert_eq!(contract.mft_total_supply(":0".to_string()).0, to_yocto("2"));
-        // remove lpt for account 3
         testing_env!(context
-            .predecessor_account_id(accounts(3))
+            .predecessor_account_id(acc.clone())
             .attached_deposit(1)
             .build());
-        contract.remove_liquidity(id, U128(to_yocto("0.6")), vec![U128(1), U128(1)]);
-        assert_eq!(
-            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
-            to_yocto("0.4")
-        );
-        assert_eq!(
-            contract.mft_total_supply(":0".to_string()).0,
-            to_yocto("1.4")
+        contract.execute_actions(
+            vec![Action::SwapByOutput(SwapByOutputAction {
+                pool_id: 0,
+                token_in: accounts(1).into(),
+                amount_out: None,
+                token_out: accounts(2).into(),
+                max_amount_in: U128(1_000_000),
+            })],
+            None,
+            None,
         );
-        // remove lpt for account 4 who got lpt from others
-        if contract.storage_balance_of(accounts(4)).is_none() {
-            testing_env!(context
-                .predecessor_account_id(accounts(4))
-                .attached_deposit(to_yocto("1"))
-                .build());
-            contract.storage_deposit(None, None);
+    }
+
+    fn split_swap(routes: Vec<(u64, u128)>, min_total_out: u128) -> Action {
+        Action::SplitSwap(SplitSwapAction {
+            routes: routes
+                .into_iter()
+                .map(|(pool_id, amount_in)| {
+                    vec![SwapAction {
+                        pool_id,
+                        token_in: accounts(1).into(),
+                        amount_in: Some(U128(amount_in)),
+                        token_out: accounts(2).into(),
+                        min_amount_out: U128(0),
+                    }]
+                })
+                .collect(),
+            token_out: accounts(2).into(),
+            min_total_out: U128(min_total_out),
+        })
+    }
+
+    #[test]
+    fn test_split_swap() {
+        let (mut context, mut contract) = setup_contract();
+        for _ in 0..2 {
+            create_pool_with_liquidity(
+                &mut context,
+                &mut contract,
+                accounts(3),
+                vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+            );
         }
-        testing_env!(context
-            .predecessor_account_id(accounts(4))
-            .attached_deposit(1)
-            .build());
-        contract.remove_liquidity(id, U128(to_yocto("1")), vec![U128(1), U128(1)]);
-        assert_eq!(
-            contract.mft_balance_of(":0".to_string(), accounts(4)).0,
-            to_yocto("0")
+        let acc = ValidAccountId::try_from("test_user").unwrap();
+        deposit_tokens(&mut context, &mut contract, acc.clone(), vec![(accounts(1), to_yocto("2"))]);
+        testing_env!(context.predecessor_account_id(acc.clone()).attached_deposit(1).build());
+        let expected = contract.get_return(0, accounts(1), U128(to_yocto("1")), accounts(2)).0;
+        let result = unwrap_value(contract.execute_actions(
+            vec![split_swap(vec![(0, to_yocto("1")), (1, to_yocto("1"))], 2 * expected)],
+            None,
+            None,
+        ));
+        assert_eq!(result.to_amount(), 2 * expected);
+        assert_eq!(contract.get_deposit(acc.clone(), accounts(1)).0, 0);
+        let deposited = contract.get_deposit(acc.clone(), accounts(2)).0;
+        assert_eq!(deposited, 2 * expected);
+
+        // Split swap through transfer, tokens are sent back directly.
+        let expected = contract.get_return(0, accounts(1), U128(to_yocto("1")), accounts(2)).0
+            + contract.get_return(1, accounts(1), U128(to_yocto("0.5")), accounts(2)).0;
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        let msg = format!(
+            "{{\"actions\": [{{\"routes\": [[{{\"pool_id\": 0, \"token_in\": \"{0}\", \"amount_in\": \"{2}\", \"token_out\": \"{1}\", \"min_amount_out\": \"0\"}}], [{{\"pool_id\": 1, \"token_in\": \"{0}\", \"amount_in\": \"{3}\", \"token_out\": \"{1}\", \"min_amount_out\": \"0\"}}]], \"token_out\": \"{1}\", \"min_total_out\": \"{4}\"}}]}}",
+            accounts(1), accounts(2), to_yocto("1"), to_yocto("0.5"), expected
         );
+        contract.ft_on_transfer(acc.clone(), U128(to_yocto("1.5")), msg);
         assert_eq!(
-            contract.mft_total_supply(":0".to_string()).0,
-            to_yocto("0.4")
+            contract.get_pool(0).amounts[0].0 + contract.get_pool(1).amounts[0].0,
+            to_yocto("13.5")
         );
+        assert_eq!(contract.get_deposit(acc, accounts(2)).0, deposited);
+    }
 
-        // [AUDIT_13]
-        // should panic cause accounts(4) not removed by a full remove liquidity
-        testing_env!(context
-            .predecessor_account_id(accounts(4))
-            .attached_deposit(to_yocto("0.00071"))
-            .build());
-        contract.mft_register(":0".to_string(), accounts(4));
+    #[test]
+    #[should_panic(expected = "E68: slippage error")]
+    fn test_split_swap_total_slippage() {
+        let (mut context, mut contract) = setup_contract();
+        for _ in 0..2 {
+            create_pool_with_liquidity(
+                &mut context,
+                &mut contract,
+                accounts(3),
+                vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+            );
+        }
+        let acc = ValidAccountId::try_from("test_user").unwrap();
+        deposit_tokens(&mut context, &mut contract, acc.clone(), vec![(accounts(1), to_yocto("2"))]);
+        testing_env!(context.predecessor_account_id(acc).attached_deposit(1).build());
+        let expected = contract.get_return(0, accounts(1), U128(to_yocto("1")), accounts(2)).0;
+        contract.execute_actions(
+            vec![split_swap(vec![(0, to_yocto("1")), (1, to_yocto("1"))], 2 * expected + 1)],
+            None,
+            None,
+        );
     }
 
     #[test]
-    #[should_panic(expected = "E33: transfer to self")]
-    fn test_lpt_transfer_self() {
-        // [AUDIT_07]
-        // account(0) -- swap contract
-        // account(1) -- token0 contract
-        // account(2) -- token1 contract
-        // account(3) -- user account
+    fn test_swap_deadline() {
         let (mut context, mut contract) = setup_contract();
-        deposit_tokens(
+        create_pool_with_liquidity(
             &mut context,
             &mut contract,
             accounts(3),
-            vec![
-                (accounts(1), to_yocto("100")),
-                (accounts(2), to_yocto("100")),
-            ],
-        );
-        testing_env!(context
-            .predecessor_account_id(accounts(3))
-            .attached_deposit(to_yocto("1"))
-            .build());
-        let id = contract.add_simple_pool(vec![accounts(1), accounts(2)], 25);
-        testing_env!(context.attached_deposit(to_yocto("0.0007")).build());
-        contract.add_liquidity(id, vec![U128(to_yocto("50")), U128(to_yocto("10"))], None);
-        assert_eq!(
-            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
-            to_yocto("1")
-        );
-        testing_env!(context.attached_deposit(1).build());
-        contract.add_liquidity(id, vec![U128(to_yocto("50")), U128(to_yocto("50"))], None);
-        assert_eq!(
-            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
-            to_yocto("2")
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
         );
-
-        // make transfer to self
+        let acc = ValidAccountId::try_from("test_user").unwrap();
+        deposit_tokens(&mut context, &mut contract, acc.clone(), vec![(accounts(1), to_yocto("2"))]);
         testing_env!(context
-            .predecessor_account_id(accounts(3))
+            .predecessor_account_id(acc.clone())
+            .block_timestamp(100)
+            .block_index(10)
             .attached_deposit(1)
             .build());
-        contract.mft_transfer(":0".to_string(), accounts(3), U128(to_yocto("1")), None);
+        let swap_action = || SwapAction {
+            pool_id: 0,
+            token_in: accounts(1).into(),
+            amount_in: Some(U128(to_yocto("1"))),
+            token_out: accounts(2).into(),
+            min_amount_out: U128(1),
+        };
+        contract.swap(vec![swap_action()], None, Some(Deadline::Timestamp(100.into())));
+        contract.swap(vec![swap_action()], None, Some(Deadline::BlockHeight(10.into())));
+        assert_eq!(contract.get_deposit(acc, accounts(1)).0, 0);
     }
 
     #[test]
-    fn test_storage() {
+    #[should_panic(expected = "E74: deadline of the actions expired")]
+    fn test_swap_deadline_expired() {
         let (mut context, mut contract) = setup_contract();
-        testing_env!(context
-            .predecessor_account_id(accounts(1))
-            .attached_deposit(to_yocto("1"))
-            .build());
-        contract.storage_deposit(Some(accounts(1)), None);
-        testing_env!(context
-            .predecessor_account_id(accounts(1))
-            .attached_deposit(1)
-            .build());
-        assert_eq!(contract.storage_withdraw(None).available.0, 0);
-        testing_env!(context
-            .predecessor_account_id(accounts(1))
-            .attached_deposit(1)
-            .build());
-        assert!(contract.storage_unregister(None));
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(1)).block_index(11).attached_deposit(1).build());
+        contract.ft_on_transfer(
+            accounts(3),
+            U128(to_yocto("1")),
+            format!(
+                r#"{{"actions": [{{"pool_id": 0, "token_in": "{}", "token_out": "{}", "min_amount_out": "1"}}], "deadline": {{"BlockHeight": "10"}}}}"#,
+                accounts(1),
+                accounts(2)
+            ),
+        );
     }
 
     #[test]
-    fn test_storage_registration_only() {
+    fn test_liquidity_actions() {
         let (mut context, mut contract) = setup_contract();
-        testing_env!(context
-            .predecessor_account_id(accounts(1))
-            .attached_deposit(to_yocto("1"))
-            .build());
-        let deposit1 = contract.storage_deposit(Some(accounts(1)), Some(true));
-        testing_env!(context
-            .predecessor_account_id(accounts(1))
-            .attached_deposit(to_yocto("1"))
-            .build());
-        let deposit2 = contract.storage_deposit(Some(accounts(1)), Some(true));
-        assert_eq!(deposit1.total, deposit2.total);
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        let acc = ValidAccountId::try_from("test_user").unwrap();
+        deposit_tokens(&mut context, &mut contract, acc.clone(), vec![(accounts(1), to_yocto("2"))]);
+        testing_env!(context.predecessor_account_id(acc.clone()).attached_deposit(1).build());
+        let prev_shares = contract.get_pool_shares(0, accounts(3)).0;
+
+        // Swap half, add liquidity and transfer the shares.
+        let actions: Vec<Action> = near_sdk::serde_json::from_str(&format!(
+            r#"[
+                {{"pool_id": 0, "token_in": "{0}", "amount_in": "{2}", "token_out": "{1}", "min_amount_out": "1"}},
+                {{"pool_id": 0, "amounts": ["{2}", null]}},
+                {{"token_id": ":0", "receiver_id": "{3}"}}
+            ]"#,
+            accounts(1),
+            accounts(2),
+            to_yocto("1"),
+            accounts(3)
+        ))
+        .unwrap();
+        assert!(matches!(actions[1], Action::AddLiquidity(_)) && matches!(actions[2], Action::Transfer(_)));
+        let shares = unwrap_value(contract.execute_actions(actions, None, None)).to_amount();
+        assert!(shares > 0);
+        assert_eq!(contract.get_pool_shares(0, accounts(3)).0, prev_shares + shares);
+        assert_eq!(contract.get_pool_shares(0, acc.clone()).0, 0);
+        // Dust of the first token is left after rounding.
+        assert!(contract.get_deposit(acc.clone(), accounts(1)).0 < 10);
+        let left = contract.get_deposit(acc.clone(), accounts(2)).0;
+        assert!(left > 0 && left < to_yocto("1"));
+
+        // Add liquidity, remove it and withdraw.
+        deposit_tokens(&mut context, &mut contract, acc.clone(), vec![(accounts(1), to_yocto("1"))]);
+        testing_env!(context.predecessor_account_id(acc.clone()).attached_deposit(1).build());
+        let actions: Vec<Action> = near_sdk::serde_json::from_str(&format!(
+            r#"[
+                {{"pool_id": 0, "token_in": "{0}", "amount_in": "{2}", "token_out": "{1}", "min_amount_out": "1"}},
+                {{"pool_id": 0, "amounts": ["{2}", null], "min_amounts": ["1", "1"]}},
+                {{"pool_id": 0, "min_amounts": ["1", "1"]}},
+                {{"token_id": "{0}", "amount": "{3}"}}
+            ]"#,
+            accounts(1),
+            accounts(2),
+            to_yocto("0.5"),
+            to_yocto("0.1"),
+        ))
+        .unwrap();
+        assert!(matches!(actions[2], Action::RemoveLiquidity(_)) && matches!(actions[3], Action::Withdraw(_)));
+        match unwrap_value(contract.execute_actions(actions, None, None)) {
+            ActionResult::Amount(amount) => assert_eq!(amount.0, to_yocto("0.1")),
+            _ => panic!("wrong result"),
+        }
+        assert_eq!(contract.get_pool_shares(0, acc.clone()).0, 0);
+        let left_after = contract.get_deposit(acc.clone(), accounts(1)).0;
+        assert!(left_after > to_yocto("0.3") && left_after < to_yocto("0.5"));
+        assert!(contract.get_deposit(acc, accounts(2)).0 > left);
     }
 
     #[test]
-    #[should_panic(expected = "E17: deposit less than min storage")]
-    fn test_storage_deposit_less_then_min_storage() {
+    fn test_remove_liquidity_into_add_liquidity() {
         let (mut context, mut contract) = setup_contract();
-        testing_env!(context
-            .predecessor_account_id(accounts(1))
-            .attached_deposit(1)
-            .build());
-        contract.storage_deposit(Some(accounts(1)), Some(true));
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        let prev_shares = contract.get_pool_shares(0, accounts(3)).0;
+        let prev_deposits = contract.get_deposits(accounts(3));
+
+        // Amounts removed from the pool are added back by position.
+        let actions: Vec<Action> = near_sdk::serde_json::from_str(&format!(
+            r#"[
+                {{"pool_id": 0, "shares": "{}", "min_amounts": ["1", "1"]}},
+                {{"pool_id": 0, "amounts": [null, null]}}
+            ]"#,
+            prev_shares / 2
+        ))
+        .unwrap();
+        assert!(matches!(actions[0], Action::RemoveLiquidity(_)) && matches!(actions[1], Action::AddLiquidity(_)));
+        let shares = unwrap_value(contract.execute_actions(actions, None, None)).to_amount();
+        assert!(shares > 0 && shares <= prev_shares / 2);
+        assert_eq!(contract.get_pool_shares(0, accounts(3)).0, prev_shares / 2 + shares);
+        assert_eq!(contract.get_deposits(accounts(3)), prev_deposits);
     }
 
     #[test]
-    fn test_instant_swap() {
+    #[should_panic(expected = "E79: action is only allowed on the deposits of an account")]
+    fn test_liquidity_action_in_instant_swap() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        contract.ft_on_transfer(
+            accounts(3),
+            U128(to_yocto("1")),
+            r#"{"actions": [{"pool_id": 0, "amounts": [null, "0"]}]}"#.to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E80: operation is not supported by this pool kind")]
+    fn test_liquidity_action_wrong_pool_kind() {
         let (mut context, mut contract) = setup_contract();
-        // add liquidity of (1,2) tokens
         create_pool_with_liquidity(
             &mut context,
             &mut contract,
@@ -1196,305 +2319,1850 @@ mod tests {
             &mut context,
             &mut contract,
             accounts(3),
-            vec![
-                (accounts(1), to_yocto("100")),
-                (accounts(2), to_yocto("100")),
-            ],
+            vec![(accounts(1), to_yocto("1")), (accounts(2), to_yocto("1"))],
         );
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        let actions: Vec<Action> = near_sdk::serde_json::from_str(
+            r#"[{"pool_id": 0, "amounts": ["1000", "1000"], "min_shares": "1"}]"#,
+        )
+        .unwrap();
+        assert!(matches!(actions[0], Action::AddStableLiquidity(_)));
+        contract.execute_actions(actions, None, None);
+    }
 
-        assert_eq!(
-            contract.get_deposit(accounts(3), accounts(1)),
-            to_yocto("100").into()
-        );
-        assert_eq!(
-            contract.get_deposit(accounts(3), accounts(2)),
-            to_yocto("100").into()
-        );
-        assert_eq!(
-            contract.get_pool_total_shares(0).0,
-            crate::utils::INIT_SHARES_SUPPLY
+    #[test]
+    #[should_panic(expected = "E80: operation is not supported by this pool kind")]
+    fn test_remove_liquidity_one_coin_simple() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
         );
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        contract.remove_liquidity_one_coin(0, U128(to_yocto("1")), accounts(1), U128(1));
+    }
 
-        // Get price from pool :0 1 -> 2 tokens.
-        let expected_out = contract.get_return(0, accounts(1), to_yocto("1").into(), accounts(2));
-        assert_eq!(expected_out.0, 1663192997082117548978741);
-
-        let actions_str = format!(
-            "{{\"pool_id\": {}, \"token_in\": \"{}\", \"token_out\": \"{}\", \"min_amount_out\": \"{}\"}}",
-            0, accounts(1), accounts(2), 1
+    #[test]
+    #[should_panic(expected = "E80: operation is not supported by this pool kind")]
+    fn test_predict_remove_liquidity_one_coin_simple() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
         );
-
-        let msg_str = format!("{{\"actions\": [{}]}}", actions_str);
-        testing_env!(context
-            .predecessor_account_id(accounts(1))
-            .attached_deposit(1)
-            .build());
-        contract.ft_on_transfer(accounts(3), to_yocto("1").into(), msg_str);
+        contract.predict_remove_liquidity_one_coin(0, U128(to_yocto("1")), accounts(1));
     }
 
     #[test]
-    fn test_mft_transfer_call() {
-        let one_near = 10u128.pow(24);
+    fn test_zap_liquidity() {
         let (mut context, mut contract) = setup_contract();
-        // add liquidity of (1,2) tokens
         create_pool_with_liquidity(
             &mut context,
             &mut contract,
             accounts(3),
             vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
         );
-        println!("{:?}", contract.get_pools(0, 100));
-        println!("{:?}", contract.get_pool(0));
+        let acc = ValidAccountId::try_from("test_user").unwrap();
         deposit_tokens(
             &mut context,
             &mut contract,
-            accounts(3),
-            vec![
-                (accounts(1), to_yocto("100")),
-                (accounts(2), to_yocto("100")),
-            ],
-        );
-        deposit_tokens(&mut context, &mut contract, accounts(1), vec![]);
-
-        assert_eq!(
-            contract.get_deposit(accounts(3), accounts(1)),
-            to_yocto("100").into()
-        );
-        assert_eq!(
-            contract.get_deposit(accounts(3), accounts(2)),
-            to_yocto("100").into()
-        );
-        assert_eq!(
-            contract.get_pool_total_shares(0).0,
-            crate::utils::INIT_SHARES_SUPPLY
+            acc.clone(),
+            vec![(accounts(1), to_yocto("1")), (accounts(2), 0)],
         );
+        testing_env!(context
+            .predecessor_account_id(acc.clone())
+            .attached_deposit(to_yocto("0.01"))
+            .build());
+        let shares = contract.zap_liquidity(0, accounts(1), U128(to_yocto("1")), U128(1)).0;
+        assert_eq!(contract.get_pool_shares(0, acc.clone()).0, shares);
+        // Only dust is left in deposits.
+        assert!(contract.get_deposit(acc.clone(), accounts(1)).0 < 10_000);
+        assert!(contract.get_deposit(acc.clone(), accounts(2)).0 < 10_000);
 
-        // Get price from pool :0 1 -> 2 tokens.
-        let expected_out = contract.get_return(0, accounts(1), one_near.into(), accounts(2));
-        assert_eq!(expected_out.0, 1663192997082117548978741);
-
+        // Zap through transfer of the other token, shares storage is already paid.
         testing_env!(context
-            .predecessor_account_id(accounts(3))
+            .predecessor_account_id(accounts(2))
             .attached_deposit(1)
             .build());
-        let amount_out = swap(&mut contract, 0, accounts(1), one_near, accounts(2));
-        assert_eq!(amount_out, expected_out.0);
-        assert_eq!(
-            contract.get_deposit(accounts(3), accounts(1)).0,
-            99 * one_near
-        );
-        assert_eq!("ref-pool-0".to_string(), contract.mft_metadata(":0".to_string()).name);
-        // transfer some of token_id 2 from acc 3 to acc 1.
-        testing_env!(context.predecessor_account_id(accounts(3)).build());
-        contract.mft_transfer_call(accounts(2).to_string(), accounts(1), U128(one_near), Some("mft".to_string()), "".to_string());
-        assert_eq!(
-            contract.get_deposit(accounts(3), accounts(2)).0,
-            99 * one_near + amount_out
+        contract.ft_on_transfer(
+            acc.clone(),
+            U128(to_yocto("1")),
+            format!("{{\"pool_id\": 0, \"min_shares\": \"{}\"}}", shares / 3),
         );
+        assert!(contract.get_pool_shares(0, acc.clone()).0 > shares + shares / 3);
+        assert!(contract.get_deposit(acc.clone(), accounts(2)).0 < 20_000);
     }
 
     #[test]
-    fn test_stable() {
+    fn test_simple_pool_predictions() {
         let (mut context, mut contract) = setup_contract();
-        let token_amounts = vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("5"))];
-        let tokens = token_amounts
-            .iter()
-            .map(|(x, _)| x.clone())
-            .collect::<Vec<_>>();
-        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
-        contract.extend_whitelisted_tokens(tokens.clone());
-        assert_eq!(contract.get_whitelisted_tokens(), vec![accounts(1).to_string(), accounts(2).to_string()]);
-        assert_eq!(0, contract.get_user_whitelisted_tokens(accounts(3)).len());
-        testing_env!(context
-            .predecessor_account_id(accounts(0))
-            .attached_deposit(env::storage_byte_cost() * 334)
-            .build());
-        let pool_id = contract.add_stable_swap_pool(tokens, vec![18, 18], 25, 240);
-        println!("{:?}", contract.version());
-        println!("{:?}", contract.get_stable_pool(pool_id));
-        println!("{:?}", contract.get_pools(0, 100));
-        println!("{:?}", contract.get_pool(0));
-        assert_eq!(1, contract.get_number_of_pools());
-        assert_eq!(25, contract.get_pool_fee(pool_id));
-        testing_env!(context
-            .predecessor_account_id(accounts(3))
-            .attached_deposit(to_yocto("0.03"))
-            .build());
-        contract.storage_deposit(None, None);
-        assert_eq!(to_yocto("0.03"), contract.get_user_storage_state(accounts(3)).unwrap().deposit.0);
-        deposit_tokens(&mut context, &mut contract, accounts(3), token_amounts.clone());
-        deposit_tokens(&mut context, &mut contract, accounts(0), vec![]);
-
-        let predict = contract.predict_add_stable_liquidity(pool_id, &vec![to_yocto("4").into(), to_yocto("4").into()]);
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        assert_eq!(contract.get_pool_share_price(pool_id).0, 10 * 100000000);
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("1")), (accounts(2), to_yocto("2"))],
+        );
+        let predict = contract.predict_add_stable_liquidity(pool_id, &vec![to_yocto("1").into(), to_yocto("2").into()]);
         testing_env!(context
             .predecessor_account_id(accounts(3))
             .attached_deposit(to_yocto("0.0007"))
             .build());
-        let add_liq = contract.add_stable_liquidity(
-            pool_id,
-            vec![to_yocto("4").into(), to_yocto("4").into()],
-            U128(1),
-        );
-        assert_eq!(predict.0, add_liq.0);
-        assert_eq!(100000000, contract.get_pool_share_price(pool_id).0);
-        assert_eq!(8000000000000000000000000, contract.get_pool_shares(pool_id, accounts(3)).0);
-        assert_eq!(8000000000000000000000000, contract.get_pool_total_shares(pool_id).0);
-        
-        let expected_out = contract.get_return(0, accounts(1), to_yocto("1").into(), accounts(2));
-        assert_eq!(expected_out.0, 996947470156575219215719);
+        let shares = contract.add_liquidity(pool_id, vec![to_yocto("1").into(), to_yocto("2").into()], None);
+        assert_eq!(predict, shares);
+
+        let predict = contract.predict_remove_liquidity(pool_id, shares);
+        testing_env!(context.attached_deposit(1).build());
+        let amounts = contract.remove_liquidity(pool_id, shares, vec![1.into(), 1.into()]);
+        assert_eq!(predict, amounts);
+        assert_eq!(amounts, vec![U128(to_yocto("1")), U128(to_yocto("2"))]);
+    }
 
+    #[test]
+    fn test_modify_pool_fee() {
+        let (mut context, mut contract) = setup_contract();
+        let sec = 1_000_000_000;
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        assert_eq!(contract.get_pool_fee(pool_id), 25);
         testing_env!(context
-            .predecessor_account_id(accounts(3))
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(100 * sec)
             .attached_deposit(1)
             .build());
-        let amount_out = swap(&mut contract, 0, accounts(1), to_yocto("1").into(), accounts(2));
-        assert_eq!(amount_out, expected_out.0);
-        assert_eq!(
-            contract.get_deposit(accounts(3), accounts(1)).0,
-            0
-        );
-        assert_eq!(0, contract.get_deposits(accounts(3)).get(&accounts(1).to_string()).unwrap().0);
-        assert_eq!(to_yocto("1") + 996947470156575219215719, contract.get_deposits(accounts(3)).get(&accounts(2).to_string()).unwrap().0);
+        contract.modify_pool_fee(pool_id, 50, None);
+        assert_eq!(contract.get_pool_fee(pool_id), 50);
 
-        let predict = contract.predict_remove_liquidity(pool_id, to_yocto("0.1").into());
+        contract.modify_pool_fee(pool_id, 150, Some((200 * sec).into()));
+        assert_eq!(contract.get_pool_fee(pool_id), 50);
+        testing_env!(context.block_timestamp(150 * sec).build());
+        assert_eq!(contract.get_pool_fee(pool_id), 100);
+        let expected = contract.get_return(pool_id, accounts(1), U128(to_yocto("1")), accounts(2)).0;
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), to_yocto("1"))]);
         testing_env!(context
             .predecessor_account_id(accounts(3))
             .attached_deposit(1)
             .build());
-        let remove_liq = contract.remove_liquidity(
-            pool_id,
-            to_yocto("0.1").into(),
-            vec![1.into(), 1.into()],
-        );
-        assert_eq!(predict, remove_liq);
+        assert_eq!(swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2)), expected);
+        testing_env!(context.block_timestamp(300 * sec).build());
+        assert_eq!(contract.get_pool_fee(pool_id), 150);
 
-        let predict = contract.predict_remove_liquidity_by_tokens(pool_id, &vec![to_yocto("0.1").into(), to_yocto("0.1").into()]);
+        // Ramping down from the current fee.
         testing_env!(context
-            .predecessor_account_id(accounts(3))
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(400 * sec)
             .attached_deposit(1)
             .build());
-        let remove_liq_by_token = contract.remove_liquidity_by_tokens(
-            pool_id,
-            vec![to_yocto("0.1").into(), to_yocto("0.1").into()],
-            to_yocto("1").into(),
-        );
-        assert_eq!(predict.0, remove_liq_by_token.0);
+        contract.modify_pool_fee(pool_id, 30, Some((500 * sec).into()));
+        testing_env!(context.block_timestamp(475 * sec).build());
+        assert_eq!(contract.get_pool_fee(pool_id), 60);
+    }
 
+    #[test]
+    #[should_panic(expected = "E62: illegal fee")]
+    fn test_modify_pool_fee_too_large() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
         testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
-        contract.remove_exchange_fee_liquidity(0, to_yocto("0.0001").into(), vec![1.into(), 1.into()]);
-        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
-        contract.withdraw_owner_token(accounts(1), to_yocto("0.00001").into());
-        testing_env!(context.predecessor_account_id(accounts(0)).block_timestamp(2*86400 * 1_000_000_000).attached_deposit(1).build());
-        contract.stable_swap_ramp_amp(0,250, (3*86400 * 1_000_000_000).into());
-        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
-        contract.stable_swap_stop_ramp_amp(0);
+        contract.modify_pool_fee(pool_id, utils::FEE_DIVISOR, None);
     }
 
-
     #[test]
-    fn test_rated() {
+    fn test_pool_state() {
         let (mut context, mut contract) = setup_contract();
-        let token_amounts = vec![(accounts(1), to_yocto("3")), (accounts(2), to_yocto("5"))];
-        let tokens = token_amounts
-            .iter()
-            .map(|(x, _)| x.clone())
-            .collect::<Vec<_>>();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
         testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
-        contract.extend_whitelisted_tokens(tokens.clone());
-        assert_eq!(contract.get_whitelisted_tokens(), vec![accounts(1).to_string(), accounts(2).to_string()]);
-        assert_eq!(0, contract.get_user_whitelisted_tokens(accounts(3)).len());
+        contract.extend_guardians(vec![accounts(4)]);
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.change_pool_state(pool_id, PoolState::SwapsPaused);
+        assert_eq!(contract.get_pool_state(pool_id), PoolState::SwapsPaused);
+        assert_eq!(contract.get_pool_state(1), PoolState::Running);
+        assert_eq!(
+            contract.get_paused_pools(),
+            vec![(pool_id, PoolState::SwapsPaused)].into_iter().collect()
+        );
+
+        // Liquidity can still be added and removed.
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("1")), (accounts(2), to_yocto("2"))],
+        );
         testing_env!(context
-            .predecessor_account_id(accounts(0))
-            .attached_deposit(env::storage_byte_cost() * 389) // required storage depends on contract_id length
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.0007"))
             .build());
-        let pool_id = contract.add_rated_swap_pool(tokens, vec![18, 18], 25, 240, "STNEAR".to_owned(), ValidAccountId::try_from("remote").unwrap());
-        println!("{:?}", contract.version());
-        println!("{:?}", contract.get_rated_pool(pool_id));
-        println!("{:?}", contract.get_pools(0, 100));
-        println!("{:?}", contract.get_pool(0));
-        assert_eq!(1, contract.get_number_of_pools());
-        assert_eq!(25, contract.get_pool_fee(pool_id));
+        let shares = contract.add_liquidity(pool_id, vec![to_yocto("1").into(), to_yocto("2").into()], None);
+
+        testing_env!(context.predecessor_account_id(accounts(4)).attached_deposit(1).build());
+        contract.change_pool_state(pool_id, PoolState::WithdrawOnly);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.remove_liquidity(pool_id, shares, vec![1.into(), 1.into()]);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.change_pool_state(pool_id, PoolState::Running);
+        assert!(contract.get_paused_pools().is_empty());
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), to_yocto("1"))]);
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "E52: pool swaps paused")]
+    fn test_pool_state_swaps_paused() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.change_pool_state(pool_id, PoolState::SwapsPaused);
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), to_yocto("1"))]);
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "E54: pool frozen")]
+    fn test_pool_state_frozen() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.change_pool_state(pool_id, PoolState::Frozen);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.remove_liquidity(pool_id, U128(1000), vec![1.into(), 1.into()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E100: no permission to invoke this")]
+    fn test_pool_state_resume_by_guardian() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.extend_guardians(vec![accounts(4)]);
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.change_pool_state(pool_id, PoolState::WithdrawOnly);
+        contract.change_pool_state(pool_id, PoolState::Running);
+    }
+
+    #[test]
+    fn test_pool_observations_storage() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), to_yocto("5"))]);
+        let storage = env::storage_usage();
+        // Swaps don't charge storage, so recording observations shouldn't take any.
+        for i in 1..=MAX_NUM_OBSERVATIONS as u64 + 1 {
+            testing_env!(context
+                .predecessor_account_id(accounts(3))
+                .block_timestamp(i * OBSERVATION_PERIOD)
+                .attached_deposit(1)
+                .build());
+            swap(&mut contract, 0, accounts(1), to_yocto("0.1"), accounts(2));
+        }
+        assert_eq!(env::storage_usage(), storage);
+    }
+
+    #[test]
+    fn test_allocate_pool_observations() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        // Pool created before observations were tracked.
+        contract.pool_observations.remove(&0);
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), to_yocto("5"))]);
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        swap(&mut contract, 0, accounts(1), to_yocto("1"), accounts(2));
+        assert!(contract.pool_observations.get(&0).is_none());
+
+        testing_env!(context.attached_deposit(to_yocto("0.03")).build());
+        contract.allocate_pool_observations(0);
+        testing_env!(context.block_timestamp(OBSERVATION_PERIOD).attached_deposit(1).build());
+        swap(&mut contract, 0, accounts(1), to_yocto("1"), accounts(2));
+        testing_env!(context.block_timestamp(2 * OBSERVATION_PERIOD).build());
+        assert_eq!(contract.get_pool_twap(0, OBSERVATION_PERIOD.into()).len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "E152: price observations of the pool are already allocated")]
+    fn test_allocate_pool_observations_twice() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.attached_deposit(to_yocto("0.03")).build());
+        contract.allocate_pool_observations(0);
+    }
+
+    #[test]
+    fn test_pool_twap() {
+        let (mut context, mut contract) = setup_contract();
+        let sec = 1_000_000_000;
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), to_yocto("5"))]);
+        let precision = U256::from(PRICE_PRECISION);
+        let initial_price = precision / 2;
+
         testing_env!(context
             .predecessor_account_id(accounts(3))
-            .attached_deposit(to_yocto("0.03"))
+            .block_timestamp(600 * sec)
+            .attached_deposit(1)
             .build());
-        contract.storage_deposit(None, None);
-        assert_eq!(to_yocto("0.03"), contract.get_user_storage_state(accounts(3)).unwrap().deposit.0);
-        deposit_tokens(&mut context, &mut contract, accounts(3), token_amounts.clone());
-        deposit_tokens(&mut context, &mut contract, accounts(0), vec![]);
+        swap(&mut contract, 0, accounts(1), to_yocto("5"), accounts(2));
+        let amounts = contract.get_pool(0).amounts;
+        let price = U256::from(amounts[0].0) * precision / U256::from(amounts[1].0);
 
-        // set token1/token2 rate = 2.0
-        let mut pool = contract.pools.get(pool_id).expect(ERR85_NO_POOL);
-        let cross_call_result = near_sdk::serde_json::to_vec(&U128(2_000000000000000000000000)).unwrap();
-        pool.update_callback(&cross_call_result);
-        contract.pools.replace(pool_id, &pool);
+        testing_env!(context.block_timestamp(1200 * sec).build());
+        let twap = contract.get_pool_twap(0, (1200 * sec).into());
+        assert_eq!(twap[0], precision.to_string());
+        assert_eq!(twap[1], ((initial_price + price) / 2).to_string());
+        // Only the price after the swap is held over the last 5 minutes.
+        let twap = contract.get_pool_twap(0, (300 * sec).into());
+        let twap_price = U256::from_dec_str(&twap[1]).unwrap();
+        assert!(twap_price <= price && price - twap_price <= U256::one());
 
-        let pool_info = contract.get_rated_pool(pool_id);
-        assert_eq!(pool_info.rates, vec![U128(2_000000000000000000000000), U128(1_000000000000000000000000)]);
+        let cumulatives = contract.get_pool_price_cumulatives(0);
+        assert_eq!(cumulatives.timestamp.0, 1200 * sec);
+        assert_eq!(
+            cumulatives.price_cumulatives[1],
+            (initial_price * U256::from(600 * sec) + price * U256::from(600 * sec)).to_string()
+        );
+    }
 
-        let predict = contract.predict_add_rated_liquidity(pool_id, &vec![to_yocto("2").into(), to_yocto("4").into()], &Some(pool_info.rates.clone()));
+    #[test]
+    #[should_panic(expected = "E151: twap window exceeds recorded observations")]
+    fn test_pool_twap_window_too_long() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.block_timestamp(1_000_000_000).build());
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.block_timestamp(3_000_000_000).build());
+        contract.get_pool_twap(0, 2_000_000_001.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "E14: LP already registered")]
+    fn test_lpt_transfer() {
+        // account(0) -- swap contract
+        // account(1) -- token0 contract
+        // account(2) -- token1 contract
+        // account(3) -- user account
+        // account(4) -- another user account
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![
+                (accounts(1), to_yocto("100")),
+                (accounts(2), to_yocto("100")),
+            ],
+        );
         testing_env!(context
             .predecessor_account_id(accounts(3))
-            .attached_deposit(to_yocto("0.0007"))
+            .attached_deposit(to_yocto("1"))
             .build());
-        let add_liq = contract.add_rated_liquidity(
-            pool_id,
-            vec![to_yocto("2").into(), to_yocto("4").into()],
-            U128(1),
+        let id = contract.add_simple_pool(vec![accounts(1), accounts(2)], 25);
+        testing_env!(context.attached_deposit(to_yocto("0.0007")).build());
+        contract.add_liquidity(id, vec![U128(to_yocto("50")), U128(to_yocto("10"))], None);
+        assert_eq!(
+            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
+            to_yocto("1")
         );
-        assert_eq!(predict.0, add_liq.0);
-        assert_eq!(100000000, contract.get_pool_share_price(pool_id).0);
-        assert_eq!(8000000000000000000000000000000, contract.get_pool_shares(pool_id, accounts(3)).0);
-        assert_eq!(8000000000000000000000000000000, contract.get_pool_total_shares(pool_id).0);
-        
-        let expected_out = contract.get_rated_return(0, accounts(1), to_yocto("1").into(), accounts(2), &Some(pool_info.rates.clone()));
-        assert_eq!(expected_out.0, 1992244454139326876254354);
+        assert_eq!(contract.mft_total_supply(":0".to_string()).0, to_yocto("1"));
+        testing_env!(context.attached_deposit(1).build());
+        contract.add_liquidity(id, vec![U128(to_yocto("50")), U128(to_yocto("50"))], None);
+        assert_eq!(
+            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
+            to_yocto("2")
+        );
+        assert_eq!(contract.mft_total_supply(":0".to_string()).0, to_yocto("2"));
 
+        // register another user
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(to_yocto("0.00071"))
+            .build());
+        contract.mft_register(":0".to_string(), accounts(4));
+        // make transfer to him
         testing_env!(context
             .predecessor_account_id(accounts(3))
             .attached_deposit(1)
             .build());
-        let amount_out = swap(&mut contract, 0, accounts(1), to_yocto("1").into(), accounts(2));
-        assert_eq!(amount_out, expected_out.0);
+        contract.mft_transfer(":0".to_string(), accounts(4), U128(to_yocto("1")), None);
         assert_eq!(
-            contract.get_deposit(accounts(3), accounts(1)).0,
-            0
+            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
+            to_yocto("1")
         );
-        assert_eq!(0, contract.get_deposits(accounts(3)).get(&accounts(1).to_string()).unwrap().0);
-        assert_eq!(to_yocto("1") + 1992244454139326876254354, contract.get_deposits(accounts(3)).get(&accounts(2).to_string()).unwrap().0);
-
-        let predict = contract.predict_remove_liquidity(pool_id, to_yocto("0.1").into());
+        assert_eq!(
+            contract.mft_balance_of(":0".to_string(), accounts(4)).0,
+            to_yocto("1")
+        );
+        assert_eq!(contract.mft_total_supply(":0".to_string()).0, to_yocto("2"));
+        // remove lpt for account 3
         testing_env!(context
             .predecessor_account_id(accounts(3))
             .attached_deposit(1)
             .build());
-        let remove_liq = contract.remove_liquidity(
-            pool_id,
-            to_yocto("0.1").into(),
-            vec![1.into(), 1.into()],
+        contract.remove_liquidity(id, U128(to_yocto("0.6")), vec![U128(1), U128(1)]);
+        assert_eq!(
+            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
+            to_yocto("0.4")
         );
-        assert_eq!(predict, remove_liq);
-
-        let predict = contract.predict_remove_rated_liquidity_by_tokens(pool_id, &vec![to_yocto("0.1").into(), to_yocto("0.1").into()], &Some(pool_info.rates));
+        assert_eq!(
+            contract.mft_total_supply(":0".to_string()).0,
+            to_yocto("1.4")
+        );
+        // remove lpt for account 4 who got lpt from others
+        if contract.storage_balance_of(accounts(4)).is_none() {
+            testing_env!(context
+                .predecessor_account_id(accounts(4))
+                .attached_deposit(to_yocto("1"))
+                .build());
+            contract.storage_deposit(None, None);
+        }
         testing_env!(context
-            .predecessor_account_id(accounts(3))
+            .predecessor_account_id(accounts(4))
             .attached_deposit(1)
             .build());
-        let remove_liq_by_token = contract.remove_liquidity_by_tokens(
-            pool_id,
-            vec![to_yocto("0.1").into(), to_yocto("0.1").into()],
-            to_yocto("1000000").into(),
+        contract.remove_liquidity(id, U128(to_yocto("1")), vec![U128(1), U128(1)]);
+        assert_eq!(
+            contract.mft_balance_of(":0".to_string(), accounts(4)).0,
+            to_yocto("0")
         );
-        assert_eq!(predict.0, remove_liq_by_token.0);
+        assert_eq!(
+            contract.mft_total_supply(":0".to_string()).0,
+            to_yocto("0.4")
+        );
+
+        // [AUDIT_13]
+        // should panic cause accounts(4) not removed by a full remove liquidity
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(to_yocto("0.00071"))
+            .build());
+        contract.mft_register(":0".to_string(), accounts(4));
+    }
+
+    #[test]
+    #[should_panic(expected = "E33: transfer to self")]
+    fn test_lpt_transfer_self() {
+        // [AUDIT_07]
+        // account(0) -- swap contract
+        // account(1) -- token0 contract
+        // account(2) -- token1 contract
+        // account(3) -- user account
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![
+                (accounts(1), to_yocto("100")),
+                (accounts(2), to_yocto("100")),
+            ],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let id = contract.add_simple_pool(vec![accounts(1), accounts(2)], 25);
+        testing_env!(context.attached_deposit(to_yocto("0.0007")).build());
+        contract.add_liquidity(id, vec![U128(to_yocto("50")), U128(to_yocto("10"))], None);
+        assert_eq!(
+            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
+            to_yocto("1")
+        );
+        testing_env!(context.attached_deposit(1).build());
+        contract.add_liquidity(id, vec![U128(to_yocto("50")), U128(to_yocto("50"))], None);
+        assert_eq!(
+            contract.mft_balance_of(":0".to_string(), accounts(3)).0,
+            to_yocto("2")
+        );
+
+        // make transfer to self
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.mft_transfer(":0".to_string(), accounts(3), U128(to_yocto("1")), None);
+    }
+
+    #[test]
+    fn test_storage() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        contract.storage_deposit(Some(accounts(1)), None);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.storage_withdraw(None).available.0, 0);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        assert!(contract.storage_unregister(None));
+    }
+
+    #[test]
+    fn test_storage_registration_only() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let deposit1 = contract.storage_deposit(Some(accounts(1)), Some(true));
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let deposit2 = contract.storage_deposit(Some(accounts(1)), Some(true));
+        assert_eq!(deposit1.total, deposit2.total);
+    }
+
+    #[test]
+    #[should_panic(expected = "E17: deposit less than min storage")]
+    fn test_storage_deposit_less_then_min_storage() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.storage_deposit(Some(accounts(1)), Some(true));
+    }
+
+    #[test]
+    fn test_instant_swap() {
+        let (mut context, mut contract) = setup_contract();
+        // add liquidity of (1,2) tokens
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![
+                (accounts(1), to_yocto("100")),
+                (accounts(2), to_yocto("100")),
+            ],
+        );
+
+        assert_eq!(
+            contract.get_deposit(accounts(3), accounts(1)),
+            to_yocto("100").into()
+        );
+        assert_eq!(
+            contract.get_deposit(accounts(3), accounts(2)),
+            to_yocto("100").into()
+        );
+        assert_eq!(
+            contract.get_pool_total_shares(0).0,
+            crate::utils::INIT_SHARES_SUPPLY
+        );
+
+        // Get price from pool :0 1 -> 2 tokens.
+        let expected_out = contract.get_return(0, accounts(1), to_yocto("1").into(), accounts(2));
+        assert_eq!(expected_out.0, 1663192997082117548978741);
+
+        let actions_str = format!(
+            "{{\"pool_id\": {}, \"token_in\": \"{}\", \"token_out\": \"{}\", \"min_amount_out\": \"{}\"}}",
+            0, accounts(1), accounts(2), 1
+        );
+
+        let msg_str = format!("{{\"actions\": [{}]}}", actions_str);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(3), to_yocto("1").into(), msg_str);
+    }
+
+    #[test]
+    fn test_mft_transfer_call() {
+        let one_near = 10u128.pow(24);
+        let (mut context, mut contract) = setup_contract();
+        // add liquidity of (1,2) tokens
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        println!("{:?}", contract.get_pools(0, 100));
+        println!("{:?}", contract.get_pool(0));
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![
+                (accounts(1), to_yocto("100")),
+                (accounts(2), to_yocto("100")),
+            ],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(1), vec![]);
+
+        assert_eq!(
+            contract.get_deposit(accounts(3), accounts(1)),
+            to_yocto("100").into()
+        );
+        assert_eq!(
+            contract.get_deposit(accounts(3), accounts(2)),
+            to_yocto("100").into()
+        );
+        assert_eq!(
+            contract.get_pool_total_shares(0).0,
+            crate::utils::INIT_SHARES_SUPPLY
+        );
+
+        // Get price from pool :0 1 -> 2 tokens.
+        let expected_out = contract.get_return(0, accounts(1), one_near.into(), accounts(2));
+        assert_eq!(expected_out.0, 1663192997082117548978741);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let amount_out = swap(&mut contract, 0, accounts(1), one_near, accounts(2));
+        assert_eq!(amount_out, expected_out.0);
+        assert_eq!(
+            contract.get_deposit(accounts(3), accounts(1)).0,
+            99 * one_near
+        );
+        assert_eq!("ref-pool-0".to_string(), contract.mft_metadata(":0".to_string()).name);
+        // transfer some of token_id 2 from acc 3 to acc 1.
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.mft_transfer_call(accounts(2).to_string(), accounts(1), U128(one_near), Some("mft".to_string()), "".to_string());
+        assert_eq!(
+            contract.get_deposit(accounts(3), accounts(2)).0,
+            99 * one_near + amount_out
+        );
+    }
+
+    #[test]
+    fn test_stable() {
+        let (mut context, mut contract) = setup_contract();
+        let token_amounts = vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("5"))];
+        let tokens = token_amounts
+            .iter()
+            .map(|(x, _)| x.clone())
+            .collect::<Vec<_>>();
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.extend_whitelisted_tokens(tokens.clone());
+        assert_eq!(contract.get_whitelisted_tokens(), vec![accounts(1).to_string(), accounts(2).to_string()]);
+        assert_eq!(0, contract.get_user_whitelisted_tokens(accounts(3)).len());
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 2816)
+            .build());
+        let pool_id = contract.add_stable_swap_pool(tokens, vec![18, 18], 25, 240);
+        println!("{:?}", contract.version());
+        println!("{:?}", contract.get_stable_pool(pool_id));
+        println!("{:?}", contract.get_pools(0, 100));
+        println!("{:?}", contract.get_pool(0));
+        assert_eq!(1, contract.get_number_of_pools());
+        assert_eq!(25, contract.get_pool_fee(pool_id));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.03"))
+            .build());
+        contract.storage_deposit(None, None);
+        assert_eq!(to_yocto("0.03"), contract.get_user_storage_state(accounts(3)).unwrap().deposit.0);
+        deposit_tokens(&mut context, &mut contract, accounts(3), token_amounts.clone());
+        deposit_tokens(&mut context, &mut contract, accounts(0), vec![]);
+
+        let predict = contract.predict_add_stable_liquidity(pool_id, &vec![to_yocto("4").into(), to_yocto("4").into()]);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.0007"))
+            .build());
+        let add_liq = contract.add_stable_liquidity(
+            pool_id,
+            vec![to_yocto("4").into(), to_yocto("4").into()],
+            U128(1),
+        );
+        assert_eq!(predict.0, add_liq.0);
+        assert_eq!(100000000, contract.get_pool_share_price(pool_id).0);
+        assert_eq!(8000000000000000000000000, contract.get_pool_shares(pool_id, accounts(3)).0);
+        assert_eq!(8000000000000000000000000, contract.get_pool_total_shares(pool_id).0);
+        
+        let expected_out = contract.get_return(0, accounts(1), to_yocto("1").into(), accounts(2));
+        assert_eq!(expected_out.0, 996947470156575219215719);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let amount_out = swap(&mut contract, 0, accounts(1), to_yocto("1").into(), accounts(2));
+        assert_eq!(amount_out, expected_out.0);
+        assert_eq!(
+            contract.get_deposit(accounts(3), accounts(1)).0,
+            0
+        );
+        assert_eq!(0, contract.get_deposits(accounts(3)).get(&accounts(1).to_string()).unwrap().0);
+        assert_eq!(to_yocto("1") + 996947470156575219215719, contract.get_deposits(accounts(3)).get(&accounts(2).to_string()).unwrap().0);
+
+        let predict = contract.predict_remove_liquidity(pool_id, to_yocto("0.1").into());
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let remove_liq = contract.remove_liquidity(
+            pool_id,
+            to_yocto("0.1").into(),
+            vec![1.into(), 1.into()],
+        );
+        assert_eq!(predict, remove_liq);
+
+        let predict = contract.predict_remove_liquidity_by_tokens(pool_id, &vec![to_yocto("0.1").into(), to_yocto("0.1").into()]);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let remove_liq_by_token = contract.remove_liquidity_by_tokens(
+            pool_id,
+            vec![to_yocto("0.1").into(), to_yocto("0.1").into()],
+            to_yocto("1").into(),
+        );
+        assert_eq!(predict.0, remove_liq_by_token.0);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.remove_exchange_fee_liquidity(0, to_yocto("0.0001").into(), vec![1.into(), 1.into()]);
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.withdraw_owner_token(accounts(1), to_yocto("0.00001").into());
+        testing_env!(context.predecessor_account_id(accounts(0)).block_timestamp(2*86400 * 1_000_000_000).attached_deposit(1).build());
+        contract.stable_swap_ramp_amp(0,250, (3*86400 * 1_000_000_000).into());
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.stable_swap_stop_ramp_amp(0);
+    }
+
+
+    #[test]
+    fn test_rated() {
+        let (mut context, mut contract) = setup_contract();
+        let token_amounts = vec![(accounts(1), to_yocto("3")), (accounts(2), to_yocto("5"))];
+        let tokens = token_amounts
+            .iter()
+            .map(|(x, _)| x.clone())
+            .collect::<Vec<_>>();
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.extend_whitelisted_tokens(tokens.clone());
+        assert_eq!(contract.get_whitelisted_tokens(), vec![accounts(1).to_string(), accounts(2).to_string()]);
+        assert_eq!(0, contract.get_user_whitelisted_tokens(accounts(3)).len());
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 2871) // required storage depends on contract_id length
+            .build());
+        let pool_id = contract.add_rated_swap_pool(tokens, vec![18, 18], 25, 240, "STNEAR".to_owned(), ValidAccountId::try_from("remote").unwrap(), None);
+        println!("{:?}", contract.version());
+        println!("{:?}", contract.get_rated_pool(pool_id));
+        println!("{:?}", contract.get_pools(0, 100));
+        println!("{:?}", contract.get_pool(0));
+        assert_eq!(1, contract.get_number_of_pools());
+        assert_eq!(25, contract.get_pool_fee(pool_id));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.03"))
+            .build());
+        contract.storage_deposit(None, None);
+        assert_eq!(to_yocto("0.03"), contract.get_user_storage_state(accounts(3)).unwrap().deposit.0);
+        deposit_tokens(&mut context, &mut contract, accounts(3), token_amounts.clone());
+        deposit_tokens(&mut context, &mut contract, accounts(0), vec![]);
+
+        // set token1/token2 rate = 2.0
+        let mut pool = contract.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let cross_call_result = near_sdk::serde_json::to_vec(&U128(2_000000000000000000000000)).unwrap();
+        pool.update_callback(&[cross_call_result]);
+        contract.pools.replace(pool_id, &pool);
+
+        let pool_info = contract.get_rated_pool(pool_id);
+        assert_eq!(pool_info.rates, vec![U128(2_000000000000000000000000), U128(1_000000000000000000000000)]);
+
+        let predict = contract.predict_add_rated_liquidity(pool_id, &vec![to_yocto("2").into(), to_yocto("4").into()], &Some(pool_info.rates.clone()));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.0007"))
+            .build());
+        let add_liq = match contract.add_rated_liquidity(
+            pool_id,
+            vec![to_yocto("2").into(), to_yocto("4").into()],
+            U128(1),
+        ) {
+            PromiseOrValue::Value(shares) => shares,
+            _ => unreachable!(),
+        };
+        assert_eq!(predict.0, add_liq.0);
+        assert_eq!(100000000, contract.get_pool_share_price(pool_id).0);
+        assert_eq!(8000000000000000000000000000000, contract.get_pool_shares(pool_id, accounts(3)).0);
+        assert_eq!(8000000000000000000000000000000, contract.get_pool_total_shares(pool_id).0);
+        
+        let expected_out = contract.get_rated_return(0, accounts(1), to_yocto("1").into(), accounts(2), &Some(pool_info.rates.clone()));
+        assert_eq!(expected_out.0, 1992244454139326876254354);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let amount_out = swap(&mut contract, 0, accounts(1), to_yocto("1").into(), accounts(2));
+        assert_eq!(amount_out, expected_out.0);
+        assert_eq!(
+            contract.get_deposit(accounts(3), accounts(1)).0,
+            0
+        );
+        assert_eq!(0, contract.get_deposits(accounts(3)).get(&accounts(1).to_string()).unwrap().0);
+        assert_eq!(to_yocto("1") + 1992244454139326876254354, contract.get_deposits(accounts(3)).get(&accounts(2).to_string()).unwrap().0);
+
+        let predict = contract.predict_remove_liquidity(pool_id, to_yocto("0.1").into());
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let remove_liq = contract.remove_liquidity(
+            pool_id,
+            to_yocto("0.1").into(),
+            vec![1.into(), 1.into()],
+        );
+        assert_eq!(predict, remove_liq);
+
+        let predict = contract.predict_remove_rated_liquidity_by_tokens(pool_id, &vec![to_yocto("0.1").into(), to_yocto("0.1").into()], &Some(pool_info.rates));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let remove_liq_by_token = contract.remove_liquidity_by_tokens(
+            pool_id,
+            vec![to_yocto("0.1").into(), to_yocto("0.1").into()],
+            to_yocto("1000000").into(),
+        );
+        assert_eq!(predict.0, remove_liq_by_token.0);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.remove_exchange_fee_liquidity(0, to_yocto("100").into(), vec![1.into(), 1.into()]);
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.withdraw_owner_token(accounts(1), to_yocto("0.00001").into());
+        testing_env!(context.predecessor_account_id(accounts(0)).block_timestamp(2*86400 * 1_000_000_000).attached_deposit(1).build());
+        contract.rated_swap_ramp_amp(0,250, (3*86400 * 1_000_000_000).into());
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.rated_swap_stop_ramp_amp(0);
+    }
+
+    fn is_rated_pool_fresh(contract: &Contract, pool_id: u64) -> bool {
+        match contract.internal_get_pool(pool_id) {
+            Pool::RatedSwapPool(pool) => pool.are_rates_fresh(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_rates_freshness() {
+        let (mut context, mut contract) = setup_contract();
+        let sec = 1_000_000_000;
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 2871)
+            .build());
+        let pool_id = contract.add_rated_swap_pool(
+            vec![accounts(1), accounts(2)],
+            vec![18, 18],
+            25,
+            240,
+            "STNEAR".to_owned(),
+            ValidAccountId::try_from("remote").unwrap(),
+            None,
+        );
+        let rate = near_sdk::serde_json::to_vec(&U128(2_000000000000000000000000)).unwrap();
+        testing_env!(
+            context.epoch_height(1).block_timestamp(100 * sec).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(rate)]
+        );
+        assert!(contract.update_pool_rates_callback(pool_id));
+        let pool_info = contract.get_rated_pool(pool_id);
+        assert_eq!(pool_info.rates_updated_at, 1);
+        assert_eq!(pool_info.rates_updated_timestamp.0, 100 * sec);
+        assert_eq!(pool_info.rates_freshness, RatesFreshness::default());
+        assert!(is_rated_pool_fresh(&contract, pool_id));
+
+        // By default rates expire with the epoch.
+        testing_env!(context.epoch_height(2).build());
+        assert!(!is_rated_pool_fresh(&contract, pool_id));
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.set_pool_rates_freshness(
+            pool_id,
+            RatesFreshness {
+                max_age_sec: Some(3600),
+                max_age_epochs: Some(2),
+            },
+        );
+        assert!(is_rated_pool_fresh(&contract, pool_id));
+        testing_env!(context.block_timestamp(3701 * sec).build());
+        assert!(!is_rated_pool_fresh(&contract, pool_id));
+        testing_env!(context.block_timestamp(3700 * sec).epoch_height(4).build());
+        assert!(!is_rated_pool_fresh(&contract, pool_id));
+    }
+
+    #[test]
+    #[should_panic(expected = "E96: rates freshness should limit age of rates")]
+    fn test_rates_freshness_without_limits() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 2871)
+            .build());
+        let pool_id = contract.add_rated_swap_pool(
+            vec![accounts(1), accounts(2)],
+            vec![18, 18],
+            25,
+            240,
+            "STNEAR".to_owned(),
+            ValidAccountId::try_from("remote").unwrap(),
+            None,
+        );
+        testing_env!(context.attached_deposit(1).build());
+        contract.set_pool_rates_freshness(
+            pool_id,
+            RatesFreshness {
+                max_age_sec: None,
+                max_age_epochs: None,
+            },
+        );
+    }
+
+    /// Creates stNEAR pool with rates updated at epoch 0 and liquidity of 10 of each token from accounts(3).
+    fn create_rated_pool_with_liquidity(context: &mut VMContextBuilder, contract: &mut Contract) -> u64 {
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.extend_whitelisted_tokens(vec![accounts(1), accounts(2)]);
+        testing_env!(context.attached_deposit(env::storage_byte_cost() * 2871).build());
+        let pool_id = contract.add_rated_swap_pool(
+            vec![accounts(1), accounts(2)],
+            vec![24, 24],
+            25,
+            240,
+            "STNEAR".to_owned(),
+            ValidAccountId::try_from("remote").unwrap(),
+            None,
+        );
+        deposit_tokens(
+            context,
+            contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("10")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.0007"))
+            .build());
+        contract.add_rated_liquidity(pool_id, vec![to_yocto("10").into(), to_yocto("10").into()], U128(1));
+        pool_id
+    }
+
+    #[test]
+    fn test_meta_pool() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.extend_whitelisted_tokens(vec![accounts(1), accounts(2), accounts(4)]);
+        testing_env!(context.attached_deposit(to_yocto("0.05")).build());
+        let base_pool_id = contract.add_stable_swap_pool(vec![accounts(1), accounts(2)], vec![24, 24], 25, 240);
+        let pool_id = contract.add_meta_pool(accounts(4), 24, base_pool_id, 25, 240);
+        assert_eq!(
+            contract.get_pool(pool_id).token_account_ids,
+            vec![accounts(4).to_string(), ":0".to_string()]
+        );
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("11")), (accounts(2), to_yocto("10")), (accounts(4), to_yocto("11"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(to_yocto("0.0007")).build());
+        let base_shares = contract.add_stable_liquidity(base_pool_id, vec![U128(to_yocto("10")), U128(to_yocto("10"))], U128(1));
+
+        // Shares of the base pool are added to the meta pool from the deposits.
+        testing_env!(context.attached_deposit(1).build());
+        contract.deposit_pool_shares(base_pool_id, base_shares);
+        assert_eq!(contract.get_pool_shares(base_pool_id, accounts(3)).0, 0);
+        assert_eq!(contract.get_deposits(accounts(3)).get(":0").unwrap(), &base_shares);
+        testing_env!(context.attached_deposit(to_yocto("0.0007")).build());
+        let meta_shares = base_shares.0 / 2;
+        contract.add_rated_liquidity(pool_id, vec![U128(to_yocto("10")), U128(meta_shares)], U128(1));
+
+        // Tokens of the base pool are swapped in the meta pool in one action.
+        testing_env!(context.attached_deposit(1).build());
+        let expected_out = contract.get_return(pool_id, accounts(1), U128(to_yocto("1")), accounts(4));
+        let amount_out = swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(4));
+        assert_eq!(amount_out, expected_out.0);
+        assert!(amount_out > to_yocto("0.99") && amount_out < to_yocto("1"));
+        let expected_out = contract.get_return(pool_id, accounts(4), U128(to_yocto("1")), accounts(2));
+        let amount_out = swap(&mut contract, pool_id, accounts(4), to_yocto("1"), accounts(2));
+        assert_eq!(amount_out, expected_out.0);
+        assert!(amount_out > to_yocto("0.99") && amount_out < to_yocto("1"));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, 0);
+        assert_eq!(contract.get_deposit(accounts(3), accounts(2)).0, amount_out);
+
+        // Holder keeps the shares of the meta pool and of the deposits, plus dust from rounding of rated amounts.
+        let held_shares = contract.get_pool(pool_id).amounts[1].0 + base_shares.0 - meta_shares;
+        let holder_shares =
+            contract.internal_get_pool(base_pool_id).share_balances(&META_POOL_SHARES_HOLDER.to_string());
+        assert!(holder_shares >= held_shares && holder_shares - held_shares <= 2);
+        contract.withdraw_pool_shares(base_pool_id, U128(base_shares.0 - meta_shares));
+        assert_eq!(contract.get_pool_shares(base_pool_id, accounts(3)).0, base_shares.0 - meta_shares);
+        assert_eq!(contract.get_deposits(accounts(3)).get(":0").unwrap().0, 0);
+    }
+
+    #[test]
+    fn test_get_best_route() {
+        let (mut context, mut contract) = setup_contract();
+        let pool0 = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("100")), (accounts(2), to_yocto("100"))],
+        );
+        let pool1 = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(2), to_yocto("100")), (accounts(4), to_yocto("100"))],
+        );
+        let pool2 = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(4), to_yocto("5"))],
+        );
+
+        // Direct pool is too shallow, so the route goes through the token in the middle.
+        let route = contract.get_best_route(accounts(1), U128(to_yocto("1")), accounts(4), 2, None, None).unwrap();
+        assert_eq!(
+            route.actions.iter().map(|action| action.pool_id).collect::<Vec<_>>(),
+            vec![pool0, pool1]
+        );
+        assert_eq!(route.actions[1].min_amount_out, route.amount_out);
+        assert!(route.price_impact > 0 && route.price_impact < 300);
+        let direct = contract.get_best_route(accounts(1), U128(to_yocto("1")), accounts(4), 1, None, None).unwrap();
+        assert_eq!(direct.actions[0].pool_id, pool2);
+        assert!(direct.amount_out.0 < route.amount_out.0 && direct.price_impact > route.price_impact);
+        let scanned = contract.get_best_route(accounts(1), U128(to_yocto("1")), accounts(4), 3, Some(1), Some(1));
+        assert!(scanned.is_none());
+        let scanned = contract.get_best_route(accounts(1), U128(to_yocto("1")), accounts(4), 3, Some(u64::MAX), None);
+        assert!(scanned.is_none());
+
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), to_yocto("1"))]);
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        assert_eq!(unwrap_value(contract.swap(route.actions, None, None)), route.amount_out);
+    }
+
+    #[test]
+    #[should_panic(expected = "E170: max hops should be from 1 to 3")]
+    fn test_get_best_route_too_many_hops() {
+        let (_, contract) = setup_contract();
+        contract.get_best_route(accounts(1), U128(1), accounts(2), 4, None, None);
+    }
+
+    #[test]
+    fn test_remove_liquidity_one_coin() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_rated_pool_with_liquidity(&mut context, &mut contract);
+        let shares = contract.get_pool_shares(pool_id, accounts(3)).0;
+        let predicted = contract.predict_remove_liquidity_one_coin(pool_id, U128(shares / 4), accounts(2));
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        let amount = contract.remove_liquidity_one_coin(pool_id, U128(shares / 4), accounts(2), predicted);
+        assert_eq!(amount, predicted);
+        assert!(amount.0 < to_yocto("5") && amount.0 > to_yocto("4.9"));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(2)), amount);
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, 0);
+        assert_eq!(contract.get_pool_shares(pool_id, accounts(3)).0, shares - shares / 4);
+    }
+
+    #[test]
+    fn test_instant_swap_with_stale_rates() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_rated_pool_with_liquidity(&mut context, &mut contract);
+        testing_env!(context.predecessor_account_id(accounts(1)).epoch_height(1).build());
+        assert!(!is_rated_pool_fresh(&contract, pool_id));
+        let msg = format!(
+            "{{\"actions\": [{{\"pool_id\": {}, \"token_in\": \"{}\", \"amount_in\": \"{}\", \"token_out\": \"{}\", \"min_amount_out\": \"1\"}}]}}",
+            pool_id, accounts(1), to_yocto("1"), accounts(2)
+        );
+        match contract.ft_on_transfer(accounts(4), U128(to_yocto("1")), msg) {
+            PromiseOrValue::Promise(_) => {}
+            _ => panic!("rates should be fetched first"),
+        }
+        let actions = vec![Action::Swap(SwapAction {
+            pool_id,
+            token_in: accounts(1).into(),
+            amount_in: Some(U128(to_yocto("1"))),
+            token_out: accounts(2).into(),
+            min_amount_out: U128(1),
+        })];
 
+        // All the tokens are refunded if the rates can't be fetched.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        let unused = contract.callback_instant_swap_with_rates(
+            accounts(4).into(),
+            accounts(1).into(),
+            U128(to_yocto("1")),
+            None,
+            actions,
+            None,
+            None,
+            vec![(pool_id, 1)],
+        );
+        assert_eq!(unused.0, to_yocto("1"));
+        assert!(!is_rated_pool_fresh(&contract, pool_id));
+        assert_eq!(contract.get_rated_pool(pool_id).amounts[0].0, to_yocto("10"));
+
+        let rate = near_sdk::serde_json::to_vec(&U128(1_100000000000000000000000)).unwrap();
+        testing_env!(
+            context.block_index(1).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(rate)]
+        );
+        // Swap after the deadline is refunded, the fetched rates are kept.
+        let expired_actions = vec![Action::Swap(SwapAction {
+            pool_id,
+            token_in: accounts(1).into(),
+            amount_in: Some(U128(to_yocto("1"))),
+            token_out: accounts(2).into(),
+            min_amount_out: U128(1),
+        })];
+        let unused = contract.callback_instant_swap_with_rates(
+            accounts(4).into(),
+            accounts(1).into(),
+            U128(to_yocto("1")),
+            None,
+            expired_actions,
+            Some(Deadline::BlockHeight(0.into())),
+            None,
+            vec![(pool_id, 1)],
+        );
+        assert_eq!(unused.0, to_yocto("1"));
+        assert!(is_rated_pool_fresh(&contract, pool_id));
+        assert_eq!(contract.get_rated_pool(pool_id).amounts[0].0, to_yocto("10"));
+        let actions = vec![Action::Swap(SwapAction {
+            pool_id,
+            token_in: accounts(1).into(),
+            amount_in: Some(U128(to_yocto("1"))),
+            token_out: accounts(2).into(),
+            min_amount_out: U128(1),
+        })];
+        let unused = contract.callback_instant_swap_with_rates(
+            accounts(4).into(),
+            accounts(1).into(),
+            U128(to_yocto("1")),
+            None,
+            actions,
+            None,
+            None,
+            vec![(pool_id, 1)],
+        );
+        assert_eq!(unused.0, 0);
+        assert!(is_rated_pool_fresh(&contract, pool_id));
+        let pool_info = contract.get_rated_pool(pool_id);
+        assert_eq!(pool_info.rates[0].0, 1_100000000000000000000000);
+        assert_eq!(pool_info.amounts[0].0, to_yocto("11"));
+    }
+
+    #[test]
+    fn test_instant_swap_refund_on_failure() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        let msg = format!(
+            "{{\"actions\": [{{\"pool_id\": 0, \"token_in\": \"{}\", \"token_out\": \"{}\", \"min_amount_out\": \"{}\"}}], \"failure_policy\": \"RefundOrDeposit\"}}",
+            accounts(1), accounts(2), to_yocto("5")
+        );
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        match contract.ft_on_transfer(accounts(4), U128(to_yocto("1")), msg) {
+            PromiseOrValue::Promise(_) => {}
+            _ => panic!("refundable swap should run in the callback"),
+        }
+        assert_eq!(contract.get_pool(0).amounts[0].0, to_yocto("5"));
+
+        // The swap failed on slippage, all of the transfer is refunded.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        let unused = contract.callback_refund_failed_swap(
+            accounts(4).into(),
+            accounts(1).into(),
+            U128(to_yocto("1")),
+        );
+        assert_eq!(unused.0, to_yocto("1"));
+
+        // Otherwise the unused amount returned by the swap is refunded.
+        let result = near_sdk::serde_json::to_vec(&U128(0)).unwrap();
+        testing_env!(
+            context.build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(result)]
+        );
+        let unused = contract.callback_refund_failed_swap(
+            accounts(4).into(),
+            accounts(1).into(),
+            U128(to_yocto("1")),
+        );
+        assert_eq!(unused.0, 0);
+    }
+
+    #[test]
+    fn test_failed_send_deposit_or_unclaimed() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), to_yocto("1"))]);
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        // Registered account gets the tokens deposited.
+        contract.exchange_callback_post_send(accounts(1).into(), accounts(3).into(), U128(to_yocto("2")));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, to_yocto("3"));
+        assert!(contract.get_unclaimed_tokens(accounts(3)).is_empty());
+
+        // Account without storage for the token can claim them later.
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(Account::min_storage_usage())
+            .build());
+        contract.storage_deposit(None, Some(true));
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.exchange_callback_post_send(accounts(1).into(), accounts(4).into(), U128(to_yocto("2")));
+        contract.exchange_callback_post_send(accounts(1).into(), accounts(4).into(), U128(to_yocto("1")));
+        let unclaimed = contract.get_unclaimed_tokens(accounts(4));
+        assert_eq!(unclaimed.get(accounts(1).as_ref()).unwrap().0, to_yocto("3"));
+
+        // Unregistered account gets them kept unclaimed as well.
+        contract.exchange_callback_post_send(accounts(1).into(), accounts(5).into(), U128(to_yocto("1")));
+        let unclaimed = contract.get_unclaimed_tokens(accounts(5));
+        assert_eq!(unclaimed.get(accounts(1).as_ref()).unwrap().0, to_yocto("1"));
+
+        // Claiming pays back the storage of the unclaimed tokens.
+        testing_env!(context.predecessor_account_id(accounts(4)).attached_deposit(to_yocto("0.01")).build());
+        contract.claim_tokens(accounts(1));
+        assert!(contract.get_unclaimed_tokens(accounts(4)).is_empty());
+        testing_env!(context.predecessor_account_id(accounts(5)).attached_deposit(to_yocto("0.01")).build());
+        contract.claim_tokens(accounts(1));
+        assert!(contract.get_unclaimed_tokens(accounts(5)).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "E11: insufficient $NEAR storage deposit")]
+    fn test_claim_tokens_without_storage_fee() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.exchange_callback_post_send(accounts(1).into(), accounts(5).into(), U128(to_yocto("1")));
+        testing_env!(context.predecessor_account_id(accounts(5)).attached_deposit(1).build());
+        contract.claim_tokens(accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "E30: no unclaimed tokens")]
+    fn test_claim_tokens_nothing_to_claim() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(4)).attached_deposit(1).build());
+        contract.claim_tokens(accounts(1));
+    }
+
+    #[test]
+    fn test_deposit_to_account() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![]);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let msg = format!("{{\"deposit_to\": \"{}\"}}", accounts(4));
+        contract.ft_on_transfer(accounts(5), U128(to_yocto("1")), msg);
+        assert_eq!(contract.get_deposit(accounts(4), accounts(1)).0, to_yocto("1"));
+    }
+
+    #[test]
+    fn test_add_transferred_liquidity() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![]);
+        let msg = "{\"add_liquidity_to\": 0, \"min_shares\": \"1\"}";
+
+        // The first token is kept pending until the second one arrives.
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.ft_on_transfer(accounts(4), U128(to_yocto("1")), msg.to_string());
+        assert_eq!(
+            contract.get_pending_liquidity(accounts(4)).get(&0).unwrap(),
+            &vec![U128(to_yocto("1")), U128(0)]
+        );
+        assert_eq!(contract.get_pool_shares(0, accounts(4)).0, 0);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(accounts(4), U128(to_yocto("3")), msg.to_string());
+        assert!(contract.get_pending_liquidity(accounts(4)).is_empty());
+        assert_eq!(
+            contract.get_pool_shares(0, accounts(4)).0,
+            crate::utils::INIT_SHARES_SUPPLY / 5
+        );
+        assert_eq!(contract.get_pool(0).amounts, vec![U128(to_yocto("6")), U128(to_yocto("12"))]);
+        // Leftover of the second token is deposited.
+        assert_eq!(contract.get_deposit(accounts(4), accounts(1)).0, 0);
+        assert_eq!(contract.get_deposit(accounts(4), accounts(2)).0, to_yocto("1"));
+    }
+
+    #[test]
+    #[should_panic(expected = "E68: slippage error")]
+    fn test_add_transferred_liquidity_strictest_min_shares() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![]);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let msg = format!("{{\"add_liquidity_to\": 0, \"min_shares\": \"{}\"}}", crate::utils::INIT_SHARES_SUPPLY);
+        contract.ft_on_transfer(accounts(4), U128(to_yocto("1")), msg);
+        // The later transfer doesn't loosen the slippage limit of the earlier one.
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let msg = "{\"add_liquidity_to\": 0, \"min_shares\": \"1\"}";
+        contract.ft_on_transfer(accounts(4), U128(to_yocto("2")), msg.to_string());
+    }
+
+    #[test]
+    fn test_cancel_pending_liquidity() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![]);
+        let storage_before = contract.get_user_storage_state(accounts(4)).unwrap();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let msg = "{\"add_liquidity_to\": 0, \"min_shares\": \"1\"}";
+        contract.ft_on_transfer(accounts(4), U128(to_yocto("1")), msg.to_string());
+
+        testing_env!(context.predecessor_account_id(accounts(4)).attached_deposit(1).build());
+        contract.cancel_pending_liquidity(0);
+        assert!(contract.get_pending_liquidity(accounts(4)).is_empty());
+        assert_eq!(contract.get_deposit(accounts(4), accounts(1)).0, to_yocto("1"));
+        assert_eq!(contract.get_pool(0).amounts[0].0, to_yocto("5"));
+        // Storage of the pending liquidity is paid back.
+        assert_eq!(contract.get_user_storage_state(accounts(4)).unwrap().deposit, storage_before.deposit);
+    }
+
+    #[test]
+    #[should_panic(expected = "E36: no pending liquidity")]
+    fn test_cancel_missing_pending_liquidity() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![]);
+        testing_env!(context.predecessor_account_id(accounts(4)).attached_deposit(1).build());
+        contract.cancel_pending_liquidity(0);
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_near() {
+        let (mut context, mut contract) = setup_contract();
         testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
-        contract.remove_exchange_fee_liquidity(0, to_yocto("100").into(), vec![1.into(), 1.into()]);
+        contract.set_wnear_id(accounts(5));
+        assert_eq!(contract.get_wnear_id(), Some(accounts(5).into()));
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(5), to_yocto("1"))]);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(to_yocto("2")).build());
+        contract.deposit_near();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.exchange_callback_post_near_deposit(accounts(3).into(), U128(to_yocto("2")));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(5)).0, to_yocto("3"));
+
+        // Failed unwrapping returns wNEAR to the deposits.
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        contract.withdraw_near(U128(to_yocto("1")));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(5)).0, to_yocto("2"));
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.exchange_callback_post_near_withdraw(accounts(3).into(), U128(to_yocto("1")));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(5)).0, to_yocto("3"));
+
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        contract.withdraw_near(U128(0));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(5)).0, 0);
+    }
+
+    #[test]
+    fn test_deposit_near_registers_wnear() {
+        let (mut context, mut contract) = setup_contract();
         testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
-        contract.withdraw_owner_token(accounts(1), to_yocto("0.00001").into());
-        testing_env!(context.predecessor_account_id(accounts(0)).block_timestamp(2*86400 * 1_000_000_000).attached_deposit(1).build());
-        contract.rated_swap_ramp_amp(0,250, (3*86400 * 1_000_000_000).into());
+        contract.set_wnear_id(accounts(5));
+        contract.extend_whitelisted_tokens(vec![accounts(5)]);
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![]);
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(to_yocto("1")).build());
+        contract.deposit_near();
+        // wNEAR is registered before wrapping, so the callback has the storage for it.
+        assert_eq!(contract.get_deposit(accounts(3), accounts(5)).0, 0);
+        assert!(contract.get_user_whitelisted_tokens(accounts(3)).contains(&accounts(5).to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "E11: insufficient $NEAR storage deposit")]
+    fn test_deposit_near_without_storage() {
+        let (mut context, mut contract) = setup_contract();
         testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
-        contract.rated_swap_stop_ramp_amp(0);
+        contract.set_wnear_id(accounts(5));
+        contract.extend_whitelisted_tokens(vec![accounts(5)]);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(Account::min_storage_usage())
+            .build());
+        contract.storage_deposit(None, Some(true));
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(to_yocto("1")).build());
+        contract.deposit_near();
+    }
+
+    #[test]
+    #[should_panic(expected = "E180: wNEAR contract is not set")]
+    fn test_deposit_near_without_wnear() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![]);
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(to_yocto("1")).build());
+        contract.deposit_near();
+    }
+
+    #[test]
+    fn test_withdraw_tokens() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        contract.withdraw_tokens(
+            Some(vec![(accounts(1), U128(to_yocto("1"))), (accounts(2), U128(0))]),
+            Some(true),
+        );
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, to_yocto("4"));
+        // Only emptied tokens are unregistered.
+        assert_eq!(contract.get_user_whitelisted_tokens(accounts(3)), vec![accounts(1).to_string()]);
+
+        // Failed send returns only that token.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.exchange_callback_post_withdraw(accounts(2).into(), accounts(3).into(), U128(to_yocto("10")));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, to_yocto("4"));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(2)).0, to_yocto("10"));
+
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        contract.withdraw_tokens(None, None);
+        let deposits = contract.get_deposits(accounts(3));
+        assert_eq!(deposits.len(), 2);
+        assert!(deposits.values().all(|amount| amount.0 == 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "E20: not enough gas to withdraw all the tokens")]
+    fn test_withdraw_tokens_not_enough_gas() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .prepaid_gas(60_000_000_000_000)
+            .build());
+        contract.withdraw_tokens(None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E29: Illegal withdraw amount")]
+    fn test_withdraw_tokens_empty() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), 0)]);
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        contract.withdraw_tokens(None, None);
+    }
+
+    #[test]
+    fn test_withdraw_call() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.extend_whitelisted_tokens(vec![accounts(1)]);
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), to_yocto("10"))]);
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        contract.withdraw_call(accounts(1), U128(to_yocto("4")), accounts(5), "deposit".to_string());
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, to_yocto("6"));
+
+        // Unused amount is returned to the deposits.
+        let used = near_sdk::serde_json::to_vec(&U128(to_yocto("3"))).unwrap();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(used)]
+        );
+        let used = contract.exchange_callback_post_withdraw_call(
+            accounts(1).into(),
+            accounts(3).into(),
+            U128(to_yocto("4")),
+        );
+        assert_eq!(used.0, to_yocto("3"));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, to_yocto("7"));
+
+        // All of it is returned if the transfer failed, to the owner if the sender is not registered.
+        testing_env!(
+            context.build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        let used = contract.exchange_callback_post_withdraw_call(
+            accounts(1).into(),
+            accounts(4).into(),
+            U128(to_yocto("4")),
+        );
+        assert_eq!(used.0, 0);
+        assert_eq!(contract.get_deposit(accounts(0), accounts(1)).0, to_yocto("4"));
+    }
+
+    fn ft_metadata(symbol: &str) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: "ft-1.0.0".to_string(),
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 24,
+        }
+    }
+
+    #[test]
+    fn test_mft_token_metadata() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.refresh_token_metadata(accounts(1));
+
+        for (token_id, symbol) in vec![(accounts(1), "REF"), (accounts(2), "wNEAR")] {
+            assert_eq!(contract.mft_metadata(":0".to_string()).symbol, "REF-POOL-0");
+            testing_env!(
+                context.predecessor_account_id(accounts(0)).build(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                vec![PromiseResult::Successful(near_sdk::serde_json::to_vec(&ft_metadata(symbol)).unwrap())]
+            );
+            contract.callback_token_metadata(token_id.clone().into());
+            assert_eq!(contract.mft_metadata(token_id.into()).symbol, symbol);
+        }
+        // Icon is cached with the rest of the metadata.
+        let mut metadata = ft_metadata("REF");
+        metadata.icon = Some("data:image/svg+xml,<svg></svg>".to_string());
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(near_sdk::serde_json::to_vec(&metadata).unwrap())]
+        );
+        contract.callback_token_metadata(accounts(1).into());
+        assert_eq!(contract.mft_metadata(accounts(1).into()).icon, metadata.icon);
+        let metadata = contract.mft_metadata(":0".to_string());
+        assert_eq!(metadata.name, "REF-wNEAR ref-pool-0");
+        assert_eq!(metadata.symbol, "REF-wNEAR LP");
+    }
+
+    #[test]
+    #[should_panic(expected = "E112: token metadata is too large")]
+    fn test_token_metadata_too_large() {
+        let (mut context, mut contract) = setup_contract();
+        let mut metadata = ft_metadata("REF");
+        metadata.reference = Some("a".repeat(257));
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(near_sdk::serde_json::to_vec(&metadata).unwrap())]
+        );
+        contract.callback_token_metadata(accounts(1).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "E111: token metadata is not cached")]
+    fn test_mft_token_metadata_not_cached() {
+        let (_, contract) = setup_contract();
+        contract.mft_metadata(accounts(1).into());
+    }
+
+    #[test]
+    #[should_panic(expected = "E100: no permission to invoke this")]
+    fn test_refresh_token_metadata_not_allowed() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        // Even whitelisted tokens are refreshed only by owner or guardians.
+        testing_env!(context.predecessor_account_id(accounts(4)).attached_deposit(1).build());
+        contract.refresh_token_metadata(accounts(1));
+    }
+
+    #[test]
+    fn test_add_rated_liquidity_with_stale_rates() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_rated_pool_with_liquidity(&mut context, &mut contract);
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(4),
+            vec![(accounts(1), to_yocto("1")), (accounts(2), to_yocto("1"))],
+        );
+        let storage_balance = contract.storage_balance_of(accounts(4)).unwrap().total.0;
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .epoch_height(1)
+            .attached_deposit(to_yocto("0.0007"))
+            .build());
+        let amounts = vec![U128(to_yocto("1")), U128(to_yocto("1"))];
+        match contract.add_rated_liquidity(pool_id, amounts.clone(), U128(1)) {
+            PromiseOrValue::Promise(_) => {}
+            _ => panic!("rates should be fetched first"),
+        }
+        // Attached NEAR pays for the shares from the storage deposit.
+        assert_eq!(
+            contract.storage_balance_of(accounts(4)).unwrap().total.0,
+            storage_balance + to_yocto("0.0007")
+        );
+        assert_eq!(contract.get_deposit(accounts(4), accounts(1)).0, to_yocto("1"));
+
+        let rate = near_sdk::serde_json::to_vec(&U128(1_000000000000000000000000)).unwrap();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(rate)]
+        );
+        let shares = contract.callback_add_rated_liquidity(
+            accounts(4).into(),
+            pool_id,
+            amounts,
+            U128(1),
+            vec![(pool_id, 1)],
+        );
+        assert!(shares.0 > 0);
+        assert_eq!(contract.get_pool_shares(pool_id, accounts(4)), shares);
+        assert_eq!(contract.get_deposit(accounts(4), accounts(1)).0, 0);
+    }
+
+    #[test]
+    fn test_swap_with_stale_rates() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_rated_pool_with_liquidity(&mut context, &mut contract);
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![(accounts(1), to_yocto("1"))]);
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .epoch_height(1)
+            .attached_deposit(1)
+            .build());
+        let actions = vec![SwapAction {
+            pool_id,
+            token_in: accounts(1).into(),
+            amount_in: Some(U128(to_yocto("1"))),
+            token_out: accounts(2).into(),
+            min_amount_out: U128(1),
+        }];
+        match contract.swap(actions, None, None) {
+            PromiseOrValue::Promise(_) => {}
+            _ => panic!("rates should be fetched first"),
+        }
+        assert_eq!(contract.get_deposit(accounts(4), accounts(1)).0, to_yocto("1"));
+
+        let rate = near_sdk::serde_json::to_vec(&U128(1_000000000000000000000000)).unwrap();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(rate)]
+        );
+        let actions = vec![Action::Swap(SwapAction {
+            pool_id,
+            token_in: accounts(1).into(),
+            amount_in: Some(U128(to_yocto("1"))),
+            token_out: accounts(2).into(),
+            min_amount_out: U128(1),
+        })];
+        let amount_out = contract.callback_swap_with_rates(accounts(4).into(), actions, None, None, vec![(pool_id, 1)]);
+        assert!(amount_out.0 > 0);
+        assert!(is_rated_pool_fresh(&contract, pool_id));
+        assert_eq!(contract.get_deposit(accounts(4), accounts(1)).0, 0);
+        assert_eq!(contract.get_deposit(accounts(4), accounts(2)), amount_out);
+    }
+
+    #[test]
+    #[should_panic(expected = "E124: Cross-contract call failed")]
+    fn test_execute_actions_with_failed_rates() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_rated_pool_with_liquidity(&mut context, &mut contract);
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![(accounts(1), to_yocto("1"))]);
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        let actions = vec![Action::Swap(SwapAction {
+            pool_id,
+            token_in: accounts(1).into(),
+            amount_in: Some(U128(to_yocto("1"))),
+            token_out: accounts(2).into(),
+            min_amount_out: U128(1),
+        })];
+        contract.callback_execute_actions_with_rates(accounts(4).into(), actions, None, None, vec![(pool_id, 1)]);
     }
 
     #[test]
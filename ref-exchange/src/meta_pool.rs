@@ -0,0 +1,209 @@
+//! Meta pools pair a token with shares of another stable or rated pool of this contract, the base pool.
+//! Shares of the base pool are used as token `:base_pool_id`, they are held by `META_POOL_SHARES_HOLDER`
+//! in the base pool, on behalf of the meta pools and of the deposits of this token.
+
+use crate::*;
+
+/// Holder of the base pool shares, not a valid account id, so it can't act on its own.
+pub const META_POOL_SHARES_HOLDER: &str = "@meta_pools";
+
+/// Id of the token, representing shares of given pool in deposits and in meta pools.
+pub(crate) fn share_token_id(pool_id: u64) -> AccountId {
+    format!(":{}", pool_id)
+}
+
+fn token_position(pool: &Pool, token_id: &AccountId) -> usize {
+    pool.tokens()
+        .iter()
+        .position(|id| id == token_id)
+        .expect(ERR63_MISSING_TOKEN)
+}
+
+impl Contract {
+    /// Swaps in meta pool, when one of the tokens is a token of its base pool.
+    /// Such token is exchanged for the base pool shares by adding or removing liquidity of the base pool,
+    /// and the shares are swapped in the meta pool at the share price from before adding the liquidity.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn internal_meta_pool_swap(
+        &mut self,
+        pool_id: u64,
+        mut pool: Pool,
+        base_pool_id: u64,
+        token_in: &AccountId,
+        amount_in: u128,
+        token_out: &AccountId,
+        min_amount_out: u128,
+        referral_id: &Option<AccountId>,
+    ) -> u128 {
+        let share_token = share_token_id(base_pool_id);
+        if !pool.tokens().contains(token_in) {
+            let shares = self.internal_meta_add_base_liquidity(base_pool_id, token_in, amount_in);
+            let amount_out = pool.swap(
+                &share_token,
+                shares,
+                token_out,
+                min_amount_out,
+                AdminFees {
+                    exchange_fee: self.exchange_fee,
+                    exchange_id: env::current_account_id(),
+                    referral_fee: self.referral_fee,
+                    referral_id: referral_id.clone(),
+                },
+            );
+            self.pools.replace(pool_id, &pool);
+            self.internal_update_pool_observations(pool_id, &pool);
+            amount_out
+        } else {
+            let shares = self.internal_pool_swap(pool_id, token_in, amount_in, &share_token, 0, referral_id);
+            self.internal_meta_remove_base_liquidity(base_pool_id, shares, token_out, min_amount_out)
+        }
+    }
+
+    /// Returns amount of token_out for swapping in meta pool, when one of the tokens is a token of its base pool.
+    pub(crate) fn internal_get_meta_pool_return(
+        &self,
+        pool: &Pool,
+        base_pool_id: u64,
+        token_in: &AccountId,
+        amount_in: u128,
+        token_out: &AccountId,
+    ) -> u128 {
+        let share_token = share_token_id(base_pool_id);
+        let fees = AdminFees::new(self.exchange_fee);
+        let base_pool = self.internal_get_pool(base_pool_id);
+        if !pool.tokens().contains(token_in) {
+            let mut amounts = vec![0; base_pool.tokens().len()];
+            amounts[token_position(&base_pool, token_in)] = amount_in;
+            let shares = match &base_pool {
+                Pool::RatedSwapPool(_) => base_pool.predict_add_rated_liquidity(&amounts, &None, &fees),
+                _ => base_pool.predict_add_stable_liquidity(&amounts, &fees),
+            };
+            pool.get_return(&share_token, shares, token_out, &fees)
+        } else {
+            let shares = pool.get_return(token_in, amount_in, &share_token, &fees);
+            base_pool.predict_remove_liquidity_one_coin(shares, token_out, &fees)
+        }
+    }
+
+    /// Adds given amount of the token to the base pool, returns shares minted to the holder.
+    fn internal_meta_add_base_liquidity(&mut self, base_pool_id: u64, token_id: &AccountId, amount: u128) -> Balance {
+        self.assert_pool_add_liquidity_allowed(base_pool_id);
+        let mut pool = self.internal_get_pool(base_pool_id);
+        let mut amounts = vec![0; pool.tokens().len()];
+        amounts[token_position(&pool, token_id)] = amount;
+        let shares = pool.add_stable_liquidity(
+            &META_POOL_SHARES_HOLDER.to_string(),
+            &amounts,
+            0,
+            AdminFees::new(self.exchange_fee),
+        );
+        self.pools.replace(base_pool_id, &pool);
+        self.internal_update_pool_observations(base_pool_id, &pool);
+        shares
+    }
+
+    /// Removes given shares of the holder from the base pool into the token.
+    fn internal_meta_remove_base_liquidity(
+        &mut self,
+        base_pool_id: u64,
+        shares: Balance,
+        token_id: &AccountId,
+        min_amount: u128,
+    ) -> u128 {
+        self.assert_pool_remove_liquidity_allowed(base_pool_id);
+        let mut pool = self.internal_get_pool(base_pool_id);
+        let amount = pool.remove_liquidity_one_coin(
+            &META_POOL_SHARES_HOLDER.to_string(),
+            shares,
+            token_id,
+            min_amount,
+            AdminFees::new(self.exchange_fee),
+        );
+        self.pools.replace(base_pool_id, &pool);
+        self.internal_update_pool_observations(base_pool_id, &pool);
+        amount
+    }
+
+    /// Returns the base pool, checking that meta pools can be built on top of it.
+    fn internal_get_base_pool(&self, base_pool_id: u64) -> Pool {
+        let pool = self.internal_get_pool(base_pool_id);
+        assert!(
+            pool.share_has_registered(&META_POOL_SHARES_HOLDER.to_string()),
+            "{}",
+            ERR161_NOT_BASE_POOL
+        );
+        pool
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Adds new "Meta Pool", a rated pool of given token and shares of the stable or rated base pool,
+    /// the shares are priced by the share price of the base pool.
+    /// It is limited to owner or guardians, same as other stable pools.
+    #[payable]
+    pub fn add_meta_pool(
+        &mut self,
+        token: ValidAccountId,
+        decimals: u8,
+        base_pool_id: u64,
+        fee: u32,
+        amp_factor: u64,
+    ) -> u64 {
+        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        let mut base_pool = self.internal_get_pool(base_pool_id);
+        let is_valid_base = match &base_pool {
+            Pool::StableSwapPool(_) => true,
+            Pool::RatedSwapPool(pool) => pool.meta_base_pool_id().is_none(),
+            _ => false,
+        };
+        assert!(is_valid_base, "{}", ERR160_ILLEGAL_BASE_POOL);
+        let prev_storage = env::storage_usage();
+        let holder_id = META_POOL_SHARES_HOLDER.to_string();
+        if !base_pool.share_has_registered(&holder_id) {
+            base_pool.share_register(&holder_id);
+            self.pools.replace(base_pool_id, &base_pool);
+        }
+        let pool_id = self.internal_push_pool(Pool::RatedSwapPool(RatedSwapPool::new_meta(
+            self.pools.len() as u32,
+            token,
+            decimals,
+            base_pool_id,
+            base_pool.get_share_decimal(),
+            amp_factor as u128,
+            fee,
+        )));
+        self.internal_check_storage(prev_storage);
+        pool_id
+    }
+
+    /// Moves shares of the base pool of meta pools from the sender into the deposits, as token `:base_pool_id`,
+    /// so they can be added to meta pools and swapped like other tokens.
+    #[payable]
+    pub fn deposit_pool_shares(&mut self, base_pool_id: u64, amount: U128) {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let sender_id = env::predecessor_account_id();
+        let mut pool = self.internal_get_base_pool(base_pool_id);
+        pool.share_transfer(&sender_id, &META_POOL_SHARES_HOLDER.to_string(), amount.0);
+        self.pools.replace(base_pool_id, &pool);
+        let mut account = self.internal_unwrap_account(&sender_id);
+        account.deposit(&share_token_id(base_pool_id), amount.0);
+        self.internal_save_account(&sender_id, account);
+    }
+
+    /// Moves shares of the base pool of meta pools from the deposits of the sender back to its shares.
+    /// Sender should be registered in the base pool.
+    #[payable]
+    pub fn withdraw_pool_shares(&mut self, base_pool_id: u64, amount: U128) {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let sender_id = env::predecessor_account_id();
+        let mut pool = self.internal_get_base_pool(base_pool_id);
+        let mut account = self.internal_unwrap_account(&sender_id);
+        account.withdraw(&share_token_id(base_pool_id), amount.0);
+        self.internal_save_account(&sender_id, account);
+        pool.share_transfer(&META_POOL_SHARES_HOLDER.to_string(), &sender_id, amount.0);
+        self.pools.replace(base_pool_id, &pool);
+    }
+}
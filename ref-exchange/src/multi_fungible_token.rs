@@ -2,7 +2,7 @@ use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::{ext_contract, near_bindgen, Balance, PromiseOrValue};
 
-use crate::utils::{GAS_FOR_FT_TRANSFER_CALL, GAS_FOR_RESOLVE_TRANSFER, NO_DEPOSIT};
+use crate::utils::{GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER_CALL, GAS_FOR_RESOLVE_TRANSFER, NO_DEPOSIT};
 use crate::*;
 
 #[ext_contract(ext_self)]
@@ -14,6 +14,12 @@ trait MFTTokenResolver {
         receiver_id: AccountId,
         amount: U128,
     ) -> U128;
+    fn callback_token_metadata(&mut self, token_id: AccountId);
+}
+
+#[ext_contract(ext_token_metadata)]
+pub trait TokenMetadataProvider {
+    fn ft_metadata(&self) -> FungibleTokenMetadata;
 }
 
 #[ext_contract(ext_share_token_receiver)]
@@ -27,6 +33,11 @@ pub trait MFTTokenReceiver {
     ) -> PromiseOrValue<U128>;
 }
 
+/// Limit on the length of the icon in the cached token metadata, which is stored at the contract's expense.
+const MAX_TOKEN_ICON_LEN: usize = 16 * 1024;
+/// Limit on the length of the other fields of the cached token metadata.
+const MAX_TOKEN_METADATA_FIELD_LEN: usize = 256;
+
 enum TokenOrPool {
     Token(AccountId),
     Pool(u64),
@@ -36,7 +47,7 @@ enum TokenOrPool {
 /// This is used to parse token_id fields in mft protocol used in ref,
 /// So, if we choose #nn as a partern, should announce it in mft protocol.
 /// cause : is not allowed in a normal account id, it can be a partern leading char
-fn try_identify_pool_id(token_id: &String) -> Result<u64, &'static str> {
+pub(crate) fn try_identify_pool_id(token_id: &String) -> Result<u64, &'static str> {
     if token_id.starts_with(":") {
         if let Ok(pool_id) = str::parse::<u64>(&token_id[1..token_id.len()]) {
             Ok(pool_id)
@@ -70,7 +81,7 @@ impl Contract {
         assert_ne!(sender_id, receiver_id, "{}", ERR33_TRANSFER_TO_SELF);
         match parse_token_id(token_id) {
             TokenOrPool::Pool(pool_id) => {
-                let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+                let mut pool = self.internal_get_pool(pool_id);
                 pool.share_transfer(sender_id, receiver_id, amount);
                 self.pools.replace(pool_id, &pool);
                 log!(
@@ -106,7 +117,7 @@ impl Contract {
     fn internal_mft_balance(&self, token_id: String, account_id: &AccountId) -> Balance {
         match parse_token_id(token_id) {
             TokenOrPool::Pool(pool_id) => {
-                let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+                let pool = self.internal_get_pool(pool_id);
                 pool.share_balances(account_id)
             }
             TokenOrPool::Token(token_id) => self.internal_get_deposit(account_id, &token_id),
@@ -124,7 +135,7 @@ impl Contract {
     pub fn mft_total_supply(&self, token_id: String) -> U128 {
         match parse_token_id(token_id) {
             TokenOrPool::Pool(pool_id) => {
-                let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+                let pool = self.internal_get_pool(pool_id);
                 U128(pool.share_total_balance())
             }
             TokenOrPool::Token(_token_id) => unimplemented!(),
@@ -140,7 +151,7 @@ impl Contract {
         match parse_token_id(token_id) {
             TokenOrPool::Token(_) => env::panic(ERR110_INVALID_REGISTER.as_bytes()),
             TokenOrPool::Pool(pool_id) => {
-                let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+                let mut pool = self.internal_get_pool(pool_id);
                 pool.share_register(account_id.as_ref());
                 self.pools.replace(pool_id, &pool);
                 self.internal_check_storage(prev_storage);
@@ -249,23 +260,77 @@ impl Contract {
         U128(unused_amount)
     }
 
+    /// Returns metadata of the pool shares, with name and symbol built from the symbols of the pool tokens,
+    /// or cached metadata of the token.
     pub fn mft_metadata(&self, token_id: String) -> FungibleTokenMetadata {
         match parse_token_id(token_id) {
             TokenOrPool::Pool(pool_id) => {
-                let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+                let pool = self.internal_get_pool(pool_id);
                 let decimals = pool.get_share_decimal();
+                let symbols: Option<Vec<String>> = pool
+                    .tokens()
+                    .iter()
+                    .map(|token_id| self.token_metadata.get(token_id).map(|metadata| metadata.symbol))
+                    .collect();
                 FungibleTokenMetadata {
                     // [AUDIT_08]
                     spec: "mft-1.0.0".to_string(),
-                    name: format!("ref-pool-{}", pool_id),
-                    symbol: format!("REF-POOL-{}", pool_id),
+                    name: match &symbols {
+                        Some(symbols) => format!("{} ref-pool-{}", symbols.join("-"), pool_id),
+                        None => format!("ref-pool-{}", pool_id),
+                    },
+                    symbol: match &symbols {
+                        Some(symbols) => format!("{} LP", symbols.join("-")),
+                        None => format!("REF-POOL-{}", pool_id),
+                    },
                     icon: None,
                     reference: None,
                     reference_hash: None,
                     decimals,
                 }
             },
-            TokenOrPool::Token(_token_id) => unimplemented!(),
+            TokenOrPool::Token(token_id) => self.token_metadata.get(&token_id).expect(ERR111_NO_TOKEN_METADATA),
         }
     }
+
+    /// Fetches metadata of the token from its contract to be returned by `mft_metadata`.
+    /// Only can be called by owner or guardians, as the metadata is stored at the contract's expense,
+    /// metadata with too long fields is rejected.
+    #[payable]
+    pub fn refresh_token_metadata(&mut self, token_id: ValidAccountId) -> Promise {
+        assert_one_yocto();
+        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        ext_token_metadata::ft_metadata(token_id.as_ref(), NO_DEPOSIT, GAS_FOR_BASIC_OP).then(
+            ext_self::callback_token_metadata(
+                token_id.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_BASIC_OP,
+            ),
+        )
+    }
+
+    #[private]
+    pub fn callback_token_metadata(&mut self, token_id: AccountId) {
+        assert_eq!(env::promise_results_count(), 1, "{}", ERR123_ONE_PROMISE_RESULT);
+        let metadata = match env::promise_result(0) {
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<FungibleTokenMetadata>(&value)
+                .expect(ERR126_FAILED_TO_PARSE_RESULT),
+            _ => env::panic(ERR124_CROSS_CALL_FAILED.as_bytes()),
+        };
+        let fields_len = [
+            metadata.spec.len(),
+            metadata.name.len(),
+            metadata.symbol.len(),
+            metadata.reference.as_ref().map_or(0, |reference| reference.len()),
+            metadata.reference_hash.as_ref().map_or(0, |hash| hash.0.len()),
+        ];
+        assert!(
+            metadata.icon.as_ref().map_or(0, |icon| icon.len()) <= MAX_TOKEN_ICON_LEN
+                && fields_len.iter().all(|len| *len <= MAX_TOKEN_METADATA_FIELD_LEN),
+            "{}",
+            ERR112_TOKEN_METADATA_TOO_LARGE
+        );
+        self.token_metadata.insert(&token_id, &metadata);
+    }
 }
@@ -4,6 +4,7 @@ use near_sdk::json_types::WrappedTimestamp;
 use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
 
 use crate::*;
+use crate::legacy::ContractV2;
 use crate::utils::{FEE_DIVISOR, GAS_FOR_BASIC_OP};
 
 #[near_bindgen]
@@ -21,6 +22,20 @@ impl Contract {
         self.owner_id.clone()
     }
 
+    /// Set wNEAR contract, that wraps native NEAR deposits. Only can be called by owner.
+    /// This contract should be registered in it.
+    #[payable]
+    pub fn set_wnear_id(&mut self, wnear_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.wnear_id = Some(wnear_id.into());
+    }
+
+    /// Get wNEAR contract, that wraps native NEAR deposits.
+    pub fn get_wnear_id(&self) -> Option<AccountId> {
+        self.wnear_id.clone()
+    }
+
     /// Retrieve NEP-141 tokens that not mananged by contract to owner,
     /// Caution: Must check that `amount <= total_amount_in_account - amount_managed_by_contract` before calling !!!
     /// Returns promise of ft_transfer action.
@@ -90,6 +105,32 @@ impl Contract {
         }
     }
 
+    /// Change state of a single pool, Only can be called by owner or guardians.
+    /// Only owner can resume the pool.
+    #[payable]
+    pub fn change_pool_state(&mut self, pool_id: u64, state: PoolState) {
+        assert_one_yocto();
+        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        assert!(pool_id < self.pools.len(), "{}", ERR85_NO_POOL);
+
+        let current_state = self.internal_get_pool_state(pool_id);
+        if current_state != state {
+            if state == PoolState::Running {
+                self.assert_owner();
+                self.pool_states.remove(&pool_id);
+            } else {
+                self.pool_states.insert(&pool_id, &state);
+            }
+            env::log(
+                format!(
+                    "Pool {} state changed from {} to {} by {}",
+                    pool_id, current_state, state, env::predecessor_account_id()
+                )
+                .as_bytes(),
+            );
+        }
+    }
+
     /// Extend whitelisted tokens with new tokens. Only can be called by owner.
     #[payable]
     pub fn extend_whitelisted_tokens(&mut self, tokens: Vec<ValidAccountId>) {
@@ -128,7 +169,8 @@ impl Contract {
         self.assert_contract_running();
         let ex_id = env::current_account_id();
         let owner_id = self.owner_id.clone();
-        let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        self.assert_pool_remove_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
         let amounts = pool.remove_liquidity(
             &ex_id,
             shares.into(),
@@ -138,6 +180,7 @@ impl Contract {
                 .collect(),
         );
         self.pools.replace(pool_id, &pool);
+        self.internal_update_pool_observations(pool_id, &pool);
         let tokens = pool.tokens();
         let mut deposits = self.internal_unwrap_account(&owner_id);
         for i in 0..tokens.len() {
@@ -146,6 +189,27 @@ impl Contract {
         self.internal_save_account(&owner_id, deposits);
     }
 
+    /// Claim exchange fees collected by concentrated liquidity pool to owner's inner account.
+    /// Owner's inner account storage should be prepared in advance.
+    #[payable]
+    pub fn claim_concentrated_exchange_fees(&mut self, pool_id: u64) -> Vec<U128> {
+        assert_one_yocto();
+        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        self.assert_contract_running();
+        let owner_id = self.owner_id.clone();
+        self.assert_pool_remove_liquidity_allowed(pool_id);
+        let mut pool = self.internal_get_concentrated_pool(pool_id);
+        let amounts = pool.withdraw_exchange_fees();
+        let mut deposits = self.internal_unwrap_account(&owner_id);
+        let tokens = pool.tokens();
+        for i in 0..tokens.len() {
+            deposits.deposit(&tokens[i], amounts[i]);
+        }
+        self.internal_save_account(&owner_id, deposits);
+        self.pools.replace(pool_id, &Pool::ConcentratedLiquidityPool(pool));
+        amounts.into_iter().map(|amount| amount.into()).collect()
+    }
+
     /// Withdraw owner inner account token to owner wallet.
     /// Owner inner account should be prepared in advance.
     #[payable]
@@ -181,7 +245,7 @@ impl Contract {
     ) {
         assert_one_yocto();
         assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
-        let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let mut pool = self.internal_get_pool(pool_id);
         match &mut pool {
             Pool::StableSwapPool(pool) => {
                 pool.ramp_amplification(future_amp_factor as u128, future_amp_time.0)
@@ -198,7 +262,7 @@ impl Contract {
     pub fn stable_swap_stop_ramp_amp(&mut self, pool_id: u64) {
         assert_one_yocto();
         assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
-        let mut pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let mut pool = self.internal_get_pool(pool_id);
         match &mut pool {
             Pool::StableSwapPool(pool) => pool.stop_ramp_amplification(),
             Pool::RatedSwapPool(pool) => pool.stop_ramp_amplification(),
@@ -207,6 +271,68 @@ impl Contract {
         self.pools.replace(pool_id, &pool);
     }
 
+    /// Change total fee of a simple, stable or rated pool. Only can be called by owner or guardians.
+    /// total_fee: the target fee in bps, should be less than FEE_DIVISOR;
+    /// fee_ramp_time: if given, fee changes linearly from the current one until this time, otherwise at once;
+    #[payable]
+    pub fn modify_pool_fee(
+        &mut self,
+        pool_id: u64,
+        total_fee: u32,
+        fee_ramp_time: Option<WrappedTimestamp>,
+    ) {
+        assert_one_yocto();
+        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        assert!(total_fee < FEE_DIVISOR, "{}", ERR62_FEE_ILLEGAL);
+        let mut pool = self.internal_get_pool(pool_id);
+        match pool {
+            Pool::SimplePool(_) | Pool::StableSwapPool(_) | Pool::RatedSwapPool(_) => {}
+            _ => env::panic(ERR93_FEE_NOT_ADJUSTABLE.as_bytes()),
+        }
+        let current_fee = pool.get_fee();
+        if let Some(fee_ramp_time) = fee_ramp_time {
+            let current_time = env::block_timestamp();
+            assert!(fee_ramp_time.0 > current_time, "{}", ERR94_ILLEGAL_FEE_RAMP_TIME);
+            env::log(
+                format!(
+                    "Pool {} fee ramps from {} to {} until {}",
+                    pool_id, current_fee, total_fee, fee_ramp_time.0
+                )
+                .as_bytes(),
+            );
+            self.pool_fee_ramps.insert(
+                &pool_id,
+                &FeeRamp {
+                    init_fee: current_fee,
+                    target_fee: total_fee,
+                    init_time: current_time,
+                    stop_time: fee_ramp_time.0,
+                },
+            );
+        } else {
+            env::log(
+                format!("Pool {} fee changed from {} to {}", pool_id, current_fee, total_fee).as_bytes(),
+            );
+            self.pool_fee_ramps.remove(&pool_id);
+            pool.set_fee(total_fee);
+        }
+        self.pools.replace(pool_id, &pool);
+    }
+
+    /// Change limits on the age of the rates of a rated pool. Only can be called by owner or guardians.
+    /// Swaps and liquidity changes depending on rates fail, once the rates are older than any of the limits.
+    #[payable]
+    pub fn set_pool_rates_freshness(&mut self, pool_id: u64, rates_freshness: RatesFreshness) {
+        assert_one_yocto();
+        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        assert!(rates_freshness.is_valid(), "{}", ERR96_ILLEGAL_RATES_FRESHNESS);
+        match self.internal_get_pool(pool_id) {
+            Pool::RatedSwapPool(_) => {}
+            _ => env::panic(ERR95_NOT_RATED_POOL.as_bytes()),
+        }
+        self.pool_rates_freshness.insert(&pool_id, &rates_freshness);
+    }
+
     ///
     #[payable]
     pub fn rated_swap_ramp_amp(
@@ -237,14 +363,33 @@ impl Contract {
             || self.guardians.contains(&env::predecessor_account_id())
     }
 
-    /// Migration function from v2 to v2.
+    /// Migration function from v2 to v3, adds price accumulators, fee ramps, states and rates freshness of the pools,
+    /// unclaimed tokens and pending liquidity of the accounts, wNEAR contract and metadata of the tokens.
     /// For next version upgrades, change this function.
     #[init(ignore_state)]
     // [AUDIT_09]
     #[private]
     pub fn migrate() -> Self {
-        let contract: Contract = env::state_read().expect(ERR103_NOT_INITIALIZED);
-        contract
+        let contract: ContractV2 = env::state_read().expect(ERR103_NOT_INITIALIZED);
+        Contract {
+            owner_id: contract.owner_id,
+            exchange_fee: contract.exchange_fee,
+            referral_fee: contract.referral_fee,
+            pools: contract.pools,
+            accounts: contract.accounts,
+            whitelisted_tokens: contract.whitelisted_tokens,
+            guardians: contract.guardians,
+            state: contract.state,
+            pool_observations: LookupMap::new(StorageKey::PoolObservations),
+            pool_fee_ramps: LookupMap::new(StorageKey::PoolFeeRamps),
+            pool_states: UnorderedMap::new(StorageKey::PoolStates),
+            pool_rates_freshness: LookupMap::new(StorageKey::PoolRatesFreshness),
+            pool_rates_updated_at: LookupMap::new(StorageKey::PoolRatesUpdatedAt),
+            unclaimed_tokens: LookupMap::new(StorageKey::UnclaimedTokens),
+            pending_liquidity: LookupMap::new(StorageKey::PendingLiquidity),
+            wnear_id: None,
+            token_metadata: LookupMap::new(StorageKey::TokenMetadata),
+        }
     }
 }
 
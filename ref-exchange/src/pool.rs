@@ -1,12 +1,44 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{AccountId, Balance, PromiseOrValue};
+use near_sdk::{AccountId, Balance, PromiseOrValue, Timestamp};
 
 use crate::admin_fee::AdminFees;
 use crate::simple_pool::SimplePool;
 use crate::stable_swap::StableSwapPool;
 use crate::rated_swap::RatedSwapPool;
 use crate::rated_swap::rates::RatesTrait;
-use crate::utils::SwapVolume;
+use crate::utils::{SwapVolume, U256};
+use crate::weighted_pool::WeightedPool;
+use crate::concentrated_liquidity::ConcentratedLiquidityPool;
+use crate::errors::{ERR147_NO_SHARES, ERR80_NOT_SUPPORTED_BY_POOL};
+use near_sdk::env;
+
+/// Gradual change of pool's total fee, kept outside of the pool and applied whenever the pool is read.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct FeeRamp {
+    pub init_fee: u32,
+    pub target_fee: u32,
+    pub init_time: Timestamp,
+    pub stop_time: Timestamp,
+}
+
+impl FeeRamp {
+    /// Returns fee at the current block, changing linearly from init_fee to target_fee.
+    pub fn get_fee(&self) -> u32 {
+        let current_time = env::block_timestamp();
+        if current_time >= self.stop_time {
+            return self.target_fee;
+        }
+        let time_range = (self.stop_time - self.init_time) as u128;
+        let time_delta = (current_time - self.init_time) as u128;
+        if self.target_fee >= self.init_fee {
+            let fee_delta = (self.target_fee - self.init_fee) as u128 * time_delta / time_range;
+            self.init_fee + fee_delta as u32
+        } else {
+            let fee_delta = (self.init_fee - self.target_fee) as u128 * time_delta / time_range;
+            self.init_fee - fee_delta as u32
+        }
+    }
+}
 
 /// Generic Pool, providing wrapper around different implementations of swap pools.
 /// Allows to add new types of pools just by adding extra item in the enum without needing to migrate the storage.
@@ -15,6 +47,8 @@ pub enum Pool {
     SimplePool(SimplePool),
     StableSwapPool(StableSwapPool),
     RatedSwapPool(RatedSwapPool),
+    WeightedPool(WeightedPool),
+    ConcentratedLiquidityPool(ConcentratedLiquidityPool),
 }
 
 impl Pool {
@@ -24,6 +58,8 @@ impl Pool {
             Pool::SimplePool(_) => "SIMPLE_POOL".to_string(),
             Pool::StableSwapPool(_) => "STABLE_SWAP".to_string(),
             Pool::RatedSwapPool(_) => "RATED_SWAP".to_string(),
+            Pool::WeightedPool(_) => "WEIGHTED_POOL".to_string(),
+            Pool::ConcentratedLiquidityPool(_) => "CONCENTRATED_LIQUIDITY".to_string(),
         }
     }
 
@@ -33,6 +69,8 @@ impl Pool {
             Pool::SimplePool(pool) => pool.tokens(),
             Pool::StableSwapPool(pool) => pool.tokens(),
             Pool::RatedSwapPool(pool) => pool.tokens(),
+            Pool::WeightedPool(pool) => pool.tokens(),
+            Pool::ConcentratedLiquidityPool(pool) => pool.tokens(),
         }
     }
 
@@ -45,8 +83,10 @@ impl Pool {
     ) -> Balance {
         match self {
             Pool::SimplePool(pool) => pool.add_liquidity(sender_id, amounts),
-            Pool::StableSwapPool(_) => unimplemented!(),
-            Pool::RatedSwapPool(_) => unimplemented!(),
+            Pool::StableSwapPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+            Pool::RatedSwapPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+            Pool::WeightedPool(pool) => pool.add_liquidity(sender_id, amounts),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
         }
     }
 
@@ -58,9 +98,30 @@ impl Pool {
         admin_fee: AdminFees,
     ) -> Balance {
         match self {
-            Pool::SimplePool(_) => unimplemented!(),
+            Pool::SimplePool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
             Pool::StableSwapPool(pool) => pool.add_liquidity(sender_id, amounts, min_shares, &admin_fee),
             Pool::RatedSwapPool(pool) => pool.add_liquidity(sender_id, amounts, min_shares, &admin_fee),
+            Pool::WeightedPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+        }
+    }
+
+    /// Adds liquidity from a single token into underlying pool, swapping the needed part of it.
+    /// Returns minted shares and leftovers of each token.
+    pub fn zap_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        token_in: &AccountId,
+        amount_in: Balance,
+        min_shares: Balance,
+        admin_fee: &AdminFees,
+    ) -> (Balance, Vec<Balance>) {
+        match self {
+            Pool::SimplePool(pool) => pool.zap_liquidity(sender_id, token_in, amount_in, min_shares, admin_fee),
+            Pool::StableSwapPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+            Pool::RatedSwapPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+            Pool::WeightedPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
         }
     }
 
@@ -79,6 +140,8 @@ impl Pool {
             Pool::RatedSwapPool(pool) => {
                 pool.remove_liquidity_by_shares(sender_id, shares, min_amounts)
             }
+            Pool::WeightedPool(pool) => pool.remove_liquidity(sender_id, shares, min_amounts),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
         }
     }
 
@@ -98,6 +161,30 @@ impl Pool {
             Pool::RatedSwapPool(pool) => {
                 pool.remove_liquidity_by_tokens(sender_id, amounts, max_burn_shares, &admin_fee)
             }
+            Pool::WeightedPool(_) => unimplemented!(),
+            Pool::ConcentratedLiquidityPool(_) => unimplemented!(),
+        }
+    }
+
+    /// Removes liquidity from underlying pool into a single token.
+    pub fn remove_liquidity_one_coin(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        token_id: &AccountId,
+        min_amount: Balance,
+        admin_fee: AdminFees,
+    ) -> Balance {
+        match self {
+            Pool::SimplePool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+            Pool::StableSwapPool(pool) => {
+                pool.remove_liquidity_one_coin(sender_id, shares, token_id, min_amount, &admin_fee)
+            },
+            Pool::RatedSwapPool(pool) => {
+                pool.remove_liquidity_one_coin(sender_id, shares, token_id, min_amount, &admin_fee)
+            }
+            Pool::WeightedPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
         }
     }
 
@@ -113,6 +200,27 @@ impl Pool {
             Pool::SimplePool(pool) => pool.get_return(token_in, amount_in, token_out),
             Pool::StableSwapPool(pool) => pool.get_return(token_in, amount_in, token_out, fees),
             Pool::RatedSwapPool(pool) => pool.get_return(token_in, amount_in, token_out, fees),
+            Pool::WeightedPool(pool) => pool.get_return(token_in, amount_in, token_out),
+            Pool::ConcentratedLiquidityPool(pool) => pool.get_return(token_in, amount_in, token_out, fees),
+        }
+    }
+
+    /// Returns amount of token_out received for amount_in of token_in, or None if the pool can't make such swap.
+    /// Unlike `get_return`, doesn't fail on empty pools and out of range amounts, so routes can be searched in views.
+    /// Concentrated liquidity pools are not quoted, as their swaps can run out of liquidity of the ticks.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Option<Balance> {
+        match self {
+            Pool::SimplePool(pool) => pool.try_get_return(token_in, amount_in, token_out),
+            Pool::StableSwapPool(pool) => pool.try_get_return(token_in, amount_in, token_out, fees),
+            Pool::RatedSwapPool(pool) => pool.try_get_return(token_in, amount_in, token_out, fees),
+            Pool::WeightedPool(pool) => pool.try_get_return(token_in, amount_in, token_out),
+            Pool::ConcentratedLiquidityPool(_) => None,
         }
     }
 
@@ -122,6 +230,8 @@ impl Pool {
             Pool::SimplePool(_) => 24,
             Pool::StableSwapPool(_) => 18,
             Pool::RatedSwapPool(_) => 24,
+            Pool::WeightedPool(_) => 24,
+            Pool::ConcentratedLiquidityPool(_) => 24,
         }
     }
 
@@ -131,6 +241,19 @@ impl Pool {
             Pool::SimplePool(pool) => pool.get_fee(),
             Pool::StableSwapPool(pool) => pool.get_fee(),
             Pool::RatedSwapPool(pool) => pool.get_fee(),
+            Pool::WeightedPool(pool) => pool.get_fee(),
+            Pool::ConcentratedLiquidityPool(pool) => pool.get_fee(),
+        }
+    }
+
+    /// Sets given pool's total fee.
+    pub fn set_fee(&mut self, total_fee: u32) {
+        match self {
+            Pool::SimplePool(pool) => pool.total_fee = total_fee,
+            Pool::StableSwapPool(pool) => pool.total_fee = total_fee,
+            Pool::RatedSwapPool(pool) => pool.total_fee = total_fee,
+            Pool::WeightedPool(_) => unimplemented!(),
+            Pool::ConcentratedLiquidityPool(_) => unimplemented!(),
         }
     }
 
@@ -140,15 +263,34 @@ impl Pool {
             Pool::SimplePool(pool) => pool.get_volumes(),
             Pool::StableSwapPool(pool) => pool.get_volumes(),
             Pool::RatedSwapPool(pool) => pool.get_volumes(),
+            Pool::WeightedPool(pool) => pool.get_volumes(),
+            Pool::ConcentratedLiquidityPool(pool) => pool.get_volumes(),
+        }
+    }
+
+    /// Returns spot price of each token denominated in the first one, given as fraction of PRICE_PRECISION.
+    /// None if the pool has no liquidity or its prices are not tracked.
+    pub fn get_spot_prices(&self) -> Option<Vec<U256>> {
+        match self {
+            Pool::SimplePool(pool) => pool.get_spot_prices(),
+            Pool::StableSwapPool(pool) => pool.get_spot_prices(),
+            Pool::RatedSwapPool(pool) => pool.get_spot_prices(),
+            Pool::WeightedPool(_) => None,
+            Pool::ConcentratedLiquidityPool(_) => None,
         }
     }
 
     /// Returns given pool's share price in precision 1e8.
-    pub fn get_share_price(&self) -> u128 {
+    /// Simple and weighted pools price shares in their first token, in whole tokens if its decimals are given,
+    /// otherwise in its smallest units per smallest unit of the share.
+    pub fn get_share_price(&self, first_token_decimals: Option<u8>) -> u128 {
+        let decimals = first_token_decimals.unwrap_or(self.get_share_decimal());
         match self {
-            Pool::SimplePool(_) => unimplemented!(),
+            Pool::SimplePool(pool) => pool.get_share_price(decimals),
             Pool::StableSwapPool(pool) => pool.get_share_price(),
             Pool::RatedSwapPool(pool) => pool.get_share_price(),
+            Pool::WeightedPool(pool) => pool.get_share_price(decimals),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR147_NO_SHARES.as_bytes()),
         }
     }
 
@@ -171,6 +313,55 @@ impl Pool {
             Pool::RatedSwapPool(pool) => {
                 pool.swap(token_in, amount_in, token_out, min_amount_out, &admin_fee)
             }
+            Pool::WeightedPool(pool) => {
+                pool.swap(token_in, amount_in, token_out, min_amount_out, &admin_fee)
+            }
+            Pool::ConcentratedLiquidityPool(pool) => {
+                pool.swap(token_in, amount_in, token_out, min_amount_out, &admin_fee)
+            }
+        }
+    }
+
+    /// Returns how many tokens of token_in one needs to receive given amount of token_out.
+    pub fn get_return_by_output(
+        &self,
+        token_in: &AccountId,
+        amount_out: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.get_return_by_output(token_in, amount_out, token_out),
+            Pool::StableSwapPool(pool) => pool.get_return_by_output(token_in, amount_out, token_out, fees),
+            Pool::RatedSwapPool(pool) => pool.get_return_by_output(token_in, amount_out, token_out, fees),
+            Pool::WeightedPool(pool) => pool.get_return_by_output(token_in, amount_out, token_out),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+        }
+    }
+
+    /// Swaps token_in for given number of token_out and returns spent amount.
+    pub fn swap_by_output(
+        &mut self,
+        token_in: &AccountId,
+        amount_out: Balance,
+        token_out: &AccountId,
+        max_amount_in: Balance,
+        admin_fee: AdminFees,
+    ) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => {
+                pool.swap_by_output(token_in, amount_out, token_out, max_amount_in, &admin_fee)
+            }
+            Pool::StableSwapPool(pool) => {
+                pool.swap_by_output(token_in, amount_out, token_out, max_amount_in, &admin_fee)
+            }
+            Pool::RatedSwapPool(pool) => {
+                pool.swap_by_output(token_in, amount_out, token_out, max_amount_in, &admin_fee)
+            }
+            Pool::WeightedPool(pool) => {
+                pool.swap_by_output(token_in, amount_out, token_out, max_amount_in, &admin_fee)
+            }
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
         }
     }
 
@@ -179,6 +370,8 @@ impl Pool {
             Pool::SimplePool(pool) => pool.share_total_balance(),
             Pool::StableSwapPool(pool) => pool.share_total_balance(),
             Pool::RatedSwapPool(pool) => pool.share_total_balance(),
+            Pool::WeightedPool(pool) => pool.share_total_balance(),
+            Pool::ConcentratedLiquidityPool(_) => 0,
         }
     }
 
@@ -187,6 +380,8 @@ impl Pool {
             Pool::SimplePool(pool) => pool.share_balance_of(account_id),
             Pool::StableSwapPool(pool) => pool.share_balance_of(account_id),
             Pool::RatedSwapPool(pool) => pool.share_balance_of(account_id),
+            Pool::WeightedPool(pool) => pool.share_balance_of(account_id),
+            Pool::ConcentratedLiquidityPool(_) => 0,
         }
     }
 
@@ -195,6 +390,26 @@ impl Pool {
             Pool::SimplePool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
             Pool::StableSwapPool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
             Pool::RatedSwapPool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
+            Pool::WeightedPool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR147_NO_SHARES.as_bytes()),
+        }
+    }
+
+    pub fn share_has_registered(&self, account_id: &AccountId) -> bool {
+        match self {
+            Pool::SimplePool(pool) => pool.shares.contains_key(account_id),
+            Pool::StableSwapPool(pool) => pool.shares.contains_key(account_id),
+            Pool::RatedSwapPool(pool) => pool.shares.contains_key(account_id),
+            Pool::WeightedPool(pool) => pool.shares.contains_key(account_id),
+            Pool::ConcentratedLiquidityPool(_) => false,
+        }
+    }
+
+    /// Returns id of the base pool for meta pool, None for other pools.
+    pub fn meta_base_pool_id(&self) -> Option<u64> {
+        match self {
+            Pool::RatedSwapPool(pool) => pool.meta_base_pool_id(),
+            _ => None,
         }
     }
 
@@ -203,6 +418,9 @@ impl Pool {
             Pool::SimplePool(pool) => pool.share_register(account_id),
             Pool::StableSwapPool(pool) => pool.share_register(account_id),
             Pool::RatedSwapPool(pool) => pool.share_register(account_id),
+            Pool::WeightedPool(pool) => pool.share_register(account_id),
+            // Liquidity is tracked by positions, no shares to register.
+            Pool::ConcentratedLiquidityPool(_) => {}
         }
     }
 
@@ -212,9 +430,11 @@ impl Pool {
         fees: &AdminFees,
     ) -> Balance {
         match self {
-            Pool::SimplePool(_) => unimplemented!(),
+            Pool::SimplePool(pool) => pool.predict_add_liquidity(amounts),
             Pool::StableSwapPool(pool) => pool.predict_add_stable_liquidity(amounts, fees),
             Pool::RatedSwapPool(_) => unimplemented!(),
+            Pool::WeightedPool(pool) => pool.predict_add_liquidity(amounts),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR147_NO_SHARES.as_bytes()),
         }
     }
 
@@ -223,9 +443,11 @@ impl Pool {
         shares: Balance,
     ) -> Vec<Balance> {
         match self {
-            Pool::SimplePool(_) => unimplemented!(),
+            Pool::SimplePool(pool) => pool.predict_remove_liquidity(shares),
             Pool::StableSwapPool(pool) => pool.predict_remove_liquidity(shares),
             Pool::RatedSwapPool(pool) => pool.predict_remove_liquidity(shares),
+            Pool::WeightedPool(pool) => pool.predict_remove_liquidity(shares),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR147_NO_SHARES.as_bytes()),
         }
     }
 
@@ -238,6 +460,23 @@ impl Pool {
             Pool::SimplePool(_) => unimplemented!(),
             Pool::StableSwapPool(pool) => pool.predict_remove_liquidity_by_tokens(amounts, fees),
             Pool::RatedSwapPool(pool) => pool.predict_remove_liquidity_by_tokens(amounts, fees),
+            Pool::WeightedPool(_) => unimplemented!(),
+            Pool::ConcentratedLiquidityPool(_) => unimplemented!(),
+        }
+    }
+
+    pub fn predict_remove_liquidity_one_coin(
+        &self,
+        shares: Balance,
+        token_id: &AccountId,
+        fees: &AdminFees,
+    ) -> Balance {
+        match self {
+            Pool::SimplePool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+            Pool::StableSwapPool(pool) => pool.predict_remove_liquidity_one_coin(shares, token_id, fees),
+            Pool::RatedSwapPool(pool) => pool.predict_remove_liquidity_one_coin(shares, token_id, fees),
+            Pool::WeightedPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
+            Pool::ConcentratedLiquidityPool(_) => env::panic(ERR80_NOT_SUPPORTED_BY_POOL.as_bytes()),
         }
     }
 
@@ -251,6 +490,8 @@ impl Pool {
             Pool::SimplePool(_) => unimplemented!(),
             Pool::StableSwapPool(_) => unimplemented!(),
             Pool::RatedSwapPool(pool) => pool.predict_add_rated_liquidity(amounts, rates, fees),
+            Pool::WeightedPool(_) => unimplemented!(),
+            Pool::ConcentratedLiquidityPool(_) => unimplemented!(),
         }
     }
 
@@ -264,6 +505,8 @@ impl Pool {
             Pool::SimplePool(_) => unimplemented!(),
             Pool::StableSwapPool(_) => unimplemented!(),
             Pool::RatedSwapPool(pool) => pool.predict_remove_rated_liquidity_by_tokens(amounts, rates, fees),
+            Pool::WeightedPool(_) => unimplemented!(),
+            Pool::ConcentratedLiquidityPool(_) => unimplemented!(),
         }
     }
 
@@ -279,6 +522,8 @@ impl Pool {
             Pool::SimplePool(_) => unimplemented!(),
             Pool::StableSwapPool(_) => unimplemented!(),
             Pool::RatedSwapPool(pool) => pool.get_rated_return(token_in, amount_in, token_out, rates, fees),
+            Pool::WeightedPool(_) => unimplemented!(),
+            Pool::ConcentratedLiquidityPool(_) => unimplemented!(),
         }
     }
 
@@ -286,15 +531,19 @@ impl Pool {
         match self {
             Pool::SimplePool(_) => unimplemented!(),
             Pool::StableSwapPool(_) => unimplemented!(),
-            Pool::RatedSwapPool(pool) => pool.rates.update(),
+            Pool::RatedSwapPool(pool) => pool.update_rates(),
+            Pool::WeightedPool(_) => unimplemented!(),
+            Pool::ConcentratedLiquidityPool(_) => unimplemented!(),
         }
     }
 
-    pub fn update_callback(&mut self, cross_call_result: &Vec<u8>) -> bool {
+    pub fn update_callback(&mut self, cross_call_results: &[Vec<u8>]) -> bool {
         match self {
             Pool::SimplePool(_) => unimplemented!(),
             Pool::StableSwapPool(_) => unimplemented!(),
-            Pool::RatedSwapPool(pool) => pool.rates.update_callback(cross_call_result),
+            Pool::RatedSwapPool(pool) => pool.rates.update_callback(cross_call_results),
+            Pool::WeightedPool(_) => unimplemented!(),
+            Pool::ConcentratedLiquidityPool(_) => unimplemented!(),
         }
     }
 }
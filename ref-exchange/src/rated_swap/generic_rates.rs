@@ -0,0 +1,161 @@
+use super::{rates::RatesTrait, PRECISION};
+use crate::errors::{
+    ERR123_ONE_PROMISE_RESULT, ERR126_FAILED_TO_PARSE_RESULT, ERR64_TOKENS_COUNT_ILLEGAL,
+};
+use crate::utils::{GAS_FOR_BASIC_OP, NO_DEPOSIT};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, json_types::U128, serde_json::from_slice, AccountId, Balance, EpochHeight, Promise,
+    PromiseOrValue,
+};
+
+/// Configuration of a rate provider contract.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct GenericRatesConfig {
+    /// View method of the provider, called without arguments.
+    /// Returns either a single rate or a list of rates, with precision of 1e24.
+    pub method_name: String,
+    /// For each token in the pool, index of its rate in the result, or none to keep the token at 1.0.
+    pub token_rate_indices: Vec<Option<u32>>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct GenericRates {
+    /// *
+    pub stored_rates: Vec<Balance>,
+    /// *
+    pub rates_updated_at: u64,
+    /// *
+    pub contract_id: AccountId,
+    /// *
+    pub method_name: String,
+    /// *
+    pub token_rate_indices: Vec<Option<u32>>,
+}
+
+impl RatesTrait for GenericRates {
+    fn updated_at(&self) -> EpochHeight {
+        self.rates_updated_at
+    }
+    fn get(&self) -> &Vec<Balance> {
+        &self.stored_rates
+    }
+    fn update(&self) -> PromiseOrValue<bool> {
+        Promise::new(self.contract_id.clone())
+            .function_call(
+                self.method_name.clone().into_bytes(),
+                b"{}".to_vec(),
+                NO_DEPOSIT,
+                GAS_FOR_BASIC_OP,
+            )
+            .into()
+    }
+    fn calls_count(&self) -> usize {
+        1
+    }
+    fn update_callback(&mut self, cross_call_results: &[Vec<u8>]) -> bool {
+        assert_eq!(cross_call_results.len(), 1, "{}", ERR123_ONE_PROMISE_RESULT);
+        let cross_call_result = &cross_call_results[0];
+        let rates: Vec<Balance> = if let Ok(rates) = from_slice::<Vec<U128>>(cross_call_result) {
+            rates.into_iter().map(|rate| rate.0).collect()
+        } else if let Ok(U128(rate)) = from_slice::<U128>(cross_call_result) {
+            vec![rate]
+        } else {
+            env::panic(ERR126_FAILED_TO_PARSE_RESULT.as_bytes());
+        };
+        let new_rates: Vec<Balance> = self
+            .token_rate_indices
+            .iter()
+            .map(|index| match index {
+                Some(index) => *rates
+                    .get(*index as usize)
+                    .expect(ERR126_FAILED_TO_PARSE_RESULT),
+                None => PRECISION,
+            })
+            .collect();
+        // A zero rate would price the token at nothing, so the stored rates are kept instead.
+        if new_rates.contains(&0) {
+            return false;
+        }
+        self.stored_rates = new_rates;
+        self.rates_updated_at = env::epoch_height();
+        true
+    }
+}
+
+impl GenericRates {
+    pub fn new(contract_id: AccountId, config: GenericRatesConfig, tokens_count: usize) -> Self {
+        assert_eq!(
+            config.token_rate_indices.len(),
+            tokens_count,
+            "{}",
+            ERR64_TOKENS_COUNT_ILLEGAL
+        );
+        Self {
+            stored_rates: vec![PRECISION; tokens_count], // all rates equals 1.0
+            rates_updated_at: 0,
+            contract_id,
+            method_name: config.method_name,
+            token_rate_indices: config.token_rate_indices,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+
+    fn new_rates(token_rate_indices: Vec<Option<u32>>) -> GenericRates {
+        GenericRates::new(
+            "provider".to_string(),
+            GenericRatesConfig {
+                method_name: "get_rates".to_string(),
+                token_rate_indices: token_rate_indices.clone(),
+            },
+            token_rate_indices.len(),
+        )
+    }
+
+    #[test]
+    fn test_generic_rates_update() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.epoch_height(1).build());
+        let mut rates = new_rates(vec![None, Some(0)]);
+        assert_eq!(rates.get(), &vec![PRECISION, PRECISION]);
+        assert_eq!(rates.updated_at(), 0);
+
+        rates.update_callback(&[br#""1100000000000000000000000""#.to_vec()]);
+        assert_eq!(rates.get(), &vec![PRECISION, 11 * PRECISION / 10]);
+        assert_eq!(rates.updated_at(), 1);
+
+        let mut rates = new_rates(vec![Some(1), None, Some(0)]);
+        rates.update_callback(&[br#"["1200000000000000000000000", "1300000000000000000000000"]"#.to_vec()]);
+        assert_eq!(rates.get(), &vec![13 * PRECISION / 10, PRECISION, 12 * PRECISION / 10]);
+    }
+
+    #[test]
+    fn test_generic_rates_zero() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.epoch_height(1).build());
+        let mut rates = new_rates(vec![Some(1), Some(0)]);
+        rates.update_callback(&[br#"["1200000000000000000000000", "1300000000000000000000000"]"#.to_vec()]);
+        testing_env!(context.epoch_height(2).build());
+        assert!(!rates.update_callback(&[br#"["1200000000000000000000000", "0"]"#.to_vec()]));
+        assert_eq!(rates.get(), &vec![13 * PRECISION / 10, 12 * PRECISION / 10]);
+        assert_eq!(rates.updated_at(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "E126: Failed to parse cross-contract call result")]
+    fn test_generic_rates_missing_index() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut rates = new_rates(vec![Some(1), None]);
+        rates.update_callback(&[br#""1100000000000000000000000""#.to_vec()]);
+    }
+}
@@ -0,0 +1,71 @@
+use super::{rates::RatesTrait, PRECISION};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, Balance, EpochHeight, PromiseOrValue};
+
+/// Precision of the share price returned by `get_share_price` of the pools.
+pub const SHARE_PRICE_PRECISION: u128 = 100_000_000;
+
+/// Rates of the meta pool, that holds shares of another pool of this contract.
+/// Share price is read from the base pool every time the meta pool is loaded, so the rates never expire.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct MetaRates {
+    /// *
+    pub stored_rates: Vec<Balance>,
+    /// Pool, which shares are held by the meta pool.
+    pub base_pool_id: u64,
+    /// Index of the base pool shares among the meta pool tokens.
+    pub share_index: u32,
+}
+
+impl RatesTrait for MetaRates {
+    fn updated_at(&self) -> EpochHeight {
+        env::epoch_height()
+    }
+    fn get(&self) -> &Vec<Balance> {
+        &self.stored_rates
+    }
+    fn update(&self) -> PromiseOrValue<bool> {
+        PromiseOrValue::Value(true)
+    }
+    fn calls_count(&self) -> usize {
+        0
+    }
+    fn update_callback(&mut self, _cross_call_results: &[Vec<u8>]) -> bool {
+        true
+    }
+}
+
+impl MetaRates {
+    pub fn new(base_pool_id: u64, share_index: u32, tokens_count: usize) -> Self {
+        Self {
+            stored_rates: vec![PRECISION; tokens_count], // all rates equals 1.0
+            base_pool_id,
+            share_index,
+        }
+    }
+
+    /// Sets the rate of base pool shares from the share price of the base pool.
+    pub fn set_share_price(&mut self, share_price: u128) {
+        self.stored_rates[self.share_index as usize] = share_price * (PRECISION / SHARE_PRICE_PRECISION);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+
+    #[test]
+    fn test_meta_rates() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.epoch_height(5).build());
+        let mut rates = MetaRates::new(0, 1, 2);
+        assert_eq!(rates.get(), &vec![PRECISION, PRECISION]);
+        assert_eq!(rates.updated_at(), 5);
+
+        rates.set_share_price(102_000_000);
+        assert_eq!(rates.get(), &vec![PRECISION, 102 * PRECISION / 100]);
+    }
+}
@@ -1,19 +1,23 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::LookupMap;
 use near_sdk::json_types::ValidAccountId;
-use near_sdk::{env, AccountId, Balance, Timestamp};
+use near_sdk::{env, AccountId, Balance, PromiseOrValue, Timestamp};
 
 use crate::admin_fee::AdminFees;
 use crate::errors::*;
 use crate::rated_swap::math::{
     Fees, RatedSwap, SwapResult, MAX_AMP, MAX_AMP_CHANGE, MIN_AMP, MIN_RAMP_DURATION,
 };
-use crate::utils::{add_to_collection, SwapVolume, FEE_DIVISOR, U256};
+use crate::utils::{add_to_collection, SwapVolume, FEE_DIVISOR, PRICE_PRECISION, U256, U384};
 use crate::StorageKey;
 
+use self::meta_rates::MetaRates;
 use self::rates::*;
 
 mod math;
+pub mod generic_rates;
+pub mod meta_rates;
+pub mod multi_rates;
 pub mod rates;
 mod stnear_rates;
 
@@ -51,9 +55,16 @@ pub struct RatedSwapPool {
     pub stop_amp_time: Timestamp,
     /// *
     pub rates: Rates,
+    /// Limits on the age of the rates, kept by the contract and set on loading the pool.
+    #[borsh_skip]
+    pub rates_freshness: RatesFreshness,
+    /// Time of the last update of the rates, kept by the contract and set on loading the pool.
+    #[borsh_skip]
+    pub rates_updated_timestamp: Timestamp,
 }
 
 impl RatedSwapPool {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: u32,
         token_account_ids: Vec<ValidAccountId>,
@@ -62,6 +73,47 @@ impl RatedSwapPool {
         total_fee: u32,
         rates_type: String,
         contract_id: AccountId,
+        rates_config: Option<RatesConfig>,
+    ) -> Self {
+        let rates = Rates::new(rates_type, contract_id, token_account_ids.len(), rates_config);
+        Self::new_with_rates(
+            id,
+            token_account_ids.into_iter().map(|a| a.into()).collect(),
+            token_decimals,
+            amp_factor,
+            total_fee,
+            rates,
+        )
+    }
+
+    /// Creates meta pool of given token and shares of the base pool, token `:base_pool_id`,
+    /// priced by the share price of the base pool.
+    pub fn new_meta(
+        id: u32,
+        token_account_id: ValidAccountId,
+        token_decimals: u8,
+        base_pool_id: u64,
+        share_decimals: u8,
+        amp_factor: u128,
+        total_fee: u32,
+    ) -> Self {
+        Self::new_with_rates(
+            id,
+            vec![token_account_id.into(), format!(":{}", base_pool_id)],
+            vec![token_decimals, share_decimals],
+            amp_factor,
+            total_fee,
+            Rates::Meta(MetaRates::new(base_pool_id, 1, 2)),
+        )
+    }
+
+    fn new_with_rates(
+        id: u32,
+        token_account_ids: Vec<AccountId>,
+        token_decimals: Vec<u8>,
+        amp_factor: u128,
+        total_fee: u32,
+        rates: Rates,
     ) -> Self {
         for decimal in token_decimals.clone().into_iter() {
             assert!(decimal <= MAX_DECIMAL, "{}", ERR60_DECIMAL_ILLEGAL);
@@ -74,10 +126,10 @@ impl RatedSwapPool {
         );
         assert!(total_fee < FEE_DIVISOR, "{}", ERR62_FEE_ILLEGAL);
         Self {
-            token_account_ids: token_account_ids.iter().map(|a| a.clone().into()).collect(),
-            token_decimals,
             c_amounts: vec![0u128; token_account_ids.len()],
             volumes: vec![SwapVolume::default(); token_account_ids.len()],
+            token_account_ids,
+            token_decimals,
             total_fee,
             shares: LookupMap::new(StorageKey::Shares { pool_id: id }),
             shares_total_supply: 0,
@@ -85,7 +137,9 @@ impl RatedSwapPool {
             target_amp_factor: amp_factor,
             init_amp_time: 0,
             stop_amp_time: 0,
-            rates: Rates::new(rates_type, contract_id, token_account_ids.len()),
+            rates,
+            rates_freshness: RatesFreshness::default(),
+            rates_updated_timestamp: 0,
         }
     }
 
@@ -138,12 +192,48 @@ impl RatedSwapPool {
     /// *
     fn assert_rates(&self) {
         assert!(
-            self.rates.are_actual(),
+            self.are_rates_fresh(),
             "{}",
             ERR120_RATES_EXPIRED
         );
     }
 
+    pub fn are_rates_fresh(&self) -> bool {
+        self.rates_freshness
+            .is_fresh(self.rates.updated_at(), self.rates_updated_timestamp)
+    }
+
+    /// Returns true if the rates are still fresh, otherwise promise to update them.
+    pub fn update_rates(&self) -> PromiseOrValue<bool> {
+        if self.are_rates_fresh() {
+            return PromiseOrValue::Value(true);
+        }
+        self.rates.update()
+    }
+
+    /// Returns id of the base pool, if this is a meta pool.
+    pub fn meta_base_pool_id(&self) -> Option<u64> {
+        match &self.rates {
+            Rates::Meta(rates) => Some(rates.base_pool_id),
+            _ => None,
+        }
+    }
+
+    /// Returns marginal price of each token denominated in the first one, in raw token amounts,
+    /// given as fraction of PRICE_PRECISION. None if the pool has no liquidity.
+    pub fn get_spot_prices(&self) -> Option<Vec<U256>> {
+        let c_prices = self.get_invariant_with_rates(self.rates.get())
+            .compute_spot_prices(&self.c_amounts, PRICE_PRECISION)?;
+        let decimals_0 = 10u128.pow(self.token_decimals[0] as u32);
+        c_prices
+            .into_iter()
+            .zip(self.token_decimals.iter())
+            .map(|(c_price, decimals)| {
+                (c_price * U384::from(decimals_0) / U384::from(10u128.pow(*decimals as u32))).to_u256()
+            })
+            .collect()
+    }
+
     pub fn get_amp(&self) -> u64 {
         if let Some(amp) = self.get_invariant_with_rates(self.rates.get()).compute_amp_factor() {
             amp as u64
@@ -495,6 +585,85 @@ impl RatedSwapPool {
         burn_shares
     }
 
+    /// Returns amount of the token, that removing given number of shares in this single token would give.
+    pub fn predict_remove_liquidity_one_coin(
+        &self,
+        shares: Balance,
+        token_id: &AccountId,
+        fees: &AdminFees,
+    ) -> Balance {
+        let index = self.token_index(token_id);
+        let (amount, _) = self.get_invariant_with_rates(self.rates.get())
+            .compute_withdraw_one_coin(
+                shares,
+                index,
+                &self.c_amounts,
+                self.shares_total_supply,
+                &Fees::new(self.total_fee, fees),
+            )
+            .expect(ERR67_LPSHARE_CALC_ERR);
+        self.c_amount_to_amount(amount, index)
+    }
+
+    /// Remove liquidity from the pool by burning given shares for a single token.
+    /// Fee will be charged according to diff between ideal token portions, as for removal by tokens.
+    pub fn remove_liquidity_one_coin(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        token_id: &AccountId,
+        min_amount: Balance,
+        fees: &AdminFees,
+    ) -> Balance {
+        self.assert_rates();
+
+        let index = self.token_index(token_id);
+        let prev_shares_amount = self.shares.get(sender_id).expect(ERR13_LP_NOT_REGISTERED);
+        assert!(
+            prev_shares_amount >= shares,
+            "{}",
+            ERR34_INSUFFICIENT_LP_SHARES
+        );
+
+        let trade_fee = Fees::new(self.total_fee, fees);
+        let (c_amount, fee) = self.get_invariant_with_rates(self.rates.get())
+            .compute_withdraw_one_coin(
+                shares,
+                index,
+                &self.c_amounts,
+                self.shares_total_supply,
+                &trade_fee,
+            )
+            .expect(ERR67_LPSHARE_CALC_ERR);
+        let amount = self.c_amount_to_amount(c_amount, index);
+        assert!(amount >= min_amount, "{}", ERR68_SLIPPAGE);
+
+        let admin_fee = trade_fee.admin_trade_fee(fee);
+        self.c_amounts[index] = self.c_amounts[index]
+            .checked_sub(c_amount)
+            .unwrap()
+            .checked_sub(admin_fee)
+            .unwrap();
+        self.assert_min_reserve(self.c_amounts[index]);
+        self.burn_shares(sender_id, prev_shares_amount, shares);
+        env::log(
+            format!(
+                "LP {} removed {} shares to gain {} {}, total fee {}, admin fee {}",
+                sender_id,
+                shares,
+                amount,
+                token_id,
+                self.c_amount_to_amount(fee, index),
+                self.c_amount_to_amount(admin_fee, index)
+            )
+            .as_bytes(),
+        );
+
+        self.distribute_admin_fee(index, admin_fee, fees);
+
+        amount
+    }
+
     /// Returns number of tokens in outcome, given amount.
     /// Tokens are provided as indexes into token list for given pool.
     /// All tokens are comparable tokens
@@ -563,6 +732,32 @@ impl RatedSwapPool {
         self.c_amount_to_amount(c_amount_out, self.token_index(token_out))
     }
 
+    /// Same as `get_return` with cached rates, but returns None instead of failing,
+    /// if the pool is empty or the swap can't be made, for example it would leave less than MIN_RESERVE.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Option<Balance> {
+        let (in_idx, out_idx) = (self.token_index(token_in), self.token_index(token_out));
+        if in_idx == out_idx || amount_in == 0 || self.c_amounts.contains(&0) {
+            return None;
+        }
+        let result = self.get_invariant_with_rates(self.rates.get()).swap_to(
+            in_idx,
+            self.amount_to_c_amount(amount_in, in_idx),
+            out_idx,
+            &self.c_amounts,
+            &Fees::new(self.total_fee, fees),
+        )?;
+        if result.new_destination_amount < MIN_RESERVE {
+            return None;
+        }
+        Some(self.c_amount_to_amount(result.amount_swapped, out_idx))
+    }
+
     /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
     /// Assuming that `token_amount_in` was already received from `sender_id`.
     pub fn swap(
@@ -611,23 +806,99 @@ impl RatedSwapPool {
         self.volumes[out_idx].output.0 += amount_swapped;
 
         // handle admin / referral fee.
+        self.distribute_admin_fee(out_idx, result.admin_fee, fees);
+
+        amount_swapped
+    }
+
+    /// Returns number of tokens needed as input to receive given amount of token_out.
+    /// Tokens are provided as indexes into token list for given pool.
+    fn internal_get_return_by_output(
+        &self,
+        token_in: usize,
+        amount_out: Balance,
+        token_out: usize,
+        fees: &AdminFees,
+    ) -> Balance {
+        // make amount into comparable-amount, rounding up to receive not less than requested.
+        let mut c_amount_out = self.amount_to_c_amount(amount_out, token_out);
+        if self.c_amount_to_amount(c_amount_out, token_out) < amount_out {
+            c_amount_out += 1;
+        }
+        let c_amount_in = self.get_invariant_with_rates(self.rates.get())
+            .swap_from(
+                token_in,
+                token_out,
+                c_amount_out,
+                &self.c_amounts,
+                &Fees::new(self.total_fee, fees),
+            )
+            .expect(ERR70_SWAP_OUT_CALC_ERR);
+        let amount_in = self.c_amount_to_amount(c_amount_in, token_in);
+        if self.amount_to_c_amount(amount_in, token_in) < c_amount_in {
+            amount_in + 1
+        } else {
+            amount_in
+        }
+    }
+
+    /// Returns how much of `token_in` is needed to receive `amount_out` of `token_out`.
+    pub fn get_return_by_output(
+        &self,
+        token_in: &AccountId,
+        amount_out: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Balance {
+        assert_ne!(token_in, token_out, "{}", ERR71_SWAP_DUP_TOKENS);
+        self.internal_get_return_by_output(
+            self.token_index(token_in),
+            amount_out,
+            self.token_index(token_out),
+            fees,
+        )
+    }
+
+    /// Swap `token_in` into exactly `amount_out` of `token_out` and return how much of `token_in` was used.
+    /// Assuming that `max_amount_in` was already received from `sender_id`.
+    pub fn swap_by_output(
+        &mut self,
+        token_in: &AccountId,
+        amount_out: Balance,
+        token_out: &AccountId,
+        max_amount_in: Balance,
+        fees: &AdminFees,
+    ) -> Balance {
+        let amount_in = self.get_return_by_output(token_in, amount_out, token_out, fees);
+        assert!(amount_in <= max_amount_in, "{}", ERR68_SLIPPAGE);
+        let amount_swapped = self.swap(token_in, amount_in, token_out, amount_out, fees);
+        // Input is rounded up, so a bit more than requested may be swapped, that rest stays in the pool.
+        let out_idx = self.token_index(token_out);
+        let rest = amount_swapped - amount_out;
+        self.c_amounts[out_idx] += self.amount_to_c_amount(rest, out_idx);
+        self.volumes[out_idx].output.0 -= rest;
+        amount_in
+    }
+
+    /// Converts admin fee, left in the pool in given token, into shares of the referral and the exchange.
+    fn distribute_admin_fee(&mut self, token_idx: usize, admin_fee: Balance, fees: &AdminFees) {
         if fees.referral_fee + fees.exchange_fee > 0 {
             let mut fee_token = 0_u128;
             // referral fee
             if let Some(referral) = &fees.referral_id {
                 if self.shares.get(referral).is_some() {
-                    fee_token = result.admin_fee * fees.referral_fee as u128
+                    fee_token = admin_fee * fees.referral_fee as u128
                         / (fees.referral_fee + fees.exchange_fee) as u128;
                     if fee_token > 0 {
                         let referral_share =
-                            self.admin_fee_to_liquidity(referral, out_idx, fee_token);
+                            self.admin_fee_to_liquidity(referral, token_idx, fee_token);
                         env::log(
                             format!(
                                 "Referral {} got {} shares from {} {}",
                                 referral,
                                 referral_share,
-                                self.c_amount_to_amount(fee_token, out_idx),
-                                self.token_account_ids[out_idx]
+                                self.c_amount_to_amount(fee_token, token_idx),
+                                self.token_account_ids[token_idx]
                             )
                             .as_bytes(),
                         );
@@ -635,24 +906,22 @@ impl RatedSwapPool {
                 }
             }
             // exchange fee = admin_fee - referral_fee
-            fee_token = result.admin_fee - fee_token;
+            fee_token = admin_fee - fee_token;
             if fee_token > 0 {
                 let exchange_share =
-                    self.admin_fee_to_liquidity(&fees.exchange_id, out_idx, fee_token);
+                    self.admin_fee_to_liquidity(&fees.exchange_id, token_idx, fee_token);
                 env::log(
                     format!(
                         "Admin {} got {} shares from {} {}",
                         &fees.exchange_id,
                         exchange_share,
-                        self.c_amount_to_amount(fee_token, out_idx),
-                        self.token_account_ids[out_idx]
+                        self.c_amount_to_amount(fee_token, token_idx),
+                        self.token_account_ids[token_idx]
                     )
                     .as_bytes(),
                 );
             }
         }
-
-        amount_swapped
     }
 
     /// convert admin_fee into shares without any fee.
@@ -825,6 +1094,7 @@ mod tests {
             total_fee,
             "STNEAR".to_owned(),
             AccountId::from("remote"),
+            None,
         )
     }
 
@@ -952,7 +1222,8 @@ mod tests {
         );
 
         match &mut pool.rates {
-            Rates::Stnear(rates) => rates.stored_rates = vec![2 * PRECISION, 1 * PRECISION]
+            Rates::Stnear(rates) => rates.stored_rates = vec![2 * PRECISION, 1 * PRECISION],
+            _ => unreachable!(),
         }
 
         let mut amounts = vec![100000 * PRECISION, 200000 * PRECISION];
@@ -963,6 +1234,27 @@ mod tests {
         assert_eq!(pool.c_amounts, vec![100001 * PRECISION, 199998_000000009995002449799089]);
     }
 
+    #[test]
+    fn test_rated_remove_liquidity_one_coin() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let fees = AdminFees::zero();
+        let mut pool = new_rated_stnear_pool(TARGET_DECIMAL, 1000, 0);
+        match &mut pool.rates {
+            Rates::Stnear(rates) => rates.stored_rates = vec![2 * PRECISION, 1 * PRECISION],
+            _ => unreachable!(),
+        }
+
+        let mut amounts = vec![100000 * PRECISION, 200000 * PRECISION];
+        let num_shares = pool.add_liquidity(accounts(0).as_ref(), &mut amounts, 1, &fees);
+        let predicted = pool.predict_remove_liquidity_one_coin(num_shares / 10, accounts(1).as_ref(), &fees);
+        let amount = pool.remove_liquidity_one_coin(accounts(0).as_ref(), num_shares / 10, accounts(1).as_ref(), 1, &fees);
+        assert_eq!(amount, predicted);
+        // a tenth of the pool value, in the token that is worth 2 of the other one
+        assert!(amount < 20000 * PRECISION && amount > 19990 * PRECISION);
+        assert_eq!(pool.c_amounts, vec![100000 * PRECISION - amount, 200000 * PRECISION]);
+    }
+
     #[test]
     fn test_rated_max() {
         let mut context = VMContextBuilder::new();
@@ -995,7 +1287,8 @@ mod tests {
             1000, 
             0,
             "STNEAR".to_owned(),
-            AccountId::from("remote")
+            AccountId::from("remote"),
+            None,
         );
         assert_eq!(
             pool.tokens(),
@@ -1091,6 +1384,35 @@ mod tests {
     }
 
     /// Test everything with fees.
+    #[test]
+    fn test_rated_swap_by_output() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let fees = AdminFees::new(1000);
+        for decimals in vec![6, 18, 24] {
+            let one = 10u128.pow(decimals as u32);
+            let mut pool = new_rated_stnear_pool(decimals, 100, 25);
+            let mut amounts = vec![1000 * one, 1000 * one];
+            let _ = pool.add_liquidity(accounts(0).as_ref(), &mut amounts, 1, &fees);
+            for amount_out in vec![1, one / 3, 10 * one + 7, 900 * one] {
+                let amount_in = pool.get_return_by_output(
+                    accounts(1).as_ref(), amount_out, accounts(2).as_ref(), &fees);
+                // Input is enough, but not much more than needed.
+                assert!(pool.get_return(accounts(1).as_ref(), amount_in, accounts(2).as_ref(), &fees) >= amount_out);
+                if amount_out > one {
+                    let less = amount_in - amount_in / 10000;
+                    assert!(pool.get_return(accounts(1).as_ref(), less, accounts(2).as_ref(), &fees) < amount_out);
+                }
+            }
+            let c_amounts = pool.c_amounts.clone();
+            let amount_in = pool.swap_by_output(
+                accounts(1).as_ref(), 10 * one, accounts(2).as_ref(), 11 * one, &fees);
+            assert!(amount_in > 10 * one && amount_in < 11 * one);
+            assert_eq!(pool.c_amounts[0], c_amounts[0] + pool.amount_to_c_amount(amount_in, 0));
+            assert_eq!(pool.volumes[1].output.0, 10 * one);
+        }
+    }
+
     #[test]
     fn test_rated_with_fees() {
         let mut context = VMContextBuilder::new();
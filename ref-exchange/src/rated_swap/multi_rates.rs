@@ -0,0 +1,190 @@
+use super::{rates::RatesTrait, PRECISION};
+use crate::errors::{
+    ERR126_FAILED_TO_PARSE_RESULT, ERR128_NO_RATE_SOURCES, ERR129_WRONG_PROMISE_RESULTS_COUNT,
+    ERR64_TOKENS_COUNT_ILLEGAL,
+};
+use crate::utils::{GAS_FOR_BASIC_OP, NO_DEPOSIT};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, json_types::U128, serde_json::from_slice, AccountId, Balance, EpochHeight, Promise,
+    PromiseOrValue,
+};
+
+/// Rate oracle of a single token.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct RateSource {
+    pub contract_id: AccountId,
+    /// View method of the oracle, called without arguments, returns the rate with precision of 1e24.
+    pub method_name: String,
+}
+
+/// Configuration of the rates, each token of the pool takes its rate from its own source.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct MultiRatesConfig {
+    /// For each token in the pool, source of its rate, or none to keep the token at 1.0.
+    pub rate_sources: Vec<Option<RateSource>>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct MultiRates {
+    /// *
+    pub stored_rates: Vec<Balance>,
+    /// *
+    pub rates_updated_at: u64,
+    /// *
+    pub rate_sources: Vec<Option<RateSource>>,
+}
+
+impl RatesTrait for MultiRates {
+    fn updated_at(&self) -> EpochHeight {
+        self.rates_updated_at
+    }
+    fn get(&self) -> &Vec<Balance> {
+        &self.stored_rates
+    }
+    fn update(&self) -> PromiseOrValue<bool> {
+        // All the sources are queried in parallel, results come in the order of tokens.
+        self.rate_sources
+            .iter()
+            .flatten()
+            .map(|source| {
+                Promise::new(source.contract_id.clone()).function_call(
+                    source.method_name.clone().into_bytes(),
+                    b"{}".to_vec(),
+                    NO_DEPOSIT,
+                    GAS_FOR_BASIC_OP,
+                )
+            })
+            .reduce(|joint, promise| joint.and(promise))
+            .unwrap()
+            .into()
+    }
+    fn calls_count(&self) -> usize {
+        self.rate_sources.iter().flatten().count()
+    }
+    fn update_callback(&mut self, cross_call_results: &[Vec<u8>]) -> bool {
+        assert_eq!(
+            cross_call_results.len(),
+            self.calls_count(),
+            "{}",
+            ERR129_WRONG_PROMISE_RESULTS_COUNT
+        );
+        let mut results = cross_call_results.iter();
+        let new_rates: Vec<Balance> = self
+            .rate_sources
+            .iter()
+            .map(|source| match source {
+                Some(_) => match from_slice::<U128>(results.next().unwrap()) {
+                    Ok(U128(rate)) => rate,
+                    Err(_) => env::panic(ERR126_FAILED_TO_PARSE_RESULT.as_bytes()),
+                },
+                None => PRECISION,
+            })
+            .collect();
+        // A zero rate would price the token at nothing, so the stored rates are kept instead.
+        if new_rates.contains(&0) {
+            return false;
+        }
+        self.stored_rates = new_rates;
+        self.rates_updated_at = env::epoch_height();
+        true
+    }
+}
+
+impl MultiRates {
+    pub fn new(config: MultiRatesConfig, tokens_count: usize) -> Self {
+        assert_eq!(
+            config.rate_sources.len(),
+            tokens_count,
+            "{}",
+            ERR64_TOKENS_COUNT_ILLEGAL
+        );
+        assert!(
+            config.rate_sources.iter().any(Option::is_some),
+            "{}",
+            ERR128_NO_RATE_SOURCES
+        );
+        Self {
+            stored_rates: vec![PRECISION; tokens_count], // all rates equals 1.0
+            rates_updated_at: 0,
+            rate_sources: config.rate_sources,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+
+    fn new_rates(rate_sources: Vec<Option<&str>>) -> MultiRates {
+        let tokens_count = rate_sources.len();
+        MultiRates::new(
+            MultiRatesConfig {
+                rate_sources: rate_sources
+                    .into_iter()
+                    .map(|contract_id| {
+                        contract_id.map(|contract_id| RateSource {
+                            contract_id: contract_id.to_string(),
+                            method_name: "get_rate".to_string(),
+                        })
+                    })
+                    .collect(),
+            },
+            tokens_count,
+        )
+    }
+
+    #[test]
+    fn test_multi_rates_update() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.epoch_height(1).build());
+        let mut rates = new_rates(vec![Some("stnear"), Some("nearx"), None]);
+        assert_eq!(rates.updated_at(), 0);
+
+        rates.update_callback(&[
+            br#""1200000000000000000000000""#.to_vec(),
+            br#""1100000000000000000000000""#.to_vec(),
+        ]);
+        assert_eq!(
+            rates.get(),
+            &vec![12 * PRECISION / 10, 11 * PRECISION / 10, PRECISION]
+        );
+        assert_eq!(rates.updated_at(), 1);
+    }
+
+    #[test]
+    fn test_multi_rates_zero() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.epoch_height(1).build());
+        let mut rates = new_rates(vec![Some("stnear"), Some("nearx")]);
+        assert!(!rates.update_callback(&[
+            br#""1200000000000000000000000""#.to_vec(),
+            br#""0""#.to_vec(),
+        ]));
+        assert_eq!(rates.get(), &vec![PRECISION, PRECISION]);
+        assert_eq!(rates.updated_at(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E128: At least one rate source is required")]
+    fn test_multi_rates_without_sources() {
+        testing_env!(VMContextBuilder::new().build());
+        new_rates(vec![None, None]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E129: Cross-contract call results don't match rate sources")]
+    fn test_multi_rates_missing_result() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut rates = new_rates(vec![Some("stnear"), Some("nearx"), None]);
+        rates.update_callback(&[br#""1200000000000000000000000""#.to_vec()]);
+    }
+}
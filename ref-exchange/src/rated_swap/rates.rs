@@ -1,55 +1,149 @@
+use super::generic_rates::{GenericRates, GenericRatesConfig};
+use super::meta_rates::MetaRates;
+use super::multi_rates::{MultiRates, MultiRatesConfig};
 use super::stnear_rates::StnearRates;
+use crate::errors::ERR127_ILLEGAL_RATES_CONFIG;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{AccountId, Balance, PromiseOrValue};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, AccountId, Balance, EpochHeight, PromiseOrValue, Timestamp};
 
 #[derive(BorshSerialize, BorshDeserialize)]
 pub enum Rates {
     Stnear(StnearRates),
+    Generic(GenericRates),
+    Multi(MultiRates),
+    Meta(MetaRates),
+}
+
+/// Configuration of the rates, that can't be described by the rates contract alone.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(untagged)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub enum RatesConfig {
+    Generic(GenericRatesConfig),
+    Multi(MultiRatesConfig),
+}
+
+/// Limits on the age of cached rates, rates are expired if any of them is exceeded.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct RatesFreshness {
+    /// Number of seconds since the update.
+    pub max_age_sec: Option<u32>,
+    /// Number of epochs since the update, 0 means rates expire at the end of the epoch.
+    pub max_age_epochs: Option<u64>,
+}
+
+impl Default for RatesFreshness {
+    fn default() -> Self {
+        Self {
+            max_age_sec: None,
+            max_age_epochs: Some(0),
+        }
+    }
+}
+
+impl RatesFreshness {
+    pub fn is_valid(&self) -> bool {
+        self.max_age_sec.is_some() || self.max_age_epochs.is_some()
+    }
+
+    pub fn is_fresh(&self, updated_epoch: EpochHeight, updated_timestamp: Timestamp) -> bool {
+        if let Some(max_age) = self.max_age_epochs {
+            if env::epoch_height() > updated_epoch + max_age {
+                return false;
+            }
+        }
+        if let Some(max_age) = self.max_age_sec {
+            if env::block_timestamp() > updated_timestamp + max_age as u64 * 1_000_000_000 {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 pub trait RatesTrait {
-    /// Check that cached rates are actual
-    fn are_actual(&self) -> bool;
+    /// Get epoch height of the last update of cached rates
+    fn updated_at(&self) -> EpochHeight;
     /// Get cached rates vector
     fn get(&self) -> &Vec<Balance>;
     /// Update cached rates
-    ///  if cached rates are actual returns true
-    ///  else returns cross-contract call promise
+    ///  returns cross-contract call promise to get new rates
     fn update(&self) -> PromiseOrValue<bool>;
+    /// Get number of cross-contract calls joined in the update promise
+    fn calls_count(&self) -> usize;
     /// Update callback
-    ///  receives JSON encoded cross-contract call result
-    ///  and updates cached rates
-    fn update_callback(&mut self, cross_call_result: &Vec<u8>) -> bool;
+    ///  receives JSON encoded results of all the cross-contract calls
+    ///  and updates cached rates,
+    ///  returns false and keeps the cached rates if the new ones can't be applied
+    fn update_callback(&mut self, cross_call_results: &[Vec<u8>]) -> bool;
 }
 
 impl RatesTrait for Rates {
-    fn are_actual(&self) -> bool {
+    fn updated_at(&self) -> EpochHeight {
         match self {
-            Rates::Stnear(rates) => rates.are_actual(),
+            Rates::Stnear(rates) => rates.updated_at(),
+            Rates::Generic(rates) => rates.updated_at(),
+            Rates::Multi(rates) => rates.updated_at(),
+            Rates::Meta(rates) => rates.updated_at(),
         }
     }
     fn get(&self) -> &Vec<Balance> {
         match self {
             Rates::Stnear(rates) => rates.get(),
+            Rates::Generic(rates) => rates.get(),
+            Rates::Multi(rates) => rates.get(),
+            Rates::Meta(rates) => rates.get(),
         }
     }
     fn update(&self) -> PromiseOrValue<bool> {
         match self {
             Rates::Stnear(rates) => rates.update(),
+            Rates::Generic(rates) => rates.update(),
+            Rates::Multi(rates) => rates.update(),
+            Rates::Meta(rates) => rates.update(),
+        }
+    }
+    fn calls_count(&self) -> usize {
+        match self {
+            Rates::Stnear(rates) => rates.calls_count(),
+            Rates::Generic(rates) => rates.calls_count(),
+            Rates::Multi(rates) => rates.calls_count(),
+            Rates::Meta(rates) => rates.calls_count(),
         }
     }
-    fn update_callback(&mut self, cross_call_result: &Vec<u8>) -> bool {
+    fn update_callback(&mut self, cross_call_results: &[Vec<u8>]) -> bool {
         match self {
-            Rates::Stnear(rates) => rates.update_callback(cross_call_result),
+            Rates::Stnear(rates) => rates.update_callback(cross_call_results),
+            Rates::Generic(rates) => rates.update_callback(cross_call_results),
+            Rates::Multi(rates) => rates.update_callback(cross_call_results),
+            Rates::Meta(rates) => rates.update_callback(cross_call_results),
         }
     }
 }
 
 impl Rates {
-    pub fn new(rates_type: String, contract_id: AccountId, tokens_count: usize) -> Self {
-        match rates_type.as_str() {
-            "STNEAR" => Rates::Stnear(StnearRates::new(contract_id, tokens_count)),
-            _ => unimplemented!(),
+    /// rates_type: "STNEAR" for stNEAR price from Meta Pool,
+    /// "GENERIC" for rates from a view method of `contract_id`, described by `config`,
+    /// "MULTI" for a separate rate source of each token, described by `config`, `contract_id` is not used.
+    pub fn new(
+        rates_type: String,
+        contract_id: AccountId,
+        tokens_count: usize,
+        config: Option<RatesConfig>,
+    ) -> Self {
+        match (rates_type.as_str(), config) {
+            ("STNEAR", _) => Rates::Stnear(StnearRates::new(contract_id, tokens_count)),
+            ("GENERIC", Some(RatesConfig::Generic(config))) => {
+                Rates::Generic(GenericRates::new(contract_id, config, tokens_count))
+            }
+            ("MULTI", Some(RatesConfig::Multi(config))) => {
+                Rates::Multi(MultiRates::new(config, tokens_count))
+            }
+            _ => env::panic(ERR127_ILLEGAL_RATES_CONFIG.as_bytes()),
         }
     }
 }
@@ -1,9 +1,10 @@
 use super::{rates::RatesTrait, PRECISION};
-use crate::errors::ERR126_FAILED_TO_PARSE_RESULT;
+use crate::errors::{ERR123_ONE_PROMISE_RESULT, ERR126_FAILED_TO_PARSE_RESULT};
 use crate::utils::{GAS_FOR_BASIC_OP, NO_DEPOSIT};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::{
-    env, ext_contract, json_types::U128, serde_json::from_slice, AccountId, Balance, PromiseOrValue,
+    env, ext_contract, json_types::U128, serde_json::from_slice, AccountId, Balance, EpochHeight,
+    PromiseOrValue,
 };
 
 #[ext_contract(ext_metapool)]
@@ -23,22 +24,25 @@ pub struct StnearRates {
 }
 
 impl RatesTrait for StnearRates {
-    fn are_actual(&self) -> bool {
-        self.rates_updated_at == env::epoch_height()
+    fn updated_at(&self) -> EpochHeight {
+        self.rates_updated_at
     }
     fn get(&self) -> &Vec<Balance> {
         &self.stored_rates
     }
     fn update(&self) -> PromiseOrValue<bool> {
-        if self.are_actual() {
-            return PromiseOrValue::Value(true);
-        }
-
         ext_metapool::get_st_near_price(&self.contract_id, NO_DEPOSIT, GAS_FOR_BASIC_OP).into()
     }
-    fn update_callback(&mut self, cross_call_result: &Vec<u8>) -> bool {
-        if let Ok(U128(price)) = from_slice::<U128>(cross_call_result) {
-            self.stored_rates = vec![price, 1 * PRECISION];
+    fn calls_count(&self) -> usize {
+        1
+    }
+    fn update_callback(&mut self, cross_call_results: &[Vec<u8>]) -> bool {
+        assert_eq!(cross_call_results.len(), 1, "{}", ERR123_ONE_PROMISE_RESULT);
+        if let Ok(U128(price)) = from_slice::<U128>(&cross_call_results[0]) {
+            // stNEAR is the first token, others stay at 1.0
+            let mut rates = vec![1 * PRECISION; self.stored_rates.len()];
+            rates[0] = price;
+            self.stored_rates = rates;
             self.rates_updated_at = env::epoch_height();
         } else {
             env::panic(ERR126_FAILED_TO_PARSE_RESULT.as_bytes());
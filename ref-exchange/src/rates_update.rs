@@ -0,0 +1,177 @@
+//! Refreshing stale rates of the rated pools as part of the actions, that can't be batched with `update_pool_rates`.
+//! The action is scheduled after the rate calls and resumes in the callback once the rates are applied.
+
+use near_sdk::Gas;
+
+use crate::rated_swap::rates::RatesTrait;
+use crate::*;
+
+impl Contract {
+    /// Returns joint promise fetching rates of the rated pools with stale rates among given pools,
+    /// together with ids of these pools and number of rate calls made for each of them.
+    /// Returns None if all the rates are fresh.
+    pub(crate) fn internal_update_stale_rates(
+        &self,
+        pool_ids: impl Iterator<Item = u64>,
+    ) -> Option<(Promise, Vec<(u64, u32)>)> {
+        let mut rate_updates: Vec<(u64, u32)> = vec![];
+        let mut promise: Option<Promise> = None;
+        for pool_id in pool_ids {
+            if rate_updates.iter().any(|(id, _)| *id == pool_id) {
+                continue;
+            }
+            if let Pool::RatedSwapPool(pool) = self.internal_get_pool(pool_id) {
+                if let PromiseOrValue::Promise(update) = pool.update_rates() {
+                    rate_updates.push((pool_id, pool.rates.calls_count() as u32));
+                    promise = Some(match promise {
+                        Some(promise) => promise.and(update),
+                        None => update,
+                    });
+                }
+            }
+        }
+        promise.map(|promise| (promise, rate_updates))
+    }
+
+    /// Gas left for the callback after the rate calls,
+    /// keeping some for creating the receipts and finishing the current call.
+    pub(crate) fn internal_callback_gas(&self, rate_updates: &[(u64, u32)]) -> Gas {
+        let calls_count: u32 = rate_updates.iter().map(|(_, count)| count).sum();
+        (env::prepaid_gas() - env::used_gas())
+            .saturating_sub(GAS_FOR_BASIC_OP * (calls_count as Gas + 2))
+    }
+
+    /// Applies results of the rate calls joined by `internal_update_stale_rates`.
+    /// Returns false and keeps all the rates if any of the calls failed.
+    pub(crate) fn internal_apply_rates_results(&mut self, rate_updates: &[(u64, u32)]) -> bool {
+        let mut cross_call_results = vec![];
+        for index in 0..env::promise_results_count() {
+            match env::promise_result(index) {
+                PromiseResult::Successful(result) => cross_call_results.push(result),
+                _ => return false,
+            }
+        }
+        let calls_count: u32 = rate_updates.iter().map(|(_, count)| count).sum();
+        assert_eq!(
+            cross_call_results.len(),
+            calls_count as usize,
+            "{}",
+            ERR129_WRONG_PROMISE_RESULTS_COUNT
+        );
+        let mut start = 0;
+        for (pool_id, count) in rate_updates {
+            let end = start + *count as usize;
+            self.internal_apply_pool_rates(*pool_id, &cross_call_results[start..end]);
+            start = end;
+        }
+        true
+    }
+
+    /// Updates cached rates of the pool from results of its rate calls.
+    pub(crate) fn internal_apply_pool_rates(&mut self, pool_id: u64, cross_call_results: &[Vec<u8>]) {
+        let mut pool = self.internal_get_pool(pool_id);
+        assert!(pool.update_callback(cross_call_results), "{}", ERR125_FAILED_TO_APPLY_RATES);
+        self.pools.replace(pool_id, &pool);
+        self.pool_rates_updated_at.insert(&pool_id, &env::block_timestamp());
+        self.internal_update_pool_observations(pool_id, &pool);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Executes instant swap, that was waiting for the rates or was made refundable, on tokens transferred by `sender_id`.
+    /// Returns amount to refund to the sender, all of it if the rates can't be fetched or the deadline expired.
+    #[private]
+    #[allow(clippy::too_many_arguments)]
+    pub fn callback_instant_swap_with_rates(
+        &mut self,
+        sender_id: AccountId,
+        token_in: AccountId,
+        amount_in: U128,
+        referral_id: Option<AccountId>,
+        actions: Vec<Action>,
+        deadline: Option<Deadline>,
+        failure_policy: Option<SwapFailurePolicy>,
+        rate_updates: Vec<(u64, u32)>,
+    ) -> U128 {
+        if !self.internal_apply_rates_results(&rate_updates) {
+            log!("Failed to update rates, refund {} {} to {}", amount_in.0, token_in, sender_id);
+            return amount_in;
+        }
+        // Refunds instead of failing, so that the fetched rates are kept.
+        if matches!(&deadline, Some(deadline) if deadline.is_expired()) {
+            log!("Deadline expired, refund {} {} to {}", amount_in.0, token_in, sender_id);
+            return amount_in;
+        }
+        self.assert_contract_running();
+        let out_amounts = self.internal_direct_actions(token_in, amount_in.0, referral_id, &actions);
+        for (token_out, amount_out) in out_amounts.into_iter() {
+            if failure_policy == Some(SwapFailurePolicy::RefundOrDeposit) {
+                self.internal_send_tokens_or_deposit(&sender_id, &token_out, amount_out);
+            } else {
+                self.internal_send_tokens(&sender_id, &token_out, amount_out);
+            }
+        }
+        U128(0)
+    }
+
+    /// Executes actions on the deposits of the sender, that were waiting for the rates.
+    #[private]
+    pub fn callback_execute_actions_with_rates(
+        &mut self,
+        sender_id: AccountId,
+        actions: Vec<Action>,
+        referral_id: Option<AccountId>,
+        deadline: Option<Deadline>,
+        rate_updates: Vec<(u64, u32)>,
+    ) -> ActionResult {
+        assert!(self.internal_apply_rates_results(&rate_updates), "{}", ERR124_CROSS_CALL_FAILED);
+        self.assert_contract_running();
+        if let Some(deadline) = deadline {
+            deadline.assert_not_expired();
+        }
+        self.internal_execute_deposit_actions(&sender_id, &referral_id, &actions)
+    }
+
+    /// Same as `callback_execute_actions_with_rates` for `swap`, returns the received amount.
+    #[private]
+    pub fn callback_swap_with_rates(
+        &mut self,
+        sender_id: AccountId,
+        actions: Vec<Action>,
+        referral_id: Option<AccountId>,
+        deadline: Option<Deadline>,
+        rate_updates: Vec<(u64, u32)>,
+    ) -> U128 {
+        U128(
+            self.callback_execute_actions_with_rates(sender_id, actions, referral_id, deadline, rate_updates)
+                .to_amount(),
+        )
+    }
+
+    /// Adds liquidity to the rated pool, that was waiting for the rates.
+    /// Storage of the shares is paid from the storage deposit of the sender.
+    #[private]
+    pub fn callback_add_rated_liquidity(
+        &mut self,
+        sender_id: AccountId,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        min_shares: U128,
+        rate_updates: Vec<(u64, u32)>,
+    ) -> U128 {
+        assert!(self.internal_apply_rates_results(&rate_updates), "{}", ERR124_CROSS_CALL_FAILED);
+        self.assert_contract_running();
+        let prev_storage = env::storage_usage();
+        let mint_shares = self.internal_add_stable_liquidity(&sender_id, pool_id, amounts, min_shares.into());
+        let storage_cost = env::storage_usage().saturating_sub(prev_storage) as Balance
+            * env::storage_byte_cost();
+        let mut account = self.internal_unwrap_account(&sender_id);
+        assert!(account.storage_available() >= storage_cost, "{}", ERR11_INSUFFICIENT_STORAGE);
+        account.near_amount -= storage_cost;
+        self.internal_save_account(&sender_id, account);
+        self.internal_update_pool_observations(pool_id, &self.internal_get_pool(pool_id));
+
+        mint_shares.into()
+    }
+}
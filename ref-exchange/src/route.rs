@@ -0,0 +1,157 @@
+//! Quoting the best route of swaps between two tokens over the pools of the contract.
+//! After every hop the search keeps only the best amount of each token reached, so each pool is quoted
+//! at most once per token and hop, and the number of pools scanned by one call is limited.
+
+use std::collections::HashMap;
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Balance};
+
+use crate::utils::{FEE_DIVISOR, U256};
+use crate::*;
+
+/// Max number of swaps in the route.
+pub const MAX_ROUTE_HOPS: u8 = 3;
+/// Max number of pools scanned by one `get_best_route` call.
+pub const MAX_ROUTE_POOLS: u64 = 100;
+/// Price impact is measured against swapping this fraction of amount_in along the same route.
+const PRICE_IMPACT_PROBE_DIVISOR: u128 = 10_000;
+
+/// Swap of the route, as (pool_id, token_in, token_out).
+type Hop = (u64, AccountId, AccountId);
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RouteInfo {
+    /// Swap actions of the route, ready to be passed to `swap` or to `ft_transfer_call` of token_in.
+    /// The last one expects exactly amount_out, its min_amount_out should be lowered by the slippage tolerance.
+    pub actions: Vec<SwapAction>,
+    /// Expected amount of token_out.
+    pub amount_out: U128,
+    /// Price impact of the route in basis points, relative to the price of swapping a tiny amount along it.
+    pub price_impact: u32,
+}
+
+impl Contract {
+    /// Returns the best amount of token_out and the hops to get it, among routes of at most max_hops through given pools.
+    fn internal_find_route(
+        &self,
+        pools: &[(u64, Pool)],
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        max_hops: u8,
+    ) -> Option<(Balance, Vec<Hop>)> {
+        let fees = AdminFees::new(self.exchange_fee);
+        let mut best: Option<(Balance, Vec<Hop>)> = None;
+        let mut reached: HashMap<AccountId, (Balance, Vec<Hop>)> = HashMap::new();
+        reached.insert(token_in.clone(), (amount_in, vec![]));
+        for _ in 0..max_hops {
+            let mut next_reached: HashMap<AccountId, (Balance, Vec<Hop>)> = HashMap::new();
+            for (token, (amount, hops)) in reached.iter() {
+                for (pool_id, pool) in pools.iter() {
+                    // Pool can't be used twice, its state changes after the first swap.
+                    if !pool.tokens().contains(token) || hops.iter().any(|(id, _, _)| id == pool_id) {
+                        continue;
+                    }
+                    for next_token in pool.tokens() {
+                        if next_token == token || next_token == token_in {
+                            continue;
+                        }
+                        let next_amount = match pool.try_get_return(token, *amount, next_token, &fees) {
+                            Some(next_amount) if next_amount > 0 => next_amount,
+                            _ => continue,
+                        };
+                        let best_amount = if next_token == token_out {
+                            best.as_ref().map(|(best_amount, _)| *best_amount)
+                        } else {
+                            next_reached.get(next_token).map(|(best_amount, _)| *best_amount)
+                        };
+                        if matches!(best_amount, Some(best_amount) if best_amount >= next_amount) {
+                            continue;
+                        }
+                        let mut next_hops = hops.clone();
+                        next_hops.push((*pool_id, token.clone(), next_token.clone()));
+                        if next_token == token_out {
+                            best = Some((next_amount, next_hops));
+                        } else {
+                            next_reached.insert(next_token.clone(), (next_amount, next_hops));
+                        }
+                    }
+                }
+            }
+            reached = next_reached;
+        }
+        best
+    }
+
+    /// Returns amount of the last token received by swapping amount_in along given hops.
+    fn internal_get_route_return(&self, pools: &[(u64, Pool)], hops: &[Hop], amount_in: Balance) -> Option<Balance> {
+        let fees = AdminFees::new(self.exchange_fee);
+        hops.iter().try_fold(amount_in, |amount, (pool_id, token_in, token_out)| {
+            let (_, pool) = pools.iter().find(|(id, _)| id == pool_id)?;
+            pool.try_get_return(token_in, amount, token_out, &fees)
+        })
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns the best route swapping amount_in of token_in into token_out in at most max_hops swaps, if there is any.
+    /// Scans at most `limit` pools starting from `from_index`, up to MAX_ROUTE_POOLS, so large sets of pools
+    /// should be scanned in several calls. Paused and concentrated liquidity pools are skipped,
+    /// rated pools are quoted with their cached rates.
+    pub fn get_best_route(
+        &self,
+        token_in: ValidAccountId,
+        amount_in: U128,
+        token_out: ValidAccountId,
+        max_hops: u8,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Option<RouteInfo> {
+        assert!(max_hops > 0 && max_hops <= MAX_ROUTE_HOPS, "{}", ERR170_ILLEGAL_MAX_HOPS);
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(MAX_ROUTE_POOLS).min(MAX_ROUTE_POOLS);
+        let pools: Vec<(u64, Pool)> = (from_index..std::cmp::min(from_index.saturating_add(limit), self.pools.len()))
+            .filter(|pool_id| self.internal_get_pool_state(*pool_id) == PoolState::Running)
+            .map(|pool_id| (pool_id, self.internal_get_pool(pool_id)))
+            .collect();
+        let (amount_out, hops) =
+            self.internal_find_route(&pools, token_in.as_ref(), amount_in.0, token_out.as_ref(), max_hops)?;
+
+        let probe_amount_in = amount_in.0 / PRICE_IMPACT_PROBE_DIVISOR;
+        let price_impact = match self.internal_get_route_return(&pools, &hops, probe_amount_in) {
+            Some(probe_amount_out) if probe_amount_in > 0 && probe_amount_out > 0 => {
+                let ideal_amount_out =
+                    U256::from(probe_amount_out) * U256::from(amount_in.0) / U256::from(probe_amount_in);
+                if ideal_amount_out > U256::from(amount_out) {
+                    ((ideal_amount_out - U256::from(amount_out)) * U256::from(FEE_DIVISOR) / ideal_amount_out)
+                        .as_u32()
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        };
+
+        let last_index = hops.len() - 1;
+        let actions = hops
+            .into_iter()
+            .enumerate()
+            .map(|(index, (pool_id, token_in, token_out))| SwapAction {
+                pool_id,
+                token_in,
+                amount_in: if index == 0 { Some(amount_in) } else { None },
+                token_out,
+                min_amount_out: U128(if index == last_index { amount_out } else { 0 }),
+            })
+            .collect();
+        Some(RouteInfo {
+            actions,
+            amount_out: U128(amount_out),
+            price_impact,
+        })
+    }
+}
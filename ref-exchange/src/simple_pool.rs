@@ -9,10 +9,12 @@ use crate::admin_fee::AdminFees;
 
 use crate::errors::*;
 use crate::utils::{
-    add_to_collection, integer_sqrt, SwapVolume, FEE_DIVISOR, INIT_SHARES_SUPPLY, U256,
+    add_to_collection, integer_sqrt, nth_root_of_ratio, SwapVolume, FEE_DIVISOR,
+    INIT_SHARES_SUPPLY, PRICE_PRECISION, U256, U384,
 };
 
-const NUM_TOKENS: usize = 2;
+pub const MIN_NUM_TOKENS: usize = 2;
+pub const MAX_NUM_TOKENS: usize = 8;
 
 /// Implementation of simple pool, that maintains constant product between balances of all the tokens.
 /// Similar in design to "Uniswap".
@@ -50,11 +52,14 @@ impl SimplePool {
             "{}", ERR90_FEE_TOO_LARGE
         );
         // [AUDIT_10]
-        assert_eq!(token_account_ids.len(), NUM_TOKENS, "{}", ERR89_WRONG_TOKEN_COUNT);
+        assert!(
+            token_account_ids.len() >= MIN_NUM_TOKENS && token_account_ids.len() <= MAX_NUM_TOKENS,
+            "{}", ERR89_WRONG_TOKEN_COUNT
+        );
         Self {
             token_account_ids: token_account_ids.iter().map(|a| a.clone().into()).collect(),
-            amounts: vec![0u128; NUM_TOKENS],
-            volumes: vec![SwapVolume::default(); NUM_TOKENS],
+            amounts: vec![0u128; token_account_ids.len()],
+            volumes: vec![SwapVolume::default(); token_account_ids.len()],
             total_fee,
             exchange_fee,
             referral_fee,
@@ -105,15 +110,15 @@ impl SimplePool {
         &self.token_account_ids
     }
 
-    /// Adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
-    /// Updates amount to amount kept in the pool.
-    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut Vec<Balance>) -> Balance {
+    /// Returns number of shares minted for adding given amounts of tokens.
+    /// Updates amounts to the ones that would be kept in the pool.
+    fn calc_add_liquidity(&self, amounts: &mut Vec<Balance>) -> Balance {
         assert_eq!(
             amounts.len(),
-            NUM_TOKENS,
+            self.token_account_ids.len(),
             "{}", ERR89_WRONG_AMOUNT_COUNT
         );
-        let shares = if self.shares_total_supply > 0 {
+        if self.shares_total_supply > 0 {
             let mut fair_supply = U256::max_value();
             for i in 0..self.token_account_ids.len() {
                 assert!(amounts[i] > 0, "{}", ERR31_ZERO_AMOUNT);
@@ -127,16 +132,26 @@ impl SimplePool {
                     / U256::from(self.shares_total_supply))
                 .as_u128();
                 assert!(amount > 0, "{}", ERR31_ZERO_AMOUNT);
-                self.amounts[i] += amount;
                 amounts[i] = amount;
             }
             fair_supply.as_u128()
         } else {
-            for i in 0..self.token_account_ids.len() {
-                self.amounts[i] += amounts[i];
-            }
             INIT_SHARES_SUPPLY
-        };
+        }
+    }
+
+    /// Returns number of shares that adding given amounts of tokens would mint.
+    pub fn predict_add_liquidity(&self, amounts: &[Balance]) -> Balance {
+        self.calc_add_liquidity(&mut amounts.to_vec())
+    }
+
+    /// Adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
+    /// Updates amount to amount kept in the pool.
+    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut Vec<Balance>) -> Balance {
+        let shares = self.calc_add_liquidity(amounts);
+        for i in 0..self.token_account_ids.len() {
+            self.amounts[i] += amounts[i];
+        }
         self.mint_shares(&sender_id, shares);
         assert!(shares > 0, "{}", ERR32_ZERO_SHARES);
         env::log(
@@ -154,6 +169,51 @@ impl SimplePool {
         shares
     }
 
+    /// Adds liquidity from a single token. Swaps part of `amount_in` into the other token through this pool,
+    /// so that the swap output and the rest of `amount_in` match the pool ratio, and adds both as liquidity.
+    /// Returns minted shares and the amounts of each token that didn't fit into the ratio.
+    /// Only pools of two tokens are supported.
+    pub fn zap_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        token_in: &AccountId,
+        amount_in: Balance,
+        min_shares: Balance,
+        admin_fee: &AdminFees,
+    ) -> (Balance, Vec<Balance>) {
+        assert_eq!(self.token_account_ids.len(), 2, "{}", ERR89_WRONG_TOKEN_COUNT);
+        let in_idx = self.token_index(token_in);
+        let out_idx = 1 - in_idx;
+        let swap_amount = self.zap_swap_amount(in_idx, amount_in);
+        let amount_out = self.internal_get_return(in_idx, swap_amount, out_idx);
+        self.internal_swap(in_idx, swap_amount, out_idx, amount_out, admin_fee);
+        let mut offered = vec![0; 2];
+        offered[in_idx] = amount_in - swap_amount;
+        offered[out_idx] = amount_out;
+        let mut amounts = offered.clone();
+        let shares = self.add_liquidity(sender_id, &mut amounts);
+        assert!(shares >= min_shares, "{}", ERR68_SLIPPAGE);
+        let leftovers = offered
+            .iter()
+            .zip(amounts.iter())
+            .map(|(offered, added)| offered - added)
+            .collect();
+        (shares, leftovers)
+    }
+
+    /// Returns how much of `amount_in` to swap, so that the output and the rest are in the pool ratio after the swap.
+    /// Solves the quadratic equation for swapped amount s, given balance R of token_in and fee f:
+    /// s = (sqrt(((2 - f) * R)^2 + 4 * (1 - f) * amount_in * R) - (2 - f) * R) / (2 * (1 - f))
+    /// Computed in U384, as squared balances scaled by the fee divisor don't fit into U256.
+    fn zap_swap_amount(&self, in_idx: usize, amount_in: Balance) -> Balance {
+        let divisor = U384::from(FEE_DIVISOR);
+        let fee_rest = U384::from(FEE_DIVISOR - self.total_fee);
+        let in_balance = U384::from(self.amounts[in_idx]);
+        let b = (divisor + fee_rest) * in_balance;
+        let discriminant = b * b + U384::from(4) * fee_rest * divisor * U384::from(amount_in) * in_balance;
+        ((discriminant.integer_sqrt() - b) / (fee_rest * 2)).as_u128()
+    }
+
     /// Mint new shares for given user.
     fn mint_shares(&mut self, account_id: &AccountId, shares: Balance) {
         if shares == 0 {
@@ -163,6 +223,20 @@ impl SimplePool {
         add_to_collection(&mut self.shares, &account_id, shares);
     }
 
+    /// Returns amounts of tokens that removing given number of shares would give.
+    pub fn predict_remove_liquidity(&self, shares: Balance) -> Vec<Balance> {
+        if self.shares_total_supply == 0 {
+            return vec![0; self.amounts.len()];
+        }
+        self.amounts
+            .iter()
+            .map(|amount| {
+                (U256::from(*amount) * U256::from(shares) / U256::from(self.shares_total_supply))
+                    .as_u128()
+            })
+            .collect()
+    }
+
     /// Removes given number of shares from the pool and returns amounts to the parent.
     pub fn remove_liquidity(
         &mut self,
@@ -172,19 +246,15 @@ impl SimplePool {
     ) -> Vec<Balance> {
         assert_eq!(
             min_amounts.len(),
-            NUM_TOKENS,
+            self.token_account_ids.len(),
             "{}", ERR89_WRONG_AMOUNT_COUNT
         );
         let prev_shares_amount = self.shares.get(&sender_id).expect(ERR13_LP_NOT_REGISTERED);
         assert!(prev_shares_amount >= shares, "{}", ERR91_NOT_ENOUGH_SHARES);
-        let mut result = vec![];
+        let result = self.predict_remove_liquidity(shares);
         for i in 0..self.token_account_ids.len() {
-            let amount = (U256::from(self.amounts[i]) * U256::from(shares)
-                / U256::from(self.shares_total_supply))
-            .as_u128();
-            assert!(amount >= min_amounts[i], "{}", ERR68_SLIPPAGE);
-            self.amounts[i] -= amount;
-            result.push(amount);
+            assert!(result[i] >= min_amounts[i], "{}", ERR68_SLIPPAGE);
+            self.amounts[i] -= result[i];
         }
         if prev_shares_amount == shares {
             // [AUDIT_13] Never unregister a LP when he removed all his liquidity.
@@ -239,6 +309,43 @@ impl SimplePool {
             .as_u128()
     }
 
+    /// Returns number of tokens needed as input to receive given amount in outcome.
+    /// Tokens are provided as indexes into token list for given pool.
+    fn internal_get_return_by_output(
+        &self,
+        token_in: usize,
+        amount_out: Balance,
+        token_out: usize,
+    ) -> Balance {
+        let in_balance = U256::from(self.amounts[token_in]);
+        let out_balance = U256::from(self.amounts[token_out]);
+        assert!(
+            in_balance > U256::zero()
+                && out_balance > U256::from(amount_out)
+                && token_in != token_out
+                && amount_out > 0,
+            "{}", ERR76_INVALID_PARAMS
+        );
+        // Rounded up, so that swapping the result gives not less than amount_out.
+        let numerator = in_balance * U256::from(amount_out) * U256::from(FEE_DIVISOR);
+        let denominator = (out_balance - U256::from(amount_out)) * U256::from(FEE_DIVISOR - self.total_fee);
+        ((numerator + denominator - 1) / denominator).as_u128()
+    }
+
+    /// Returns how much of `token_in` is needed to receive `amount_out` of `token_out`.
+    pub fn get_return_by_output(
+        &self,
+        token_in: &AccountId,
+        amount_out: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.internal_get_return_by_output(
+            self.token_index(token_in),
+            amount_out,
+            self.token_index(token_out),
+        )
+    }
+
     /// Returns how much token you will receive if swap `token_amount_in` of `token_in` for `token_out`.
     pub fn get_return(
         &self,
@@ -253,6 +360,51 @@ impl SimplePool {
         )
     }
 
+    /// Same as `get_return`, but returns None instead of failing if the pool has no liquidity of given tokens.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Option<Balance> {
+        let (in_idx, out_idx) = (self.token_index(token_in), self.token_index(token_out));
+        if in_idx == out_idx || amount_in == 0 || self.amounts[in_idx] == 0 || self.amounts[out_idx] == 0 {
+            return None;
+        }
+        Some(self.internal_get_return(in_idx, amount_in, out_idx))
+    }
+
+    /// Returns price of each token denominated in the first one, given as fraction of PRICE_PRECISION.
+    /// None if the pool has no liquidity.
+    pub fn get_spot_prices(&self) -> Option<Vec<U256>> {
+        if self.amounts.contains(&0) {
+            return None;
+        }
+        Some(
+            self.amounts
+                .iter()
+                .map(|amount| {
+                    U256::from(self.amounts[0]) * U256::from(PRICE_PRECISION) / U256::from(*amount)
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns value of a whole share (shares have 24 decimals) in whole first tokens of given `decimals`,
+    /// with 1e8 precision. As value of each token in the pool is the same, that's number of tokens times amount of the first one per share.
+    pub fn get_share_price(&self, decimals: u8) -> u128 {
+        if self.shares_total_supply == 0 {
+            return 0;
+        }
+        (U256::from(self.amounts[0])
+            * U256::from(self.token_account_ids.len())
+            * U256::from(100000000)
+            * U256::from(10u128.pow(24))
+            / U256::from(self.shares_total_supply)
+            / U256::from(10u128.pow(decimals as u32)))
+        .as_u128()
+    }
+
     /// Returns given pool's total fee.
     pub fn get_fee(&self) -> u32 {
         self.total_fee
@@ -278,6 +430,40 @@ impl SimplePool {
         let out_idx = self.token_index(token_out);
         let amount_out = self.internal_get_return(in_idx, amount_in, out_idx);
         assert!(amount_out >= min_amount_out, "{}", ERR68_SLIPPAGE);
+        self.internal_swap(in_idx, amount_in, out_idx, amount_out, admin_fee);
+        amount_out
+    }
+
+    /// Swap `token_in` into exactly `amount_out` of `token_out` and return how much of `token_in` was used.
+    /// Assuming that `max_amount_in` was already received from `sender_id`.
+    pub fn swap_by_output(
+        &mut self,
+        token_in: &AccountId,
+        amount_out: Balance,
+        token_out: &AccountId,
+        max_amount_in: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        assert_ne!(token_in, token_out, "{}", ERR73_SAME_TOKEN);
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        let amount_in = self.internal_get_return_by_output(in_idx, amount_out, out_idx);
+        assert!(amount_in <= max_amount_in, "{}", ERR68_SLIPPAGE);
+        self.internal_swap(in_idx, amount_in, out_idx, amount_out, admin_fee);
+        amount_in
+    }
+
+    /// Moves swapped amounts into and out of the pool and allocates admin fees.
+    fn internal_swap(
+        &mut self,
+        in_idx: usize,
+        amount_in: Balance,
+        out_idx: usize,
+        amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) {
+        let token_in = &self.token_account_ids[in_idx];
+        let token_out = &self.token_account_ids[out_idx];
         env::log(
             format!(
                 "Swapped {} {} for {} {}",
@@ -286,18 +472,32 @@ impl SimplePool {
             .as_bytes(),
         );
 
-        let prev_invariant =
-            integer_sqrt(U256::from(self.amounts[in_idx]) * U256::from(self.amounts[out_idx]));
+        let prev_product = U256::from(self.amounts[in_idx]) * U256::from(self.amounts[out_idx]);
 
         self.amounts[in_idx] += amount_in;
         self.amounts[out_idx] -= amount_out;
 
-        // "Invariant" is by how much the dot product of amounts increased due to fees.
-        let new_invariant =
-            integer_sqrt(U256::from(self.amounts[in_idx]) * U256::from(self.amounts[out_idx]));
+        let new_product = U256::from(self.amounts[in_idx]) * U256::from(self.amounts[out_idx]);
 
         // Invariant can not reduce (otherwise loosing balance of the pool and something it broken).
-        assert!(new_invariant >= prev_invariant, "{}", ERR75_INVARIANT_REDUCE);
+        assert!(new_product >= prev_product, "{}", ERR75_INVARIANT_REDUCE);
+
+        // "Invariant" is geometric mean of all the balances, it increases due to fees.
+        // Only balances of the swapped tokens change, so for more than two tokens
+        // the invariant is measured relative to the new one, as N-th root of the ratio of their products.
+        let (prev_invariant, new_invariant) = if self.token_account_ids.len() == 2 {
+            (integer_sqrt(prev_product), integer_sqrt(new_product))
+        } else {
+            (
+                nth_root_of_ratio(
+                    prev_product,
+                    new_product,
+                    self.token_account_ids.len() as u32,
+                    INIT_SHARES_SUPPLY,
+                ),
+                U256::from(INIT_SHARES_SUPPLY),
+            )
+        };
         let numerator = (new_invariant - prev_invariant) * U256::from(self.shares_total_supply);
 
         // Allocate exchange fee as fraction of total fee by issuing LP shares proportionally.
@@ -321,8 +521,6 @@ impl SimplePool {
         // Reported volume with fees will be sum of `input`, without fees will be sum of `output`.
         self.volumes[in_idx].input.0 += amount_in;
         self.volumes[in_idx].output.0 += amount_out;
-
-        amount_out
     }
 }
 
@@ -421,6 +619,174 @@ mod tests {
         assert_eq!(liq1[1] + liq2[1], to_yocto("10") - out);
     }
 
+    #[test]
+    fn test_pool_swap_by_output() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], 30, 0, 0);
+        let mut amounts = vec![to_yocto("5"), to_yocto("10")];
+        pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        for amount_out in vec![1, 1000, to_yocto("1"), to_yocto("9.9")] {
+            let amount_in = pool.get_return_by_output(accounts(1).as_ref(), amount_out, accounts(2).as_ref());
+            assert!(pool.get_return(accounts(1).as_ref(), amount_in, accounts(2).as_ref()) >= amount_out);
+            if amount_in > 1 {
+                assert!(pool.get_return(accounts(1).as_ref(), amount_in - 1, accounts(2).as_ref()) < amount_out);
+            }
+        }
+        let amount_in = pool.swap_by_output(
+            accounts(1).as_ref(),
+            to_yocto("1"),
+            accounts(2).as_ref(),
+            to_yocto("1"),
+            &AdminFees {
+                exchange_fee: 0,
+                exchange_id: accounts(3).as_ref().clone(),
+                referral_fee: 0,
+                referral_id: None,
+            },
+        );
+        assert_eq!(pool.amounts, vec![to_yocto("5") + amount_in, to_yocto("9")]);
+    }
+
+    #[test]
+    fn test_multi_token_pool_swap_with_fees() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2), accounts(3)], 100, 0, 0);
+        let mut amounts = vec![to_yocto("5"), to_yocto("10"), to_yocto("20")];
+        let num_shares = pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        assert_eq!(num_shares, INIT_SHARES_SUPPLY);
+        let expected = pool.get_return(accounts(1).as_ref(), to_yocto("1"), accounts(3).as_ref());
+        let out = pool.swap(
+            accounts(1).as_ref(),
+            to_yocto("1"),
+            accounts(3).as_ref(),
+            1,
+            &AdminFees {
+                exchange_fee: 2000,
+                exchange_id: accounts(4).as_ref().clone(),
+                referral_fee: 0,
+                referral_id: None,
+            },
+        );
+        assert_eq!(out, expected);
+        // Balance of the token not participating in the swap is unchanged.
+        assert_eq!(pool.amounts, vec![to_yocto("6"), to_yocto("10"), to_yocto("20") - out]);
+        let exchange_shares = pool.share_balance_of(accounts(4).as_ref());
+        let liq1 = pool.remove_liquidity(accounts(0).as_ref(), num_shares, vec![1, 1, 1]);
+        let liq2 = pool.remove_liquidity(accounts(4).as_ref(), exchange_shares, vec![1, 1, 1]);
+        // Exchange gets about 20% of the fee (1% of 1 token), spread evenly in value over all the tokens.
+        assert!(liq2[0] * 3 > to_yocto("0.0019") && liq2[0] * 3 < to_yocto("0.0021"));
+        assert_eq!(liq1[0] + liq2[0], to_yocto("6"));
+        assert_eq!(liq1[1] + liq2[1], to_yocto("10"));
+        assert_eq!(liq1[2] + liq2[2], to_yocto("20") - out);
+    }
+
+    #[test]
+    fn test_pool_predict_liquidity() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], 30, 0, 0);
+        assert_eq!(pool.get_share_price(24), 0);
+        assert_eq!(pool.predict_remove_liquidity(INIT_SHARES_SUPPLY), vec![0, 0]);
+        assert_eq!(pool.predict_add_liquidity(&vec![to_yocto("5"), to_yocto("10")]), INIT_SHARES_SUPPLY);
+        pool.add_liquidity(accounts(0).as_ref(), &mut vec![to_yocto("5"), to_yocto("10")]);
+        // Share is worth 5 of the first token and 10 of the second one, which is 10 of the first token.
+        assert_eq!(pool.get_share_price(24), 10 * 100000000);
+
+        // Extra amount of the second token isn't taken.
+        let amounts = vec![to_yocto("1"), to_yocto("3")];
+        let predicted = pool.predict_add_liquidity(&amounts);
+        assert_eq!(predicted, INIT_SHARES_SUPPLY / 5);
+        assert_eq!(pool.add_liquidity(accounts(1).as_ref(), &mut amounts.clone()), predicted);
+
+        let predicted = pool.predict_remove_liquidity(predicted);
+        assert_eq!(predicted, vec![to_yocto("1"), to_yocto("2")]);
+        assert_eq!(
+            pool.remove_liquidity(accounts(1).as_ref(), INIT_SHARES_SUPPLY / 5, vec![0, 0]),
+            predicted
+        );
+    }
+
+    #[test]
+    fn test_pool_share_price_low_decimals() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], 30, 0, 0);
+        // 1000 of a 6 decimals token and 2000 of a 24 decimals one.
+        pool.add_liquidity(accounts(0).as_ref(), &mut vec![1000 * 10u128.pow(6), to_yocto("2000")]);
+        // Initial whole share is worth 1000 + 1000 of the first token.
+        assert_eq!(pool.get_share_price(6), 2000 * 100000000);
+    }
+
+    #[test]
+    fn test_pool_zap_liquidity() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], 30, 0, 0);
+        let mut amounts = vec![to_yocto("1000"), to_yocto("2000")];
+        pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        let (shares, leftovers) = pool.zap_liquidity(
+            accounts(3).as_ref(),
+            accounts(1).as_ref(),
+            to_yocto("100"),
+            1,
+            &AdminFees::zero(),
+        );
+        // Only dust from rounding to whole shares doesn't fit into the pool ratio.
+        assert!(leftovers.iter().all(|leftover| *leftover < 10_000));
+        assert_eq!(pool.share_balance_of(accounts(3).as_ref()), shares);
+        // Value at the initial price is a bit less than zapped due to the swap fee and price impact.
+        let removed = pool.remove_liquidity(accounts(3).as_ref(), shares, vec![1, 1]);
+        let value = removed[0] + removed[1] / 2;
+        assert!(value > to_yocto("97") && value < to_yocto("100"));
+        assert_eq!(removed[0] + leftovers[0] + pool.amounts[0], to_yocto("1100"));
+    }
+
+    #[test]
+    fn test_pool_zap_liquidity_large_balances() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], 30, 0, 0);
+        let balance = 10u128.pow(36);
+        let mut amounts = vec![balance, balance];
+        pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        let (shares, leftovers) = pool.zap_liquidity(
+            accounts(3).as_ref(),
+            accounts(1).as_ref(),
+            balance / 10,
+            1,
+            &AdminFees::zero(),
+        );
+        assert!(shares > 0);
+        assert!(leftovers.iter().all(|leftover| *leftover < balance / 10u128.pow(12)));
+    }
+
+    #[test]
+    #[should_panic(expected = "E68: slippage error")]
+    fn test_pool_zap_liquidity_slippage() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], 30, 0, 0);
+        let mut amounts = vec![to_yocto("1000"), to_yocto("2000")];
+        let num_shares = pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        // Zapping 10% of the pool can't give 5% of its shares.
+        pool.zap_liquidity(
+            accounts(3).as_ref(),
+            accounts(1).as_ref(),
+            to_yocto("100"),
+            num_shares / 20,
+            &AdminFees::zero(),
+        );
+    }
+
     #[test]
     #[should_panic(expected = "E31: adding zero amount")]
     fn test_rounding() {
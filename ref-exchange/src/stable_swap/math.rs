@@ -3,7 +3,7 @@
 use near_sdk::{Balance, Timestamp};
 
 use crate::admin_fee::AdminFees;
-use crate::utils::{FEE_DIVISOR, U256};
+use crate::utils::{FEE_DIVISOR, U256, U384};
 
 /// Minimum ramp duration, in nano sec.
 pub const MIN_RAMP_DURATION: Timestamp = 86400 * 1_000_000_000;
@@ -180,6 +180,36 @@ impl StableSwap {
         }
     }
 
+    /// Compute marginal price of each token denominated in the first one,
+    /// given as fraction of `precision`. Returns None if any of the amounts is zero.
+    /// Equation:
+    /// p_i = x_0 * (A * n**n * x_i + D_P) / (x_i * (A * n**n * x_0 + D_P)), where D_P = D**(n+1) / (n**n * prod(x_i))
+    pub fn compute_spot_prices(&self, c_amounts: &Vec<Balance>, precision: u128) -> Option<Vec<U384>> {
+        if c_amounts.contains(&0) {
+            return None;
+        }
+        let n_coins = c_amounts.len() as u128;
+        let d = self.compute_d(c_amounts)?;
+        let mut d_prod = d;
+        for c_amount in c_amounts {
+            d_prod = d_prod.checked_mul(d)?
+            .checked_div((c_amount * n_coins).into())?;
+        }
+        let d_prod = U384::from(d_prod);
+        let ann = U384::from(self.compute_amp_factor()?.checked_mul(n_coins.checked_pow(n_coins as u32)?)?);
+        let x_0 = U384::from(c_amounts[0]);
+        let denominator_0 = ann.checked_mul(x_0)?.checked_add(d_prod)?;
+        c_amounts
+            .iter()
+            .map(|c_amount| {
+                let x_i = U384::from(*c_amount);
+                x_0.checked_mul(ann.checked_mul(x_i)?.checked_add(d_prod)?)?
+                    .checked_mul(precision.into())?
+                    .checked_div(x_i.checked_mul(denominator_0)?)
+            })
+            .collect()
+    }
+
     /// Compute the amount of LP tokens to mint after a deposit
     /// return <lp_amount_to_mint, lp_fees_part>
     pub fn compute_lp_amount_for_deposit(
@@ -249,15 +279,27 @@ impl StableSwap {
         index_x: usize, // x token's index
         index_y: usize, // y token's index
     ) -> Option<U256> {
-        let n_coins = current_c_amounts.len() as u128;
-        let amp_factor = self.compute_amp_factor()?;
-        let ann = amp_factor.checked_mul(n_coins.checked_pow(n_coins as u32)?.into())?;
         // invariant
         let d = self.compute_d(current_c_amounts)?;
-        let mut s_ = x_c_amount;
-        let mut c = d.checked_mul(d)?.checked_div(x_c_amount.into())?;
-        for (idx, c_amount) in current_c_amounts.iter().enumerate() {
-            if idx != index_x && idx != index_y {
+        let mut new_c_amounts = current_c_amounts.clone();
+        new_c_amounts[index_x] = x_c_amount;
+        self.compute_y_d(d, &new_c_amounts, index_y)
+    }
+
+    /// Compute amount of token 'y', that keeps invariant equal to `d` with other tokens amounts fixed
+    pub fn compute_y_d(
+        &self,
+        d: U256,
+        c_amounts: &[Balance], // in-pool tokens amount in comparable precision, amount of y is ignored
+        index_y: usize, // y token's index
+    ) -> Option<U256> {
+        let n_coins = c_amounts.len() as u128;
+        let amp_factor = self.compute_amp_factor()?;
+        let ann = amp_factor.checked_mul(n_coins.checked_pow(n_coins as u32)?.into())?;
+        let mut s_ = 0_u128;
+        let mut c = d;
+        for (idx, c_amount) in c_amounts.iter().enumerate() {
+            if idx != index_y {
                 s_ += *c_amount;
                 c = c.checked_mul(d)?
                     .checked_div((*c_amount).into())?;
@@ -353,6 +395,47 @@ impl StableSwap {
 
     }
 
+    /// Compute amount of single token to withdraw by burning given shares,
+    /// return <token_amount, fee>, all amounts are in c_amount (comparable amount)
+    pub fn compute_withdraw_one_coin(
+        &self,
+        shares: Balance, // shares to burn,
+        index: usize, // withdraw token's index
+        current_c_amounts: &Vec<Balance>, // in-pool tokens comparable amounts vector,
+        pool_token_supply: Balance, // total share supply
+        fees: &Fees,
+    ) -> Option<(Balance, Balance)> {
+        let n_coins = current_c_amounts.len();
+        let d_0 = self.compute_d(current_c_amounts)?;
+        let d_1 = d_0.checked_sub(
+            d_0.checked_mul(shares.into())?
+                .checked_div(pool_token_supply.into())?
+        )?;
+        let new_y = self.compute_y_d(d_1, current_c_amounts, index)?.as_u128();
+
+        // charge fee on the diff with ideal token portions, as if the tokens were swapped into the withdrawn one
+        let mut reduced_c_amounts = current_c_amounts.clone();
+        for i in 0..n_coins {
+            let ideal_balance = d_1
+                .checked_mul(current_c_amounts[i].into())?
+                .checked_div(d_0)?
+                .as_u128();
+            let difference = if i == index {
+                ideal_balance.checked_sub(new_y)?
+            } else {
+                current_c_amounts[i].checked_sub(ideal_balance)?
+            };
+            let fee = fees.normalized_trade_fee(n_coins as u32, difference);
+            reduced_c_amounts[i] = reduced_c_amounts[i].checked_sub(fee)?;
+        }
+        let y = self.compute_y_d(d_1, &reduced_c_amounts, index)?.as_u128();
+        // keep 1 more token in the pool for rounding errors, same as swap_to
+        let dy = reduced_c_amounts[index].checked_sub(y)?.saturating_sub(1);
+        let dy_0 = current_c_amounts[index].checked_sub(new_y)?;
+
+        Some((dy, dy_0.saturating_sub(dy)))
+    }
+
     /// Compute SwapResult after an exchange
     /// all tokens in and out with comparable precision
     pub fn swap_to(
@@ -390,4 +473,31 @@ impl StableSwap {
             fee: trade_fee,
         })
     }
+
+    /// Compute amount of source token, that should be swapped to get given amount of destination token,
+    /// inverse of `swap_to`, rounded up so that the swap gives not less than requested.
+    /// all tokens in and out with comparable precision
+    pub fn swap_from(
+        &self,
+        token_in_idx: usize, // token_in index in token vector,
+        token_out_idx: usize, // token_out index in token vector,
+        amount_swapped: Balance, // token_out amount user wants to receive in comparable precision (1e18),
+        current_c_amounts: &Vec<Balance>, // in-pool tokens comparable amounts vector,
+        fees: &Fees,
+    ) -> Option<Balance> {
+        // amount before trade fee is taken
+        let dy = (U256::from(amount_swapped) * U256::from(FEE_DIVISOR) + U256::from(FEE_DIVISOR - fees.trade_fee - 1))
+            .checked_div(U256::from(FEE_DIVISOR - fees.trade_fee))?
+            .as_u128();
+        // swap_to keeps 1 more token in the pool for rounding errors
+        let y = current_c_amounts[token_out_idx].checked_sub(dy)?.checked_sub(1)?;
+        let x = self.compute_y(
+            y,
+            current_c_amounts,
+            token_out_idx,
+            token_in_idx,
+        )?.as_u128();
+        // compute_y converges up to 1, so add it to stay on the safe side
+        x.checked_sub(current_c_amounts[token_in_idx])?.checked_add(1)
+    }
 }
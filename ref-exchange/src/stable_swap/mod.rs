@@ -8,7 +8,7 @@ use crate::errors::*;
 use crate::stable_swap::math::{
     Fees, StableSwap, SwapResult, MAX_AMP, MAX_AMP_CHANGE, MIN_AMP, MIN_RAMP_DURATION,
 };
-use crate::utils::{add_to_collection, SwapVolume, FEE_DIVISOR, U256};
+use crate::utils::{add_to_collection, SwapVolume, FEE_DIVISOR, PRICE_PRECISION, U256, U384};
 use crate::StorageKey;
 
 mod math;
@@ -151,6 +151,21 @@ impl StableSwapPool {
         );
     }
 
+    /// Returns marginal price of each token denominated in the first one, in raw token amounts,
+    /// given as fraction of PRICE_PRECISION. None if the pool has no liquidity.
+    pub fn get_spot_prices(&self) -> Option<Vec<U256>> {
+        let c_prices = self.get_invariant()
+            .compute_spot_prices(&self.c_amounts, PRICE_PRECISION)?;
+        let decimals_0 = 10u128.pow(self.token_decimals[0] as u32);
+        c_prices
+            .into_iter()
+            .zip(self.token_decimals.iter())
+            .map(|(c_price, decimals)| {
+                (c_price * U384::from(decimals_0) / U384::from(10u128.pow(*decimals as u32))).to_u256()
+            })
+            .collect()
+    }
+
     pub fn get_amp(&self) -> u64 {
         if let Some(amp) = self.get_invariant().compute_amp_factor() {
             amp as u64
@@ -463,6 +478,83 @@ impl StableSwapPool {
         burn_shares
     }
 
+    /// Returns amount of the token, that removing given number of shares in this single token would give.
+    pub fn predict_remove_liquidity_one_coin(
+        &self,
+        shares: Balance,
+        token_id: &AccountId,
+        fees: &AdminFees,
+    ) -> Balance {
+        let index = self.token_index(token_id);
+        let (amount, _) = self.get_invariant()
+            .compute_withdraw_one_coin(
+                shares,
+                index,
+                &self.c_amounts,
+                self.shares_total_supply,
+                &Fees::new(self.total_fee, fees),
+            )
+            .expect(ERR67_LPSHARE_CALC_ERR);
+        self.c_amount_to_amount(amount, index)
+    }
+
+    /// Remove liquidity from the pool by burning given shares for a single token.
+    /// Fee will be charged according to diff between ideal token portions, as for removal by tokens.
+    pub fn remove_liquidity_one_coin(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        token_id: &AccountId,
+        min_amount: Balance,
+        fees: &AdminFees,
+    ) -> Balance {
+        let index = self.token_index(token_id);
+        let prev_shares_amount = self.shares.get(sender_id).expect(ERR13_LP_NOT_REGISTERED);
+        assert!(
+            prev_shares_amount >= shares,
+            "{}",
+            ERR34_INSUFFICIENT_LP_SHARES
+        );
+
+        let trade_fee = Fees::new(self.total_fee, fees);
+        let (c_amount, fee) = self.get_invariant()
+            .compute_withdraw_one_coin(
+                shares,
+                index,
+                &self.c_amounts,
+                self.shares_total_supply,
+                &trade_fee,
+            )
+            .expect(ERR67_LPSHARE_CALC_ERR);
+        let amount = self.c_amount_to_amount(c_amount, index);
+        assert!(amount >= min_amount, "{}", ERR68_SLIPPAGE);
+
+        let admin_fee = trade_fee.admin_trade_fee(fee);
+        self.c_amounts[index] = self.c_amounts[index]
+            .checked_sub(c_amount)
+            .unwrap()
+            .checked_sub(admin_fee)
+            .unwrap();
+        self.assert_min_reserve(self.c_amounts[index]);
+        self.burn_shares(sender_id, prev_shares_amount, shares);
+        env::log(
+            format!(
+                "LP {} removed {} shares to gain {} {}, total fee {}, admin fee {}",
+                sender_id,
+                shares,
+                amount,
+                token_id,
+                self.c_amount_to_amount(fee, index),
+                self.c_amount_to_amount(admin_fee, index)
+            )
+            .as_bytes(),
+        );
+
+        self.distribute_admin_fee(index, admin_fee, fees);
+
+        amount
+    }
+
     /// Returns number of tokens in outcome, given amount.
     /// Tokens are provided as indexes into token list for given pool.
     /// All tokens are comparable tokens
@@ -507,6 +599,32 @@ impl StableSwapPool {
         self.c_amount_to_amount(c_amount_out, self.token_index(token_out))
     }
 
+    /// Same as `get_return`, but returns None instead of failing,
+    /// if the pool is empty or the swap can't be made, for example it would leave less than MIN_RESERVE.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Option<Balance> {
+        let (in_idx, out_idx) = (self.token_index(token_in), self.token_index(token_out));
+        if in_idx == out_idx || amount_in == 0 || self.c_amounts.contains(&0) {
+            return None;
+        }
+        let result = self.get_invariant().swap_to(
+            in_idx,
+            self.amount_to_c_amount(amount_in, in_idx),
+            out_idx,
+            &self.c_amounts,
+            &Fees::new(self.total_fee, fees),
+        )?;
+        if result.new_destination_amount < MIN_RESERVE {
+            return None;
+        }
+        Some(self.c_amount_to_amount(result.amount_swapped, out_idx))
+    }
+
     /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
     /// Assuming that `token_amount_in` was already received from `sender_id`.
     pub fn swap(
@@ -547,23 +665,99 @@ impl StableSwapPool {
         self.volumes[out_idx].output.0 += self.c_amount_to_amount(result.amount_swapped, out_idx);
 
         // handle admin / referral fee.
+        self.distribute_admin_fee(out_idx, result.admin_fee, fees);
+
+        self.c_amount_to_amount(result.amount_swapped, out_idx)
+    }
+
+    /// Returns number of tokens needed as input to receive given amount of token_out.
+    /// Tokens are provided as indexes into token list for given pool.
+    fn internal_get_return_by_output(
+        &self,
+        token_in: usize,
+        amount_out: Balance,
+        token_out: usize,
+        fees: &AdminFees,
+    ) -> Balance {
+        // make amount into comparable-amount, rounding up to receive not less than requested.
+        let mut c_amount_out = self.amount_to_c_amount(amount_out, token_out);
+        if self.c_amount_to_amount(c_amount_out, token_out) < amount_out {
+            c_amount_out += 1;
+        }
+        let c_amount_in = self.get_invariant()
+            .swap_from(
+                token_in,
+                token_out,
+                c_amount_out,
+                &self.c_amounts,
+                &Fees::new(self.total_fee, fees),
+            )
+            .expect(ERR70_SWAP_OUT_CALC_ERR);
+        let amount_in = self.c_amount_to_amount(c_amount_in, token_in);
+        if self.amount_to_c_amount(amount_in, token_in) < c_amount_in {
+            amount_in + 1
+        } else {
+            amount_in
+        }
+    }
+
+    /// Returns how much of `token_in` is needed to receive `amount_out` of `token_out`.
+    pub fn get_return_by_output(
+        &self,
+        token_in: &AccountId,
+        amount_out: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Balance {
+        assert_ne!(token_in, token_out, "{}", ERR71_SWAP_DUP_TOKENS);
+        self.internal_get_return_by_output(
+            self.token_index(token_in),
+            amount_out,
+            self.token_index(token_out),
+            fees,
+        )
+    }
+
+    /// Swap `token_in` into exactly `amount_out` of `token_out` and return how much of `token_in` was used.
+    /// Assuming that `max_amount_in` was already received from `sender_id`.
+    pub fn swap_by_output(
+        &mut self,
+        token_in: &AccountId,
+        amount_out: Balance,
+        token_out: &AccountId,
+        max_amount_in: Balance,
+        fees: &AdminFees,
+    ) -> Balance {
+        let amount_in = self.get_return_by_output(token_in, amount_out, token_out, fees);
+        assert!(amount_in <= max_amount_in, "{}", ERR68_SLIPPAGE);
+        let amount_swapped = self.swap(token_in, amount_in, token_out, amount_out, fees);
+        // Input is rounded up, so a bit more than requested may be swapped, that rest stays in the pool.
+        let out_idx = self.token_index(token_out);
+        let rest = amount_swapped - amount_out;
+        self.c_amounts[out_idx] += self.amount_to_c_amount(rest, out_idx);
+        self.volumes[out_idx].output.0 -= rest;
+        amount_in
+    }
+
+    /// Converts admin fee, left in the pool in given token, into shares of the referral and the exchange.
+    fn distribute_admin_fee(&mut self, token_idx: usize, admin_fee: Balance, fees: &AdminFees) {
         if fees.referral_fee + fees.exchange_fee > 0 {
             let mut fee_token = 0_u128;
             // referral fee
             if let Some(referral) = &fees.referral_id {
                 if self.shares.get(referral).is_some() {
-                    fee_token = result.admin_fee * fees.referral_fee as u128
+                    fee_token = admin_fee * fees.referral_fee as u128
                         / (fees.referral_fee + fees.exchange_fee) as u128;
                     if fee_token > 0 {
                         let referral_share =
-                            self.admin_fee_to_liquidity(referral, out_idx, fee_token);
+                            self.admin_fee_to_liquidity(referral, token_idx, fee_token);
                         env::log(
                             format!(
                                 "Referral {} got {} shares from {} {}",
                                 referral,
                                 referral_share,
-                                self.c_amount_to_amount(fee_token, out_idx),
-                                self.token_account_ids[out_idx]
+                                self.c_amount_to_amount(fee_token, token_idx),
+                                self.token_account_ids[token_idx]
                             )
                             .as_bytes(),
                         );
@@ -571,24 +765,22 @@ impl StableSwapPool {
                 }
             }
             // exchange fee = admin_fee - referral_fee
-            fee_token = result.admin_fee - fee_token;
+            fee_token = admin_fee - fee_token;
             if fee_token > 0 {
                 let exchange_share =
-                    self.admin_fee_to_liquidity(&fees.exchange_id, out_idx, fee_token);
+                    self.admin_fee_to_liquidity(&fees.exchange_id, token_idx, fee_token);
                 env::log(
                     format!(
                         "Admin {} got {} shares from {} {}",
                         &fees.exchange_id,
                         exchange_share,
-                        self.c_amount_to_amount(fee_token, out_idx),
-                        self.token_account_ids[out_idx]
+                        self.c_amount_to_amount(fee_token, token_idx),
+                        self.token_account_ids[token_idx]
                     )
                     .as_bytes(),
                 );
             }
         }
-
-        self.c_amount_to_amount(result.amount_swapped, out_idx)
     }
 
     /// convert admin_fee into shares without any fee.
@@ -1026,6 +1218,71 @@ mod tests {
         assert_eq!(out, 99998999999);
     }
 
+    #[test]
+    fn test_stable_swap_by_output() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let fees = AdminFees::new(1000);
+        for decimals in vec![vec![6, 6], vec![24, 18], vec![8, 24]] {
+            let one_in = 10u128.pow(decimals[0] as u32);
+            let one_out = 10u128.pow(decimals[1] as u32);
+            let mut pool = StableSwapPool::new(0, vec![accounts(1), accounts(2)], decimals, 100, 25);
+            let mut amounts = vec![1000 * one_in, 1000 * one_out];
+            let _ = pool.add_liquidity(accounts(0).as_ref(), &mut amounts, 1, &fees);
+            for amount_out in vec![1, one_out / 3, 10 * one_out + 7, 900 * one_out] {
+                let amount_in = pool.get_return_by_output(
+                    accounts(1).as_ref(), amount_out, accounts(2).as_ref(), &fees);
+                // Input is enough, but not much more than needed.
+                assert!(pool.get_return(accounts(1).as_ref(), amount_in, accounts(2).as_ref(), &fees) >= amount_out);
+                if amount_out > one_out {
+                    let less = amount_in - amount_in / 10000;
+                    assert!(pool.get_return(accounts(1).as_ref(), less, accounts(2).as_ref(), &fees) < amount_out);
+                }
+            }
+            let c_amounts = pool.c_amounts.clone();
+            let amount_in = pool.swap_by_output(
+                accounts(1).as_ref(), 10 * one_out, accounts(2).as_ref(), 11 * one_in, &fees);
+            assert!(amount_in > 10 * one_in && amount_in < 11 * one_in);
+            assert_eq!(pool.c_amounts[0], c_amounts[0] + pool.amount_to_c_amount(amount_in, 0));
+            assert_eq!(pool.volumes[1].output.0, 10 * one_out);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "E68: slippage error")]
+    fn test_stable_swap_by_output_slippage() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let mut pool = StableSwapPool::new(0, vec![accounts(1), accounts(2)], vec![6, 6], 10000, 0);
+        let mut amounts = vec![5000000, 10000000];
+        let _ = pool.add_liquidity(accounts(0).as_ref(), &mut amounts, 1, &AdminFees::zero());
+        pool.swap_by_output(accounts(2).as_ref(), 1000000, accounts(1).as_ref(), 1000000, &AdminFees::zero());
+    }
+
+    #[test]
+    fn test_stable_spot_prices() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let fees = AdminFees::zero();
+        let mut pool = StableSwapPool::new(0, vec![accounts(1), accounts(2)], vec![18, 6], 100, 0);
+        assert!(pool.get_spot_prices().is_none());
+        let mut amounts = vec![1000 * 10u128.pow(18), 1000 * 10u128.pow(6)];
+        let _ = pool.add_liquidity(accounts(0).as_ref(), &mut amounts, 1, &fees);
+        // Balanced pool prices one to one, scaled to raw amounts.
+        assert_eq!(
+            pool.get_spot_prices().unwrap(),
+            vec![U256::from(PRICE_PRECISION), U256::from(PRICE_PRECISION) * U256::from(10u128.pow(12))]
+        );
+
+        swap(&mut pool, 2, 500 * 10u128.pow(6), 1);
+        let price = pool.get_spot_prices().unwrap()[1];
+        let amount_in = 1000;
+        let amount_out = pool.get_return(accounts(2).as_ref(), amount_in, accounts(1).as_ref(), &fees);
+        let swap_price = U256::from(amount_out) * U256::from(PRICE_PRECISION) / U256::from(amount_in);
+        assert!(price < U256::from(PRICE_PRECISION) * U256::from(10u128.pow(12)));
+        assert!(price > swap_price && price - swap_price < price / 10000);
+    }
+
     #[test]
     fn test_stable_basics() {
         let mut context = VMContextBuilder::new();
@@ -1106,6 +1363,45 @@ mod tests {
         assert_eq!(tokens[1], 4593934);
     }
 
+    /// Test removing liquidity into a single token, with and without fees.
+    #[test]
+    fn test_stable_remove_liquidity_one_coin() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let mut pool = StableSwapPool::new(0, vec![accounts(1), accounts(2)], vec![6, 6], 10000, 0);
+        let num_shares = pool.add_liquidity(accounts(0).as_ref(), &mut vec![10000000, 10000000], 1, &AdminFees::zero());
+        let predicted = pool.predict_remove_liquidity_one_coin(num_shares / 10, accounts(2).as_ref(), &AdminFees::zero());
+        let amount = pool.remove_liquidity_one_coin(accounts(0).as_ref(), num_shares / 10, accounts(2).as_ref(), predicted, &AdminFees::zero());
+        assert_eq!(amount, predicted);
+        // on a balanced pool it is close to the value of the shares, as after swap with small slippage
+        assert!(amount < 2000000 && amount > 1999000);
+        assert_eq!(pool.get_amounts()[0], 10000000);
+        // withdrawn amount is rounded down
+        assert_eq!(pool.get_amounts()[1], 10000000 - amount - 1);
+        assert_eq!(pool.share_total_balance(), num_shares - num_shares / 10);
+
+        let mut pool = StableSwapPool::new(0, vec![accounts(1), accounts(2)], vec![6, 6], 10000, 2000);
+        let fees = AdminFees::new(1000); // 10% exchange fee
+        let num_shares = pool.add_liquidity(accounts(0).as_ref(), &mut vec![10000000, 10000000], 1, &fees);
+        let amount_with_fee = pool.remove_liquidity_one_coin(accounts(0).as_ref(), num_shares / 10, accounts(2).as_ref(), 1, &fees);
+        assert!(amount_with_fee < amount);
+        // admin fee is left in the pool as shares of the exchange
+        assert!(pool.share_balance_of(&fees.exchange_id) > 0);
+        assert!(pool.get_amounts()[1] > 10000000 - amount_with_fee - amount_with_fee / 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "E68: slippage error")]
+    fn test_stable_remove_liquidity_one_coin_slippage() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let mut pool = StableSwapPool::new(0, vec![accounts(1), accounts(2)], vec![6, 6], 10000, 2000);
+        let fees = AdminFees::new(1000);
+        let num_shares = pool.add_liquidity(accounts(0).as_ref(), &mut vec![10000000, 5000000], 1, &fees);
+        let predicted = pool.predict_remove_liquidity_one_coin(num_shares / 10, accounts(1).as_ref(), &fees);
+        pool.remove_liquidity_one_coin(accounts(0).as_ref(), num_shares / 10, accounts(1).as_ref(), predicted + 1, &fees);
+    }
+
     /// Test that adding and then removing all of the liquidity leaves the pool empty and with no shares.
     #[test]
     #[should_panic(expected = "E69: pool reserved token balance less than MIN_RESERVE")]
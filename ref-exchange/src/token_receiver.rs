@@ -3,10 +3,30 @@ use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{serde_json, PromiseOrValue};
 
+use crate::action::{AddLiquidityAction, AddStableLiquidityAction};
 use crate::*;
 
 pub const VIRTUAL_ACC: &str = "@";
 
+/// Tokens transferred to add liquidity to a pool, waiting for the rest of the pool tokens.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct PendingLiquidity {
+    pub amounts: Vec<Balance>,
+    /// The strictest `min_shares` among the transfers.
+    pub min_shares: Balance,
+}
+
+/// What happens to the transferred tokens, when the instant swap fails.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum SwapFailurePolicy {
+    /// The transfer fails with the swap. Outputs, that fail to be sent, go to the owner as lostfound.
+    Abort,
+    /// The swap runs in a separate call, if it fails the transferred tokens are refunded as unused.
+    /// Outputs, that fail to be sent, are deposited to the sender or kept unclaimed for it.
+    RefundOrDeposit,
+}
+
 /// Message parameters to receive via token function call.
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -17,13 +37,33 @@ enum TokenReceiverMessage {
         referral_id: Option<ValidAccountId>,
         /// List of sequential actions.
         actions: Vec<Action>,
+        /// Optional deadline, after which the transfer is refunded instead.
+        deadline: Option<Deadline>,
+        /// Defaults to `Abort`.
+        failure_policy: Option<SwapFailurePolicy>,
+    },
+    /// Alternative to deposit + zap_liquidity call.
+    Zap {
+        pool_id: u64,
+        min_shares: U128,
+    },
+    /// Deposit to the given registered account instead of the sender.
+    DepositTo {
+        deposit_to: ValidAccountId,
+    },
+    /// Alternative to deposit + add_liquidity call, when the tokens arrive in separate transfers.
+    /// Transferred tokens are kept pending until every token of the pool arrives.
+    /// The liquidity is added with the highest `min_shares` among the transfers.
+    AddLiquidity {
+        add_liquidity_to: u64,
+        min_shares: U128,
     },
 }
 
 impl Contract {
     /// Executes set of actions on virtual account.
     /// Returns amounts to send to the sender directly.
-    fn internal_direct_actions(
+    pub(crate) fn internal_direct_actions(
         &mut self,
         token_in: AccountId,
         amount_in: Balance,
@@ -37,6 +77,7 @@ impl Contract {
         account.deposit(&token_in, amount_in);
         let _ = self.internal_execute_actions(
             &mut account,
+            &String::from(VIRTUAL_ACC),
             &referral_id,
             &actions,
             ActionResult::Amount(U128(amount_in)),
@@ -53,6 +94,153 @@ impl Contract {
         result
     }
 
+    /// Adds liquidity to a simple pool from tokens transferred by `sender_id`.
+    /// As nothing can be attached to the transfer, storage of the shares is paid from the sender's storage deposit.
+    /// Leftovers are deposited into the sender's account.
+    fn internal_zap_transferred_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        token_in: &AccountId,
+        amount_in: Balance,
+        pool_id: u64,
+        min_shares: Balance,
+    ) {
+        let prev_storage = env::storage_usage();
+        self.assert_pool_swap_allowed(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
+        let (_, leftovers) = pool.zap_liquidity(
+            sender_id,
+            token_in,
+            amount_in,
+            min_shares,
+            &AdminFees::new(self.exchange_fee),
+        );
+        self.pools.replace(pool_id, &pool);
+        let storage_cost = env::storage_usage().saturating_sub(prev_storage) as Balance
+            * env::storage_byte_cost();
+        let mut account = self.internal_unwrap_account(sender_id);
+        assert!(account.storage_available() >= storage_cost, "{}", ERR11_INSUFFICIENT_STORAGE);
+        account.near_amount -= storage_cost;
+        let tokens = pool.tokens();
+        for i in 0..tokens.len() {
+            account.deposit(&tokens[i], leftovers[i]);
+        }
+        self.internal_save_account(sender_id, account);
+        self.internal_update_pool_observations(pool_id, &pool);
+    }
+
+    /// Adds tokens transferred by `sender_id` to its pending liquidity of the pool,
+    /// and adds the liquidity once every token of the pool has arrived.
+    /// Storage of the pending liquidity and of the shares is paid from the sender's storage deposit.
+    /// Leftovers are deposited into the sender's account.
+    fn internal_add_transferred_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        token_in: &AccountId,
+        amount_in: Balance,
+        pool_id: u64,
+        min_shares: Balance,
+    ) {
+        self.assert_pool_add_liquidity_allowed(pool_id);
+        let pool = self.internal_get_pool(pool_id);
+        assert!(
+            !matches!(pool, Pool::ConcentratedLiquidityPool(_)),
+            "{}",
+            ERR37_TRANSFERRED_LIQUIDITY_NOT_SUPPORTED
+        );
+        let tokens = pool.tokens();
+        let index = tokens.iter().position(|id| id == token_in).expect(ERR63_MISSING_TOKEN);
+        let mut account = self.internal_unwrap_account(sender_id);
+        let prev_storage = env::storage_usage();
+        let mut pending = self.pending_liquidity.get(sender_id).unwrap_or_default();
+        let pool_pending = pending.entry(pool_id).or_insert_with(|| PendingLiquidity {
+            amounts: vec![0; tokens.len()],
+            min_shares: 0,
+        });
+        pool_pending.amounts[index] += amount_in;
+        pool_pending.min_shares = std::cmp::max(pool_pending.min_shares, min_shares);
+        let ready = if pool_pending.amounts.iter().all(|amount| *amount > 0) {
+            pending.remove(&pool_id)
+        } else {
+            None
+        };
+        if pending.is_empty() {
+            self.pending_liquidity.remove(sender_id);
+        } else {
+            self.pending_liquidity.insert(sender_id, &pending);
+        }
+        account.settle_storage(prev_storage);
+
+        if let Some(PendingLiquidity { amounts, min_shares }) = ready {
+            for (token_id, amount) in tokens.iter().zip(amounts.iter()) {
+                account.deposit(token_id, *amount);
+            }
+            let amounts = amounts.into_iter().map(|amount| Some(U128(amount))).collect();
+            let action = match pool {
+                Pool::StableSwapPool(_) | Pool::RatedSwapPool(_) => {
+                    Action::AddStableLiquidity(AddStableLiquidityAction {
+                        pool_id,
+                        amounts,
+                        min_shares: U128(min_shares),
+                    })
+                }
+                _ => Action::AddLiquidity(AddLiquidityAction {
+                    pool_id,
+                    amounts,
+                    min_amounts: None,
+                }),
+            };
+            let shares = self
+                .internal_execute_action(&mut account, sender_id, &None, &action, ActionResult::None)
+                .to_amount();
+            assert!(shares >= min_shares, "{}", ERR68_SLIPPAGE);
+        }
+        self.internal_save_account(sender_id, account);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Resolves refundable instant swap: returns amount to refund to the sender,
+    /// all of it if the swap failed.
+    #[private]
+    pub fn callback_refund_failed_swap(
+        &mut self,
+        sender_id: AccountId,
+        token_in: AccountId,
+        amount_in: U128,
+    ) -> U128 {
+        match env::promise_result(0) {
+            PromiseResult::Successful(result) => serde_json::from_slice::<U128>(&result).unwrap_or(U128(0)),
+            _ => {
+                log!("Instant swap failed, refund {} {} to {}", amount_in.0, token_in, sender_id);
+                amount_in
+            }
+        }
+    }
+
+    /// Moves tokens, that are pending to add liquidity to the pool, into the deposits of the sender.
+    #[payable]
+    pub fn cancel_pending_liquidity(&mut self, pool_id: u64) {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let sender_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&sender_id);
+        let prev_storage = env::storage_usage();
+        let mut pending = self.pending_liquidity.get(&sender_id).unwrap_or_default();
+        let amounts = pending.remove(&pool_id).expect(ERR36_NO_PENDING_LIQUIDITY).amounts;
+        if pending.is_empty() {
+            self.pending_liquidity.remove(&sender_id);
+        } else {
+            self.pending_liquidity.insert(&sender_id, &pending);
+        }
+        account.settle_storage(prev_storage);
+        let tokens = self.internal_get_pool(pool_id).tokens().to_vec();
+        for (token_id, amount) in tokens.iter().zip(amounts) {
+            account.deposit(token_id, amount);
+        }
+        self.internal_save_account(&sender_id, account);
+    }
 }
 
 #[near_bindgen]
@@ -80,8 +268,59 @@ impl FungibleTokenReceiver for Contract {
                 TokenReceiverMessage::Execute {
                     referral_id,
                     actions,
+                    deadline,
+                    failure_policy,
                 } => {
+                    if let Some(deadline) = &deadline {
+                        deadline.assert_not_expired();
+                    }
                     let referral_id = referral_id.map(|x| x.to_string());
+                    let refundable = failure_policy == Some(SwapFailurePolicy::RefundOrDeposit);
+                    // Instant swap can't be batched with `update_pool_rates`, so stale rates are fetched first
+                    // and the swap resumes in the callback, holding the transferred tokens until then.
+                    // Refundable swap always runs in the callback, so that its failure is resolved into a refund.
+                    let stale_rates =
+                        self.internal_update_stale_rates(actions.iter().flat_map(|action| action.pool_ids()));
+                    if stale_rates.is_some() || refundable {
+                        let (rates_promise, rate_updates) = match stale_rates {
+                            Some((promise, rate_updates)) => (Some(promise), rate_updates),
+                            None => (None, vec![]),
+                        };
+                        let mut callback_gas = self.internal_callback_gas(&rate_updates);
+                        if refundable {
+                            callback_gas = callback_gas.saturating_sub(GAS_FOR_BASIC_OP * 2);
+                        }
+                        let swap = ext_self::callback_instant_swap_with_rates(
+                            sender_id.to_string(),
+                            token_in.clone(),
+                            amount,
+                            referral_id,
+                            actions,
+                            deadline,
+                            failure_policy,
+                            rate_updates,
+                            &env::current_account_id(),
+                            NO_DEPOSIT,
+                            callback_gas,
+                        );
+                        let swap = match rates_promise {
+                            Some(promise) => promise.then(swap),
+                            None => swap,
+                        };
+                        if !refundable {
+                            return swap.into();
+                        }
+                        return swap
+                            .then(ext_self::callback_refund_failed_swap(
+                                sender_id.into(),
+                                token_in,
+                                amount,
+                                &env::current_account_id(),
+                                NO_DEPOSIT,
+                                GAS_FOR_BASIC_OP,
+                            ))
+                            .into();
+                    }
                     let out_amounts = self.internal_direct_actions(
                         token_in,
                         amount.0,
@@ -94,6 +333,36 @@ impl FungibleTokenReceiver for Contract {
                     // Even if send tokens fails, we don't return funds back to sender.
                     PromiseOrValue::Value(U128(0))
                 }
+                TokenReceiverMessage::DepositTo { deposit_to } => {
+                    self.internal_deposit(deposit_to.as_ref(), &token_in, amount.into());
+                    PromiseOrValue::Value(U128(0))
+                }
+                TokenReceiverMessage::AddLiquidity {
+                    add_liquidity_to,
+                    min_shares,
+                } => {
+                    self.internal_add_transferred_liquidity(
+                        sender_id.as_ref(),
+                        &token_in,
+                        amount.0,
+                        add_liquidity_to,
+                        min_shares.0,
+                    );
+                    PromiseOrValue::Value(U128(0))
+                }
+                TokenReceiverMessage::Zap {
+                    pool_id,
+                    min_shares,
+                } => {
+                    self.internal_zap_transferred_liquidity(
+                        sender_id.as_ref(),
+                        &token_in,
+                        amount.0,
+                        pool_id,
+                        min_shares.0,
+                    );
+                    PromiseOrValue::Value(U128(0))
+                }
             }
         }
     }
@@ -0,0 +1,243 @@
+//! Time-weighted average prices of the pools, to let other contracts use them as a price oracle.
+//! Prices are accumulated on every change of the pool and sampled into a small ring buffer.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen, Duration, Timestamp};
+
+use crate::errors::*;
+use crate::pool::Pool;
+use crate::utils::{U256, U384};
+use crate::*;
+
+/// Minimum time between two recorded observations, 2 minutes.
+pub const OBSERVATION_PERIOD: Duration = 120_000_000_000;
+
+/// Number of recorded observations kept for each pool.
+pub const MAX_NUM_OBSERVATIONS: usize = 30;
+
+/// Cumulative prices of the pool tokens at the given moment.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct Observation {
+    pub timestamp: Timestamp,
+    /// Sum of the spot price of each token multiplied by nanoseconds it was held, wraps around on overflow.
+    pub price_cumulatives: Vec<U256>,
+}
+
+impl Observation {
+    /// Moves accumulators forward to given timestamp, assuming `prices` were held since this observation.
+    fn advance(&self, timestamp: Timestamp, prices: &[U256]) -> Self {
+        let elapsed = U256::from(timestamp - self.timestamp);
+        Self {
+            timestamp,
+            price_cumulatives: self
+                .price_cumulatives
+                .iter()
+                .zip(prices.iter())
+                .map(|(cumulative, price)| cumulative.overflowing_add(price.overflowing_mul(elapsed).0).0)
+                .collect(),
+        }
+    }
+}
+
+/// Price accumulators of a single pool.
+/// Allocated in full when the pool is created, so that later updates don't take more storage.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct PoolObservations {
+    /// Whether the pool had spot prices yet, tracking starts from then.
+    pub started: bool,
+    /// Spot prices after the last change of the pool.
+    pub spot_prices: Vec<U256>,
+    /// Accumulators as of the last change of the pool.
+    pub last: Observation,
+    /// Ring buffer of recorded observations, at least OBSERVATION_PERIOD apart.
+    pub observations: Vec<Observation>,
+    /// Index of the most recent recorded observation.
+    pub latest_index: u32,
+}
+
+impl PoolObservations {
+    pub fn new(num_tokens: usize) -> Self {
+        let last = Observation {
+            timestamp: env::block_timestamp(),
+            price_cumulatives: vec![U256::zero(); num_tokens],
+        };
+        Self {
+            started: false,
+            spot_prices: vec![U256::zero(); num_tokens],
+            observations: vec![last.clone(); MAX_NUM_OBSERVATIONS],
+            last,
+            latest_index: 0,
+        }
+    }
+
+    /// Starts tracking from the current block with given `spot_prices`.
+    /// All the recorded observations are set to the starting one, which becomes the oldest.
+    pub fn start(&mut self, spot_prices: Vec<U256>) {
+        self.last = Observation {
+            timestamp: env::block_timestamp(),
+            price_cumulatives: vec![U256::zero(); spot_prices.len()],
+        };
+        for observation in self.observations.iter_mut() {
+            *observation = self.last.clone();
+        }
+        self.spot_prices = spot_prices;
+        self.latest_index = 0;
+        self.started = true;
+    }
+
+    /// Accumulates prices held since the last change of the pool and starts holding new `spot_prices`.
+    /// Records an observation if enough time has passed since the previous one.
+    pub fn update(&mut self, spot_prices: Vec<U256>) {
+        let now = env::block_timestamp();
+        self.last = self.last.advance(now, &self.spot_prices);
+        self.spot_prices = spot_prices;
+        let latest = &self.observations[self.latest_index as usize];
+        if now >= latest.timestamp + OBSERVATION_PERIOD {
+            let next_index = (self.latest_index as usize + 1) % MAX_NUM_OBSERVATIONS;
+            self.observations[next_index] = self.last.clone();
+            self.latest_index = next_index as u32;
+        }
+    }
+
+    /// Returns cumulative prices as of current block.
+    pub fn get_current(&self) -> Observation {
+        self.last.advance(env::block_timestamp(), &self.spot_prices)
+    }
+
+    /// Returns time-weighted average prices over the last `window` nanoseconds.
+    /// Cumulative prices in between recorded observations are interpolated linearly.
+    pub fn get_twap(&self, window: Duration) -> Vec<U256> {
+        assert!(window > 0, "{}", ERR76_INVALID_PARAMS);
+        let current = self.get_current();
+        let target = current
+            .timestamp
+            .checked_sub(window)
+            .expect(ERR151_TWAP_WINDOW_TOO_LONG);
+        // Recorded observations in chronological order, followed by the current one.
+        let len = self.observations.len();
+        let mut points = (1..=len)
+            .map(|i| &self.observations[(self.latest_index as usize + i) % len])
+            .chain(std::iter::once(&current));
+        let mut prev = points.next().unwrap();
+        assert!(prev.timestamp <= target, "{}", ERR151_TWAP_WINDOW_TOO_LONG);
+        let mut start = prev.price_cumulatives.clone();
+        for point in points {
+            if point.timestamp >= target {
+                if point.timestamp > prev.timestamp {
+                    let span = U384::from(point.timestamp - prev.timestamp);
+                    let elapsed = U384::from(target - prev.timestamp);
+                    for (cumulative, next) in start.iter_mut().zip(point.price_cumulatives.iter()) {
+                        let delta = U384::from(next.overflowing_sub(*cumulative).0) * elapsed / span;
+                        *cumulative = cumulative.overflowing_add(delta.to_u256().unwrap()).0;
+                    }
+                }
+                break;
+            }
+            prev = point;
+            start = prev.price_cumulatives.clone();
+        }
+        current
+            .price_cumulatives
+            .iter()
+            .zip(start.iter())
+            .map(|(to, from)| to.overflowing_sub(*from).0 / U256::from(window))
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Updates price accumulators of the pool after it was changed.
+    /// Tracking starts once the pool has spot prices, afterwards an empty pool accumulates zero prices.
+    /// Pools without allocated observations aren't tracked.
+    pub(crate) fn internal_update_pool_observations(&mut self, pool_id: u64, pool: &Pool) {
+        let mut observations = match self.pool_observations.get(&pool_id) {
+            Some(observations) => observations,
+            None => return,
+        };
+        match (observations.started, pool.get_spot_prices()) {
+            (false, None) => return,
+            (false, Some(spot_prices)) => observations.start(spot_prices),
+            (true, spot_prices) => observations.update(
+                spot_prices.unwrap_or_else(|| vec![U256::zero(); pool.tokens().len()]),
+            ),
+        }
+        self.pool_observations.insert(&pool_id, &observations);
+    }
+
+    pub(crate) fn internal_get_pool_observations(&self, pool_id: u64) -> PoolObservations {
+        let observations = self
+            .pool_observations
+            .get(&pool_id)
+            .expect(ERR150_NO_PRICE_OBSERVATIONS);
+        assert!(observations.started, "{}", ERR150_NO_PRICE_OBSERVATIONS);
+        observations
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Allocates price observations of a pool created before they were tracked, storage is paid by the caller.
+    /// Tracking starts with the next change of the pool.
+    #[payable]
+    pub fn allocate_pool_observations(&mut self, pool_id: u64) {
+        self.assert_contract_running();
+        let num_tokens = self.internal_get_pool(pool_id).tokens().len();
+        assert!(
+            self.pool_observations.get(&pool_id).is_none(),
+            "{}",
+            ERR152_PRICE_OBSERVATIONS_ALLOCATED
+        );
+        let prev_storage = env::storage_usage();
+        self.pool_observations.insert(&pool_id, &PoolObservations::new(num_tokens));
+        self.internal_check_storage(prev_storage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+
+    #[test]
+    fn test_observations_ring_buffer() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.block_timestamp(0).build());
+        let mut observations = PoolObservations::new(1);
+        observations.start(vec![U256::from(100)]);
+        // Price alternates between 100 and 300 every period.
+        for i in 1..=2 * MAX_NUM_OBSERVATIONS as u64 {
+            testing_env!(context.block_timestamp(i * OBSERVATION_PERIOD).build());
+            observations.update(vec![U256::from(if i % 2 == 0 { 100 } else { 300 })]);
+        }
+        assert_eq!(observations.observations.len(), MAX_NUM_OBSERVATIONS);
+        assert_eq!(observations.latest_index, 0);
+
+        testing_env!(context
+            .block_timestamp((2 * MAX_NUM_OBSERVATIONS as u64 + 1) * OBSERVATION_PERIOD)
+            .build());
+        assert_eq!(observations.get_twap(OBSERVATION_PERIOD), vec![U256::from(100)]);
+        assert_eq!(observations.get_twap(OBSERVATION_PERIOD / 2), vec![U256::from(100)]);
+        assert_eq!(observations.get_twap(2 * OBSERVATION_PERIOD), vec![U256::from(200)]);
+        // Oldest kept observation is recorded right at the start of the window.
+        let window = MAX_NUM_OBSERVATIONS as u64 * OBSERVATION_PERIOD;
+        assert_eq!(observations.get_twap(window), vec![U256::from(200)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E151: twap window exceeds recorded observations")]
+    fn test_twap_window_beyond_oldest_observation() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.block_timestamp(0).build());
+        let mut observations = PoolObservations::new(1);
+        observations.start(vec![U256::from(100)]);
+        for i in 1..=MAX_NUM_OBSERVATIONS as u64 {
+            testing_env!(context.block_timestamp(i * OBSERVATION_PERIOD).build());
+            observations.update(vec![U256::from(100)]);
+        }
+        // Observation at 0 was overwritten by the last update.
+        observations.get_twap(MAX_NUM_OBSERVATIONS as u64 * OBSERVATION_PERIOD - 1);
+        observations.get_twap(MAX_NUM_OBSERVATIONS as u64 * OBSERVATION_PERIOD);
+    }
+}
@@ -28,6 +28,9 @@ pub const FEE_DIVISOR: u32 = 10_000;
 /// Initial shares supply on deposit of liquidity.
 pub const INIT_SHARES_SUPPLY: u128 = 1_000_000_000_000_000_000_000_000;
 
+/// Precision of pool spot prices and their time-weighted averages.
+pub const PRICE_PRECISION: u128 = 1_000_000_000_000_000_000_000_000_000_000_000_000;
+
 construct_uint! {
     /// 256-bit unsigned integer.
     pub struct U256(4);
@@ -38,6 +41,37 @@ construct_uint! {
     pub struct U384(6);
 }
 
+impl From<U256> for U384 {
+    fn from(value: U256) -> Self {
+        let mut words = [0u64; 6];
+        words[..4].copy_from_slice(&value.0);
+        U384(words)
+    }
+}
+
+impl U384 {
+    /// Narrows down to U256, returns None if the value doesn't fit.
+    pub fn to_u256(self) -> Option<U256> {
+        if self.bits() > 256 {
+            None
+        } else {
+            Some(U256([self.0[0], self.0[1], self.0[2], self.0[3]]))
+        }
+    }
+}
+
+impl BorshSerialize for U256 {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        BorshSerialize::serialize(&self.0, writer)
+    }
+}
+
+impl BorshDeserialize for U256 {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        Ok(U256(BorshDeserialize::deserialize(buf)?))
+    }
+}
+
 /// Volume of swap on the given token.
 #[derive(Clone, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -63,6 +97,20 @@ pub trait RefExchange {
         sender_id: AccountId,
         amount: U128,
     );
+    fn exchange_callback_post_send(
+        &mut self,
+        token_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    );
+    fn exchange_callback_post_withdraw_call(
+        &mut self,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+    ) -> U128;
+    fn exchange_callback_post_near_deposit(&mut self, sender_id: AccountId, amount: U128);
+    fn exchange_callback_post_near_withdraw(&mut self, sender_id: AccountId, amount: U128);
 }
 
 /// Adds given value to item stored in the given key in the LookupMap collection.
@@ -88,6 +136,31 @@ pub fn integer_sqrt(value: U256) -> U256 {
     res
 }
 
+/// Newton's method of n-th root of `numerator / denominator`, which should be not above 1.
+/// Result is given as fraction of `precision`.
+pub fn nth_root_of_ratio(numerator: U256, denominator: U256, n: u32, precision: u128) -> U256 {
+    assert!(numerator <= denominator && !denominator.is_zero(), "{}", ERR76_INVALID_PARAMS);
+    let precision = U384::from(precision);
+    let value = U384::from(numerator) * precision / U384::from(denominator);
+    if value.is_zero() {
+        return U256::zero();
+    }
+    // Starting from 1, which is not below the root, guesses decrease until the root is reached.
+    let mut res = precision;
+    loop {
+        let mut quotient = value;
+        for _ in 1..n {
+            quotient = quotient * precision / res;
+        }
+        let guess = (res * (n - 1) + quotient) / n;
+        if guess >= res {
+            break;
+        }
+        res = guess;
+    }
+    U256::from(res.as_u128())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,4 +174,17 @@ mod tests {
             U256::from(1_231_323)
         );
     }
+
+    #[test]
+    fn test_nth_root_of_ratio() {
+        let precision = 10u128.pow(24);
+        assert_eq!(nth_root_of_ratio(U256::from(1), U256::from(1), 3, precision), precision.into());
+        assert_eq!(nth_root_of_ratio(U256::from(1), U256::from(4), 2, precision), (precision / 2).into());
+        // Rounding of intermediate divisions may lose a unit of precision.
+        let root = nth_root_of_ratio(U256::from(1), U256::from(8), 3, precision).as_u128();
+        assert!(precision / 2 - root <= 1);
+        let root = nth_root_of_ratio(U256::from(999), U256::from(1000), 8, precision).as_u128();
+        let expected = (0.999f64.powf(1.0 / 8.0) * precision as f64) as u128;
+        assert!((root as f64 - expected as f64).abs() / (precision as f64) < 1e-12);
+    }
 }
@@ -2,7 +2,7 @@
 
 use std::collections::HashMap;
 
-use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::json_types::{ValidAccountId, WrappedDuration, WrappedTimestamp, U128};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{near_bindgen, AccountId};
 
@@ -76,6 +76,22 @@ impl From<Pool> for PoolInfo {
                 total_fee: pool.total_fee,
                 shares_total_supply: U128(pool.shares_total_supply),
             },
+            Pool::WeightedPool(pool) => Self {
+                pool_kind,
+                amp: 0,
+                token_account_ids: pool.token_account_ids,
+                amounts: pool.amounts.into_iter().map(U128).collect(),
+                total_fee: pool.total_fee,
+                shares_total_supply: U128(pool.shares_total_supply),
+            },
+            Pool::ConcentratedLiquidityPool(pool) => Self {
+                pool_kind,
+                amp: 0,
+                token_account_ids: pool.token_account_ids,
+                amounts: pool.amounts.into_iter().map(U128).collect(),
+                total_fee: pool.total_fee,
+                shares_total_supply: U128(0),
+            },
         }
     }
 }
@@ -112,6 +128,8 @@ impl From<Pool> for StablePoolInfo {
                 shares_total_supply: U128(pool.shares_total_supply),
             },
             Pool::RatedSwapPool(_) => unimplemented!(),
+            Pool::WeightedPool(_) => unimplemented!(),
+            Pool::ConcentratedLiquidityPool(_) => unimplemented!(),
         }
     }
 }
@@ -133,7 +151,11 @@ pub struct RatedPoolInfo {
     pub shares_total_supply: U128,
     pub amp: u64,
     pub rates: Vec<U128>,
-    // pub rates_updated_at: u64,
+    /// Epoch height of the last update of the rates.
+    pub rates_updated_at: u64,
+    /// Time of the last update of the rates, zero if not updated since the limits by time were introduced.
+    pub rates_updated_timestamp: WrappedTimestamp,
+    pub rates_freshness: RatesFreshness,
 }
 
 impl From<Pool> for RatedPoolInfo {
@@ -150,12 +172,105 @@ impl From<Pool> for RatedPoolInfo {
                 total_fee: pool.total_fee,
                 shares_total_supply: U128(pool.shares_total_supply),
                 rates: pool.rates.get().into_iter().map(|&a| U128(a)).collect(),
-                // rates_updated_at: pool.rates_updated_at,
+                rates_updated_at: pool.rates.updated_at(),
+                rates_updated_timestamp: pool.rates_updated_timestamp.into(),
+                rates_freshness: pool.rates_freshness,
             },
+            Pool::WeightedPool(_) => unimplemented!(),
+            Pool::ConcentratedLiquidityPool(_) => unimplemented!(),
         }
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct WeightedPoolInfo {
+    /// List of tokens in the pool.
+    pub token_account_ids: Vec<AccountId>,
+    /// How much of each token is in the pool.
+    pub amounts: Vec<U128>,
+    /// Weights of the tokens in basis points.
+    pub weights: Vec<u32>,
+    /// Fee charged for swap.
+    pub total_fee: u32,
+    /// Total number of shares.
+    pub shares_total_supply: U128,
+}
+
+impl From<Pool> for WeightedPoolInfo {
+    fn from(pool: Pool) -> Self {
+        match pool {
+            Pool::WeightedPool(pool) => Self {
+                token_account_ids: pool.token_account_ids,
+                amounts: pool.amounts.into_iter().map(U128).collect(),
+                weights: pool.weights,
+                total_fee: pool.total_fee,
+                shares_total_supply: U128(pool.shares_total_supply),
+            },
+            _ => unimplemented!(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct ConcentratedPoolInfo {
+    /// List of tokens in the pool.
+    pub token_account_ids: Vec<AccountId>,
+    /// How much of each token is in the pool, including not collected fees.
+    pub amounts: Vec<U128>,
+    /// Fee charged for swap.
+    pub total_fee: u32,
+    pub tick_spacing: u32,
+    /// Current tick of the price.
+    pub tick: i32,
+    /// Square root of the current price in Q64.96, as decimal string.
+    pub sqrt_price: String,
+    /// Liquidity in range of the current price.
+    pub liquidity: U128,
+}
+
+impl From<Pool> for ConcentratedPoolInfo {
+    fn from(pool: Pool) -> Self {
+        match pool {
+            Pool::ConcentratedLiquidityPool(pool) => Self {
+                token_account_ids: pool.token_account_ids,
+                amounts: pool.amounts.into_iter().map(U128).collect(),
+                total_fee: pool.total_fee,
+                tick_spacing: pool.tick_spacing,
+                tick: pool.tick,
+                sqrt_price: pool.sqrt_price.to_string(),
+                liquidity: U128(pool.liquidity),
+            },
+            _ => unimplemented!(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PositionInfo {
+    pub owner_id: AccountId,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub liquidity: U128,
+    /// Accrued fees and removed liquidity, that would be paid out on collect.
+    pub tokens_owed: Vec<U128>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PriceCumulativesInfo {
+    pub timestamp: WrappedTimestamp,
+    /// Sum of the price of each token multiplied by nanoseconds it was held, as decimal strings.
+    /// Wraps around 2^256, so only differences between two readings make sense.
+    pub price_cumulatives: Vec<String>,
+}
+
 #[near_bindgen]
 impl Contract {
 
@@ -196,31 +311,83 @@ impl Contract {
 
     /// Returns information about specified pool.
     pub fn get_pool(&self, pool_id: u64) -> PoolInfo {
-        self.pools.get(pool_id).expect(ERR85_NO_POOL).into()
+        self.internal_get_pool(pool_id).into()
     }
 
     /// Returns stable pool information about specified pool.
     pub fn get_stable_pool(&self, pool_id: u64) -> StablePoolInfo {
-        self.pools.get(pool_id).expect(ERR85_NO_POOL).into()
+        self.internal_get_pool(pool_id).into()
     }
 
     /// Returns rated pool information about specified pool.
     pub fn get_rated_pool(&self, pool_id: u64) -> RatedPoolInfo {
-        self.pools.get(pool_id).expect(ERR85_NO_POOL).into()
+        self.internal_get_pool(pool_id).into()
+    }
+
+    /// Returns weighted pool information about specified pool.
+    pub fn get_weighted_pool(&self, pool_id: u64) -> WeightedPoolInfo {
+        self.internal_get_pool(pool_id).into()
+    }
+
+    /// Returns concentrated liquidity pool information about specified pool.
+    pub fn get_concentrated_pool(&self, pool_id: u64) -> ConcentratedPoolInfo {
+        self.internal_get_pool(pool_id).into()
+    }
+
+    /// Returns position in the concentrated liquidity pool, including fees accrued so far.
+    pub fn get_position(&self, pool_id: u64, position_id: u64) -> Option<PositionInfo> {
+        let pool = self.internal_get_concentrated_pool(pool_id);
+        pool.get_position(position_id).map(|position| PositionInfo {
+            owner_id: position.owner_id,
+            tick_lower: position.tick_lower,
+            tick_upper: position.tick_upper,
+            liquidity: U128(position.liquidity),
+            tokens_owed: pool
+                .get_position_tokens_owed(position_id)
+                .into_iter()
+                .map(U128)
+                .collect(),
+        })
+    }
+
+    /// Returns ids of positions of given account in the concentrated liquidity pool.
+    pub fn get_account_positions(&self, pool_id: u64, account_id: ValidAccountId) -> Vec<u64> {
+        self.internal_get_concentrated_pool(pool_id)
+            .get_account_positions(account_id.as_ref())
+    }
+
+    /// Return state of the given pool.
+    pub fn get_pool_state(&self, pool_id: u64) -> PoolState {
+        assert!(pool_id < self.pools.len(), "{}", ERR85_NO_POOL);
+        self.internal_get_pool_state(pool_id)
+    }
+
+    /// Returns all the pools that are not running, with their states.
+    pub fn get_paused_pools(&self) -> HashMap<u64, PoolState> {
+        self.pool_states.iter().collect()
     }
 
     /// Return total fee of the given pool.
     pub fn get_pool_fee(&self, pool_id: u64) -> u32 {
-        self.pools.get(pool_id).expect(ERR85_NO_POOL).get_fee()
+        self.internal_get_pool(pool_id).get_fee()
     }
 
     /// Return volumes of the given pool.
     pub fn get_pool_volumes(&self, pool_id: u64) -> Vec<SwapVolume> {
-        self.pools.get(pool_id).expect(ERR85_NO_POOL).get_volumes()
+        self.internal_get_pool(pool_id).get_volumes()
     }
 
+    /// Returns value of a share of the given pool with 1e8 precision.
+    /// For stable pools it's in comparable decimals, for simple and weighted pools it's denominated
+    /// in whole first tokens if their metadata is cached by `refresh_token_metadata`,
+    /// otherwise in their smallest units per smallest unit of the share.
     pub fn get_pool_share_price(&self, pool_id: u64) -> U128 {
-        self.pools.get(pool_id).expect(ERR85_NO_POOL).get_share_price().into()
+        let pool = self.internal_get_pool(pool_id);
+        let first_token_decimals = self
+            .token_metadata
+            .get(&pool.tokens()[0])
+            .map(|metadata| metadata.decimals);
+        pool.get_share_price(first_token_decimals).into()
     }
 
     /// Returns number of shares given account has in given pool.
@@ -261,7 +428,29 @@ impl Contract {
             .into()
     }
 
+    /// Returns tokens, that failed to be sent to given user and can be claimed with `claim_tokens`.
+    pub fn get_unclaimed_tokens(&self, account_id: ValidAccountId) -> HashMap<AccountId, U128> {
+        self.unclaimed_tokens
+            .get(account_id.as_ref())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(token_id, amount)| (token_id, U128(amount)))
+            .collect()
+    }
+
+    /// Returns amounts of the pool tokens, transferred by given user to add liquidity to the pools,
+    /// for the pools, that are still waiting for some of their tokens.
+    pub fn get_pending_liquidity(&self, account_id: ValidAccountId) -> HashMap<u64, Vec<U128>> {
+        self.pending_liquidity
+            .get(account_id.as_ref())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(pool_id, pending)| (pool_id, pending.amounts.into_iter().map(U128).collect()))
+            .collect()
+    }
+
     /// Given specific pool, returns amount of token_out recevied swapping amount_in of token_in.
+    /// For meta pool, one of the tokens can be a token of its base pool.
     pub fn get_return(
         &self,
         pool_id: u64,
@@ -269,11 +458,55 @@ impl Contract {
         amount_in: U128,
         token_out: ValidAccountId,
     ) -> U128 {
-        let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let pool = self.internal_get_pool(pool_id);
+        if let Some(base_pool_id) = pool.meta_base_pool_id() {
+            if !pool.tokens().contains(token_in.as_ref()) || !pool.tokens().contains(token_out.as_ref()) {
+                return self
+                    .internal_get_meta_pool_return(&pool, base_pool_id, token_in.as_ref(), amount_in.into(), token_out.as_ref())
+                    .into();
+            }
+        }
         pool.get_return(token_in.as_ref(), amount_in.into(), token_out.as_ref(), &AdminFees::new(self.exchange_fee))
             .into()
     }
 
+    /// Given specific pool, returns amount of token_in needed to receive amount_out of token_out.
+    pub fn get_return_by_output(
+        &self,
+        pool_id: u64,
+        token_in: ValidAccountId,
+        amount_out: U128,
+        token_out: ValidAccountId,
+    ) -> U128 {
+        let pool = self.internal_get_pool(pool_id);
+        pool.get_return_by_output(token_in.as_ref(), amount_out.into(), token_out.as_ref(), &AdminFees::new(self.exchange_fee))
+            .into()
+    }
+
+    /// Returns time-weighted average price of each token in the pool over the last `window` nanoseconds.
+    /// Prices are denominated in the first token of the pool, as decimal strings with precision of 1e36.
+    /// Window can't reach beyond the oldest observation kept, observations are recorded at most every 2 minutes.
+    pub fn get_pool_twap(&self, pool_id: u64, window: WrappedDuration) -> Vec<String> {
+        self.internal_get_pool_observations(pool_id)
+            .get_twap(window.into())
+            .into_iter()
+            .map(|price| price.to_string())
+            .collect()
+    }
+
+    /// Returns current cumulative prices of the pool, to calculate averages over arbitrary periods.
+    pub fn get_pool_price_cumulatives(&self, pool_id: u64) -> PriceCumulativesInfo {
+        let current = self.internal_get_pool_observations(pool_id).get_current();
+        PriceCumulativesInfo {
+            timestamp: current.timestamp.into(),
+            price_cumulatives: current
+                .price_cumulatives
+                .into_iter()
+                .map(|cumulative| cumulative.to_string())
+                .collect(),
+        }
+    }
+
     /// Get contract level whitelisted tokens.
     pub fn get_whitelisted_tokens(&self) -> Vec<AccountId> {
         self.whitelisted_tokens.to_vec()
@@ -301,23 +534,24 @@ impl Contract {
         }
     }
 
-    ///
+    /// Returns number of shares that adding given amounts to a simple or stable pool would mint.
     pub fn predict_add_stable_liquidity(
         &self,
         pool_id: u64,
         amounts: &Vec<U128>,
     ) -> U128 {
-        let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let pool = self.internal_get_pool(pool_id);
         pool.predict_add_stable_liquidity(&amounts.into_iter().map(|x| x.0).collect(), &AdminFees::new(self.exchange_fee))
             .into()
     }
 
+    /// Returns amounts of tokens that removing given number of shares from a simple, stable or rated pool would give.
     pub fn predict_remove_liquidity(
         &self,
         pool_id: u64,
         shares: U128,
     ) -> Vec<U128> {
-        let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let pool = self.internal_get_pool(pool_id);
         pool.predict_remove_liquidity(shares.into()).into_iter().map(|x| U128(x)).collect()
     }
 
@@ -326,11 +560,23 @@ impl Contract {
         pool_id: u64,
         amounts: &Vec<U128>,
     ) -> U128 {
-        let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let pool = self.internal_get_pool(pool_id);
         pool.predict_remove_liquidity_by_tokens(&amounts.into_iter().map(|x| x.0).collect(), &AdminFees::new(self.exchange_fee))
             .into()
     }
 
+    /// Returns amount of the token that removing given number of shares from a stable or rated pool into this single token would give.
+    pub fn predict_remove_liquidity_one_coin(
+        &self,
+        pool_id: u64,
+        shares: U128,
+        token_id: ValidAccountId,
+    ) -> U128 {
+        let pool = self.internal_get_pool(pool_id);
+        pool.predict_remove_liquidity_one_coin(shares.into(), token_id.as_ref(), &AdminFees::new(self.exchange_fee))
+            .into()
+    }
+
     ///
     pub fn predict_add_rated_liquidity(
         &self,
@@ -338,7 +584,7 @@ impl Contract {
         amounts: &Vec<U128>,
         rates: &Option<Vec<U128>>,
     ) -> U128 {
-        let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let pool = self.internal_get_pool(pool_id);
         let rates = match rates {
             Some(rates) => Some(rates.into_iter().map(|x| x.0).collect()),
             _ => None
@@ -357,7 +603,7 @@ impl Contract {
         amounts: &Vec<U128>,
         rates: &Option<Vec<U128>>,
     ) -> U128 {
-        let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let pool = self.internal_get_pool(pool_id);
         let rates = match rates {
             Some(rates) => Some(rates.into_iter().map(|x| x.0).collect()),
             _ => None
@@ -375,7 +621,7 @@ impl Contract {
         token_out: ValidAccountId,
         rates: &Option<Vec<U128>>,
     ) -> U128 {
-        let pool = self.pools.get(pool_id).expect(ERR85_NO_POOL);
+        let pool = self.internal_get_pool(pool_id);
         let rates = match rates {
             Some(rates) => Some(rates.into_iter().map(|x| x.0).collect()),
             _ => None
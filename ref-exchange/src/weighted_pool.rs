@@ -0,0 +1,778 @@
+use std::cmp::min;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{env, AccountId, Balance};
+
+use crate::admin_fee::AdminFees;
+use crate::errors::*;
+use crate::utils::{add_to_collection, SwapVolume, FEE_DIVISOR, INIT_SHARES_SUPPLY, U256};
+use crate::StorageKey;
+
+pub const MIN_NUM_TOKENS: usize = 2;
+pub const MAX_NUM_TOKENS: usize = 8;
+/// Weights are given in basis points and must sum up to this value.
+pub const WEIGHT_DIVISOR: u32 = 10_000;
+/// Minimal weight of a single token in the pool (1%).
+pub const MIN_WEIGHT: u32 = 100;
+
+/// Fixed point precision used by the weighted math.
+const ONE: u128 = 1_000_000_000_000_000_000;
+/// Precision at which the power series approximation stops.
+const POW_PRECISION: u128 = ONE / 10_000_000_000;
+
+/// Implementation of weighted pool, that maintains constant weighted product between balances of all the tokens:
+/// `prod(balance_i ^ weight_i)`. Similar in design to "Balancer".
+/// Liquidity providers when depositing receive shares, that can be later burnt to withdraw pool's tokens in proportion.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct WeightedPool {
+    /// List of tokens in the pool.
+    pub token_account_ids: Vec<AccountId>,
+    /// How much of each token is in the pool.
+    pub amounts: Vec<Balance>,
+    /// Weights of the tokens in basis points, sum up to WEIGHT_DIVISOR.
+    pub weights: Vec<u32>,
+    /// Volumes accumulated by this pool.
+    pub volumes: Vec<SwapVolume>,
+    /// Fee charged for swap (gets divided by FEE_DIVISOR).
+    pub total_fee: u32,
+    /// Shares of the pool by liquidity providers.
+    pub shares: LookupMap<AccountId, Balance>,
+    /// Total number of shares.
+    pub shares_total_supply: Balance,
+}
+
+impl WeightedPool {
+    pub fn new(
+        id: u32,
+        token_account_ids: Vec<ValidAccountId>,
+        weights: Vec<u32>,
+        total_fee: u32,
+    ) -> Self {
+        assert!(total_fee < FEE_DIVISOR, "{}", ERR90_FEE_TOO_LARGE);
+        assert!(
+            token_account_ids.len() >= MIN_NUM_TOKENS && token_account_ids.len() <= MAX_NUM_TOKENS,
+            "{}", ERR89_WRONG_TOKEN_COUNT
+        );
+        assert_eq!(weights.len(), token_account_ids.len(), "{}", ERR130_ILLEGAL_WEIGHTS);
+        assert!(
+            weights.iter().all(|&w| w >= MIN_WEIGHT)
+                && weights.iter().map(|&w| w as u64).sum::<u64>() == WEIGHT_DIVISOR as u64,
+            "{}", ERR130_ILLEGAL_WEIGHTS
+        );
+        let num_tokens = token_account_ids.len();
+        Self {
+            token_account_ids: token_account_ids.iter().map(|a| a.clone().into()).collect(),
+            amounts: vec![0u128; num_tokens],
+            weights,
+            volumes: vec![SwapVolume::default(); num_tokens],
+            total_fee,
+            shares: LookupMap::new(StorageKey::Shares {
+                pool_id: id,
+            }),
+            shares_total_supply: 0,
+        }
+    }
+
+    /// Register given account with 0 balance in shares.
+    /// Storage payment should be checked by caller.
+    pub fn share_register(&mut self, account_id: &AccountId) {
+        if self.shares.contains_key(account_id) {
+            env::panic(ERR14_LP_ALREADY_REGISTERED.as_bytes());
+        }
+        self.shares.insert(account_id, &0);
+    }
+
+    /// Transfers shares from predecessor to receiver.
+    pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        let balance = self.shares.get(sender_id).expect(ERR13_LP_NOT_REGISTERED);
+        if let Some(new_balance) = balance.checked_sub(amount) {
+            self.shares.insert(sender_id, &new_balance);
+        } else {
+            env::panic(ERR91_NOT_ENOUGH_SHARES.as_bytes());
+        }
+        let balance_out = self
+            .shares
+            .get(receiver_id)
+            .expect(ERR13_LP_NOT_REGISTERED);
+        self.shares.insert(receiver_id, &(balance_out + amount));
+    }
+
+    /// Returns balance of shares for given user.
+    pub fn share_balance_of(&self, account_id: &AccountId) -> Balance {
+        self.shares.get(account_id).unwrap_or_default()
+    }
+
+    /// Returns total number of shares in this pool.
+    pub fn share_total_balance(&self) -> Balance {
+        self.shares_total_supply
+    }
+
+    /// Returns list of tokens in this pool.
+    pub fn tokens(&self) -> &[AccountId] {
+        &self.token_account_ids
+    }
+
+    /// Returns number of shares minted for adding given amounts of tokens.
+    /// Liquidity is added in proportion to the current balances, so weights and prices stay the same.
+    /// Updates amounts to the ones that would be kept in the pool.
+    fn calc_add_liquidity(&self, amounts: &mut [Balance]) -> Balance {
+        assert_eq!(
+            amounts.len(),
+            self.token_account_ids.len(),
+            "{}", ERR89_WRONG_AMOUNT_COUNT
+        );
+        if self.shares_total_supply > 0 {
+            let mut fair_supply = U256::max_value();
+            for (amount, pool_amount) in amounts.iter().zip(self.amounts.iter()) {
+                assert!(*amount > 0, "{}", ERR31_ZERO_AMOUNT);
+                fair_supply = min(
+                    fair_supply,
+                    U256::from(*amount) * U256::from(self.shares_total_supply) / *pool_amount,
+                );
+            }
+            for (amount, pool_amount) in amounts.iter_mut().zip(self.amounts.iter()) {
+                *amount = (U256::from(*pool_amount) * fair_supply
+                    / U256::from(self.shares_total_supply))
+                .as_u128();
+                assert!(*amount > 0, "{}", ERR31_ZERO_AMOUNT);
+            }
+            fair_supply.as_u128()
+        } else {
+            // Initial amounts define the prices, as `price_i / price_j = (amount_j / weight_j) / (amount_i / weight_i)`.
+            for amount in amounts.iter() {
+                assert!(*amount > 0, "{}", ERR65_INIT_TOKEN_BALANCE);
+            }
+            INIT_SHARES_SUPPLY
+        }
+    }
+
+    /// Returns number of shares that adding given amounts of tokens would mint.
+    pub fn predict_add_liquidity(&self, amounts: &[Balance]) -> Balance {
+        self.calc_add_liquidity(&mut amounts.to_vec())
+    }
+
+    /// Adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
+    /// Updates amount to amount kept in the pool.
+    pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut [Balance]) -> Balance {
+        let shares = self.calc_add_liquidity(amounts);
+        for (amount, pool_amount) in amounts.iter().zip(self.amounts.iter_mut()) {
+            *pool_amount += *amount;
+        }
+        self.mint_shares(sender_id, shares);
+        assert!(shares > 0, "{}", ERR32_ZERO_SHARES);
+        env::log(
+            format!(
+                "Liquidity added {:?}, minted {} shares",
+                amounts
+                    .iter()
+                    .zip(self.token_account_ids.iter())
+                    .map(|(amount, token_id)| format!("{} {}", amount, token_id))
+                    .collect::<Vec<String>>(),
+                shares
+            )
+            .as_bytes(),
+        );
+        shares
+    }
+
+    /// Mint new shares for given user.
+    fn mint_shares(&mut self, account_id: &AccountId, shares: Balance) {
+        if shares == 0 {
+            return;
+        }
+        self.shares_total_supply += shares;
+        add_to_collection(&mut self.shares, account_id, shares);
+    }
+
+    /// Returns amounts of tokens that removing given number of shares would give.
+    pub fn predict_remove_liquidity(&self, shares: Balance) -> Vec<Balance> {
+        if self.shares_total_supply == 0 {
+            return vec![0; self.amounts.len()];
+        }
+        self.amounts
+            .iter()
+            .map(|amount| {
+                (U256::from(*amount) * U256::from(shares) / U256::from(self.shares_total_supply))
+                    .as_u128()
+            })
+            .collect()
+    }
+
+    /// Removes given number of shares from the pool and returns amounts to the parent.
+    pub fn remove_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        min_amounts: Vec<Balance>,
+    ) -> Vec<Balance> {
+        assert_eq!(
+            min_amounts.len(),
+            self.token_account_ids.len(),
+            "{}", ERR89_WRONG_AMOUNT_COUNT
+        );
+        let prev_shares_amount = self.shares.get(sender_id).expect(ERR13_LP_NOT_REGISTERED);
+        assert!(prev_shares_amount >= shares, "{}", ERR91_NOT_ENOUGH_SHARES);
+        let mut result = vec![];
+        for (pool_amount, min_amount) in self.amounts.iter_mut().zip(min_amounts.iter()) {
+            let amount = (U256::from(*pool_amount) * U256::from(shares)
+                / U256::from(self.shares_total_supply))
+            .as_u128();
+            assert!(amount >= *min_amount, "{}", ERR68_SLIPPAGE);
+            *pool_amount -= amount;
+            result.push(amount);
+        }
+        // Never unregister a LP when he removed all his liquidity.
+        self.shares.insert(sender_id, &(prev_shares_amount - shares));
+        env::log(
+            format!(
+                "{} shares of liquidity removed: receive back {:?}",
+                shares,
+                result
+                    .iter()
+                    .zip(self.token_account_ids.iter())
+                    .map(|(amount, token_id)| format!("{} {}", amount, token_id))
+                    .collect::<Vec<String>>(),
+            )
+            .as_bytes(),
+        );
+        self.shares_total_supply -= shares;
+        result
+    }
+
+    /// Returns token index for given pool.
+    fn token_index(&self, token_id: &AccountId) -> usize {
+        self.token_account_ids
+            .iter()
+            .position(|id| id == token_id)
+            .expect(ERR102_INVALID_TOKEN_ID)
+    }
+
+    /// Returns number of tokens in outcome, given amount.
+    /// Tokens are provided as indexes into token list for given pool.
+    /// out = balance_out * (1 - (balance_in / (balance_in + amount_in * (1 - fee))) ^ (weight_in / weight_out))
+    fn internal_get_return(
+        &self,
+        token_in: usize,
+        amount_in: Balance,
+        token_out: usize,
+    ) -> Balance {
+        let in_balance = U256::from(self.amounts[token_in]);
+        let out_balance = U256::from(self.amounts[token_out]);
+        assert!(
+            in_balance > U256::zero()
+                && out_balance > U256::zero()
+                && token_in != token_out
+                && amount_in > 0,
+            "{}", ERR76_INVALID_PARAMS
+        );
+        // Keeps the base of the power function in the range where the approximation converges fast.
+        assert!(U256::from(amount_in) * 2 <= in_balance, "{}", ERR131_MAX_IN_RATIO);
+        let amount_with_fee =
+            U256::from(amount_in) * U256::from(FEE_DIVISOR - self.total_fee) / U256::from(FEE_DIVISOR);
+        let weight_ratio = fdiv(
+            U256::from(self.weights[token_in]),
+            U256::from(self.weights[token_out]),
+        );
+        let base = fdiv(in_balance, in_balance + amount_with_fee);
+        let factor = fpow(base, weight_ratio);
+        if factor >= U256::from(ONE) {
+            return 0;
+        }
+        (out_balance * (U256::from(ONE) - factor) / U256::from(ONE)).as_u128()
+    }
+
+    /// Returns number of tokens needed as input to receive given amount in outcome.
+    /// Tokens are provided as indexes into token list for given pool.
+    /// in = balance_in * ((balance_out / (balance_out - amount_out)) ^ (weight_out / weight_in) - 1) / (1 - fee)
+    fn internal_get_return_by_output(
+        &self,
+        token_in: usize,
+        amount_out: Balance,
+        token_out: usize,
+    ) -> Balance {
+        let in_balance = U256::from(self.amounts[token_in]);
+        let out_balance = U256::from(self.amounts[token_out]);
+        assert!(
+            in_balance > U256::zero()
+                && out_balance > U256::from(amount_out)
+                && token_in != token_out
+                && amount_out > 0,
+            "{}", ERR76_INVALID_PARAMS
+        );
+        // Keeps the base of the power function in the range where the approximation converges fast.
+        assert!(U256::from(amount_out) * 2 <= out_balance, "{}", ERR131_MAX_IN_RATIO);
+        let weight_ratio = fdiv(
+            U256::from(self.weights[token_out]),
+            U256::from(self.weights[token_in]),
+        );
+        let base = fdiv(out_balance, out_balance - U256::from(amount_out));
+        let factor = fpow(base, weight_ratio);
+        // Amounts below the precision of the weighted math can't be priced, and must not be given away for free.
+        assert!(factor > U256::from(ONE), "{}", ERR70_SWAP_OUT_CALC_ERR);
+        // Rounded up with a margin for the power approximation error,
+        // so that swapping the result gives not less than amount_out.
+        let numerator = in_balance * (factor - U256::from(ONE)) * U256::from(FEE_DIVISOR);
+        let denominator = U256::from(ONE) * U256::from(FEE_DIVISOR - self.total_fee);
+        let amount_in = (numerator + denominator - 1) / denominator;
+        (amount_in + amount_in * U256::from(POW_PRECISION) * 10 / U256::from(ONE) + 1).as_u128()
+    }
+
+    /// Returns how much of `token_in` is needed to receive `amount_out` of `token_out`.
+    pub fn get_return_by_output(
+        &self,
+        token_in: &AccountId,
+        amount_out: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.internal_get_return_by_output(
+            self.token_index(token_in),
+            amount_out,
+            self.token_index(token_out),
+        )
+    }
+
+    /// Returns how much token you will receive if swap `token_amount_in` of `token_in` for `token_out`.
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.internal_get_return(
+            self.token_index(token_in),
+            amount_in,
+            self.token_index(token_out),
+        )
+    }
+
+    /// Same as `get_return`, but returns None instead of failing if the pool has no liquidity of given tokens
+    /// or the amount exceeds half of the pool balance.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Option<Balance> {
+        let (in_idx, out_idx) = (self.token_index(token_in), self.token_index(token_out));
+        if in_idx == out_idx
+            || amount_in == 0
+            || self.amounts[out_idx] == 0
+            || U256::from(amount_in) * 2 > U256::from(self.amounts[in_idx])
+        {
+            return None;
+        }
+        Some(self.internal_get_return(in_idx, amount_in, out_idx))
+    }
+
+    /// Returns value of a whole share (shares have 24 decimals) in whole first tokens of given `decimals`,
+    /// with 1e8 precision. Value of each token in the pool is proportional to its weight,
+    /// so the pool is worth the amount of the first token divided by its weight.
+    pub fn get_share_price(&self, decimals: u8) -> u128 {
+        if self.shares_total_supply == 0 {
+            return 0;
+        }
+        (U256::from(self.amounts[0])
+            * U256::from(WEIGHT_DIVISOR)
+            * U256::from(100000000)
+            * U256::from(10u128.pow(24))
+            / U256::from(self.weights[0])
+            / U256::from(self.shares_total_supply)
+            / U256::from(10u128.pow(decimals as u32)))
+        .as_u128()
+    }
+
+    /// Returns given pool's total fee.
+    pub fn get_fee(&self) -> u32 {
+        self.total_fee
+    }
+
+    /// Returns volumes of the given pool.
+    pub fn get_volumes(&self) -> Vec<SwapVolume> {
+        self.volumes.clone()
+    }
+
+    /// Returns how many shares should be issued for `amount` of token `token_idx`,
+    /// that is already accounted in the pool balance, as if it was a single sided deposit.
+    /// shares = supply * ((balance / (balance - amount)) ^ normalized_weight - 1)
+    fn single_token_shares(&self, token_idx: usize, amount: Balance) -> Balance {
+        let balance = U256::from(self.amounts[token_idx]);
+        if amount == 0 || self.shares_total_supply == 0 || U256::from(amount) >= balance {
+            return 0;
+        }
+        let ratio = fdiv(balance, balance - U256::from(amount));
+        let normalized_weight =
+            U256::from(self.weights[token_idx]) * U256::from(ONE) / U256::from(WEIGHT_DIVISOR);
+        let pool_ratio = fpow(ratio, normalized_weight);
+        if pool_ratio <= U256::from(ONE) {
+            return 0;
+        }
+        (U256::from(self.shares_total_supply) * (pool_ratio - U256::from(ONE)) / U256::from(ONE))
+            .as_u128()
+    }
+
+    /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
+    /// Assuming that `token_amount_in` was already received from `sender_id`.
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        assert_ne!(token_in, token_out, "{}", ERR73_SAME_TOKEN);
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        let amount_out = self.internal_get_return(in_idx, amount_in, out_idx);
+        assert!(amount_out >= min_amount_out, "{}", ERR68_SLIPPAGE);
+        self.internal_swap(in_idx, amount_in, out_idx, amount_out, admin_fee);
+        amount_out
+    }
+
+    /// Swap `token_in` into exactly `amount_out` of `token_out` and return how much of `token_in` was used.
+    /// Assuming that `max_amount_in` was already received from `sender_id`.
+    pub fn swap_by_output(
+        &mut self,
+        token_in: &AccountId,
+        amount_out: Balance,
+        token_out: &AccountId,
+        max_amount_in: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        assert_ne!(token_in, token_out, "{}", ERR73_SAME_TOKEN);
+        let in_idx = self.token_index(token_in);
+        let out_idx = self.token_index(token_out);
+        let amount_in = self.internal_get_return_by_output(in_idx, amount_out, out_idx);
+        assert!(amount_in <= max_amount_in, "{}", ERR68_SLIPPAGE);
+        self.internal_swap(in_idx, amount_in, out_idx, amount_out, admin_fee);
+        amount_in
+    }
+
+    /// Moves swapped amounts into and out of the pool and allocates admin fees.
+    fn internal_swap(
+        &mut self,
+        in_idx: usize,
+        amount_in: Balance,
+        out_idx: usize,
+        amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) {
+        let token_in = &self.token_account_ids[in_idx];
+        let token_out = &self.token_account_ids[out_idx];
+        env::log(
+            format!(
+                "Swapped {} {} for {} {}",
+                amount_in, token_in, amount_out, token_out
+            )
+            .as_bytes(),
+        );
+
+        self.amounts[in_idx] += amount_in;
+        self.amounts[out_idx] -= amount_out;
+
+        // Admin fees are a fraction of the swap fee, which stays in the pool as token_in.
+        // They are allocated by issuing LP shares as if that part of the fee was deposited single sided.
+        let fee_amount = U256::from(amount_in) * U256::from(self.total_fee) / U256::from(FEE_DIVISOR);
+        let exchange_amount =
+            (fee_amount * U256::from(admin_fee.exchange_fee) / U256::from(FEE_DIVISOR)).as_u128();
+        let referral_id = admin_fee
+            .referral_id
+            .as_ref()
+            .filter(|referral_id| admin_fee.referral_fee > 0 && self.shares.contains_key(referral_id));
+        let referral_amount = if referral_id.is_some() {
+            (fee_amount * U256::from(admin_fee.referral_fee) / U256::from(FEE_DIVISOR)).as_u128()
+        } else {
+            0
+        };
+        let admin_shares = self.single_token_shares(in_idx, exchange_amount + referral_amount);
+        if admin_shares > 0 {
+            let referral_shares = (U256::from(admin_shares) * U256::from(referral_amount)
+                / U256::from(exchange_amount + referral_amount))
+            .as_u128();
+            self.mint_shares(&admin_fee.exchange_id, admin_shares - referral_shares);
+            if let Some(referral_id) = referral_id {
+                self.mint_shares(referral_id, referral_shares);
+            }
+        }
+
+        // Keeping track of volume per each input traded separately.
+        // Reported volume with fees will be sum of `input`, without fees will be sum of `output`.
+        self.volumes[in_idx].input.0 += amount_in;
+        self.volumes[in_idx].output.0 += amount_out;
+    }
+}
+
+/// Fixed point multiplication, rounding to the nearest.
+fn fmul(a: U256, b: U256) -> U256 {
+    (a * b + U256::from(ONE / 2)) / U256::from(ONE)
+}
+
+/// Fixed point division, rounding to the nearest.
+fn fdiv(a: U256, b: U256) -> U256 {
+    (a * U256::from(ONE) + b / 2) / b
+}
+
+/// Returns `|a - b|` and whether `a < b`.
+fn sub_sign(a: U256, b: U256) -> (U256, bool) {
+    if a >= b {
+        (a - b, false)
+    } else {
+        (b - a, true)
+    }
+}
+
+/// Fixed point `base ^ n` for whole `n`, by squaring.
+fn fpowi(mut base: U256, mut n: u128) -> U256 {
+    let mut result = if n % 2 == 1 { base } else { U256::from(ONE) };
+    n /= 2;
+    while n != 0 {
+        base = fmul(base, base);
+        if n % 2 == 1 {
+            result = fmul(result, base);
+        }
+        n /= 2;
+    }
+    result
+}
+
+/// Fixed point `base ^ exp` for `exp` < 1, using binomial series of `(1 + x) ^ exp`.
+fn fpow_approx(base: U256, exp: U256) -> U256 {
+    let one = U256::from(ONE);
+    let (x, x_neg) = sub_sign(base, one);
+    let mut term = one;
+    let mut sum = term;
+    let mut negative = false;
+    let mut k = 1u128;
+    while term >= U256::from(POW_PRECISION) {
+        let big_k = U256::from(k * ONE);
+        let (c, c_neg) = sub_sign(exp, big_k - one);
+        term = fdiv(fmul(term, fmul(c, x)), big_k);
+        if term.is_zero() {
+            break;
+        }
+        if x_neg {
+            negative = !negative;
+        }
+        if c_neg {
+            negative = !negative;
+        }
+        if negative {
+            sum -= term;
+        } else {
+            sum += term;
+        }
+        k += 1;
+    }
+    sum
+}
+
+/// Fixed point `base ^ exp`, base should be within (0, 2).
+fn fpow(base: U256, exp: U256) -> U256 {
+    assert!(
+        base > U256::zero() && base < U256::from(2 * ONE),
+        "{}", ERR76_INVALID_PARAMS
+    );
+    let whole = exp / U256::from(ONE);
+    let remain = exp - whole * U256::from(ONE);
+    let whole_pow = fpowi(base, whole.as_u128());
+    if remain.is_zero() {
+        return whole_pow;
+    }
+    fmul(whole_pow, fpow_approx(base, remain))
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+    use near_sdk_sim::to_yocto;
+
+    use super::*;
+
+    fn admin_fees(exchange_fee: u32) -> AdminFees {
+        AdminFees {
+            exchange_fee,
+            exchange_id: accounts(3).as_ref().clone(),
+            referral_fee: 0,
+            referral_id: None,
+        }
+    }
+
+    fn assert_close(actual: u128, expected: f64) {
+        let diff = (actual as f64 - expected).abs();
+        assert!(diff / expected < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_fpow() {
+        let one = U256::from(ONE);
+        assert_eq!(fpow(one / 2, one * 3), one / 8);
+        // 0.5 ^ 0.5
+        assert_close(fpow(one / 2, one / 2).as_u128(), 0.5f64.sqrt() * ONE as f64);
+        // 1.5 ^ 2.25
+        assert_close(
+            fpow(one * 3 / 2, one * 9 / 4).as_u128(),
+            1.5f64.powf(2.25) * ONE as f64,
+        );
+    }
+
+    #[test]
+    fn test_pool_swap_equal_weights() {
+        let one_near = 10u128.pow(24);
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut pool = WeightedPool::new(0, vec![accounts(1), accounts(2)], vec![5000, 5000], 0);
+        let mut amounts = vec![to_yocto("5"), to_yocto("10")];
+        let num_shares = pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        assert_eq!(num_shares, INIT_SHARES_SUPPLY);
+        let expected = pool.get_return(accounts(1).as_ref(), one_near, accounts(2).as_ref());
+        let out = pool.swap(accounts(1).as_ref(), one_near, accounts(2).as_ref(), 1, &admin_fees(0));
+        assert_eq!(out, expected);
+        // 50/50 weighted pool is a constant product pool.
+        assert_close(out, 10.0 * one_near as f64 / 6.0);
+        assert_eq!(pool.amounts, vec![to_yocto("6"), to_yocto("10") - out]);
+        assert_eq!(pool.share_balance_of(accounts(3).as_ref()), 0);
+        assert_eq!(
+            pool.remove_liquidity(accounts(0).as_ref(), num_shares / 2, vec![1, 1]),
+            [3 * one_near, (to_yocto("10") - out) / 2]
+        );
+    }
+
+    #[test]
+    fn test_pool_swap_unequal_weights() {
+        let one_near = 10u128.pow(24);
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut pool = WeightedPool::new(
+            0,
+            vec![accounts(1), accounts(2), accounts(4)],
+            vec![6000, 2000, 2000],
+            30,
+        );
+        let mut amounts = vec![to_yocto("30"), to_yocto("10"), to_yocto("10")];
+        pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        let out = pool.swap(accounts(1).as_ref(), one_near, accounts(2).as_ref(), 1, &admin_fees(0));
+        let expected = 10.0 * (1.0 - (30.0f64 / (30.0 + 0.997)).powf(3.0)) * one_near as f64;
+        assert_close(out, expected);
+        let out = pool.swap(accounts(4).as_ref(), one_near, accounts(1).as_ref(), 1, &admin_fees(0));
+        let expected = 31.0 * (1.0 - (10.0f64 / (10.0 + 0.997)).powf(1.0 / 3.0)) * one_near as f64;
+        assert_close(out, expected);
+    }
+
+    #[test]
+    fn test_pool_swap_by_output() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut pool = WeightedPool::new(0, vec![accounts(1), accounts(2)], vec![8000, 2000], 30);
+        let mut amounts = vec![to_yocto("40"), to_yocto("10")];
+        pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        for amount_out in [to_yocto("0.001"), to_yocto("0.01"), to_yocto("1"), to_yocto("4")] {
+            let amount_in = pool.get_return_by_output(accounts(1).as_ref(), amount_out, accounts(2).as_ref());
+            let received = pool.get_return(accounts(1).as_ref(), amount_in, accounts(2).as_ref());
+            assert!(received >= amount_out);
+            assert!(received - amount_out <= amount_out / 1_000_000);
+        }
+        let amount_out = to_yocto("1");
+        let expected = pool.get_return_by_output(accounts(1).as_ref(), amount_out, accounts(2).as_ref());
+        let amount_in = pool.swap_by_output(
+            accounts(1).as_ref(),
+            amount_out,
+            accounts(2).as_ref(),
+            expected,
+            &admin_fees(0),
+        );
+        assert_eq!(amount_in, expected);
+        assert_eq!(pool.amounts, vec![to_yocto("40") + amount_in, to_yocto("9")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E68: slippage error")]
+    fn test_pool_swap_by_output_slippage() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut pool = WeightedPool::new(0, vec![accounts(1), accounts(2)], vec![5000, 5000], 30);
+        let mut amounts = vec![to_yocto("10"), to_yocto("10")];
+        pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        pool.swap_by_output(accounts(1).as_ref(), to_yocto("1"), accounts(2).as_ref(), to_yocto("1"), &admin_fees(0));
+    }
+
+    #[test]
+    fn test_pool_swap_with_fees() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut pool = WeightedPool::new(0, vec![accounts(1), accounts(2)], vec![8000, 2000], 100);
+        let mut amounts = vec![to_yocto("40"), to_yocto("10")];
+        let num_shares = pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        let out = pool.swap(
+            accounts(1).as_ref(),
+            to_yocto("1"),
+            accounts(2).as_ref(),
+            1,
+            &admin_fees(2000),
+        );
+        let num_shares2 = pool.share_balance_of(accounts(3).as_ref());
+        assert!(num_shares2 > 0);
+        let liq1 = pool.remove_liquidity(accounts(0).as_ref(), num_shares, vec![1, 1]);
+        let liq2 = pool.remove_liquidity(accounts(3).as_ref(), num_shares2, vec![0, 0]);
+        assert_eq!(liq1[0] + liq2[0], to_yocto("41"));
+        assert_eq!(liq1[1] + liq2[1], to_yocto("10") - out);
+        // Exchange gets 20% of 1% fee of 1 token, which is withdrawn 80% in that token.
+        let expected = to_yocto("0.002") as f64 * 0.8;
+        assert!((liq2[0] as f64 - expected).abs() / expected < 1e-3);
+    }
+
+    #[test]
+    fn test_predict_liquidity() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut pool = WeightedPool::new(0, vec![accounts(1), accounts(2)], vec![8000, 2000], 30);
+        assert_eq!(pool.predict_remove_liquidity(INIT_SHARES_SUPPLY), vec![0, 0]);
+        let amounts = vec![to_yocto("40"), to_yocto("10")];
+        assert_eq!(pool.predict_add_liquidity(&amounts), INIT_SHARES_SUPPLY);
+        pool.add_liquidity(accounts(0).as_ref(), &mut amounts.clone());
+
+        // Extra amount of the first token isn't taken.
+        let amounts = vec![to_yocto("5"), to_yocto("1")];
+        let predicted = pool.predict_add_liquidity(&amounts);
+        assert_eq!(predicted, INIT_SHARES_SUPPLY / 10);
+        assert_eq!(pool.add_liquidity(accounts(1).as_ref(), &mut amounts.clone()), predicted);
+
+        let predicted = pool.predict_remove_liquidity(predicted);
+        assert_eq!(predicted, vec![to_yocto("4"), to_yocto("1")]);
+        assert_eq!(
+            pool.remove_liquidity(accounts(1).as_ref(), INIT_SHARES_SUPPLY / 10, vec![0, 0]),
+            predicted
+        );
+    }
+
+    #[test]
+    fn test_share_price() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut pool = WeightedPool::new(0, vec![accounts(1), accounts(2)], vec![8000, 2000], 30);
+        assert_eq!(pool.get_share_price(24), 0);
+        let mut amounts = vec![40 * 10u128.pow(6), to_yocto("10")];
+        pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        // First token is 80% of the value, so the initial whole share is worth 50 of it.
+        assert_eq!(pool.get_share_price(6), 50 * 100000000);
+    }
+
+    #[test]
+    #[should_panic(expected = "E130: illegal weights")]
+    fn test_weights_sum() {
+        testing_env!(VMContextBuilder::new().build());
+        WeightedPool::new(0, vec![accounts(1), accounts(2)], vec![5000, 4000], 30);
+    }
+
+    #[test]
+    #[should_panic(expected = "E130: illegal weights")]
+    fn test_weight_too_small() {
+        testing_env!(VMContextBuilder::new().build());
+        WeightedPool::new(0, vec![accounts(1), accounts(2)], vec![9950, 50], 30);
+    }
+
+    #[test]
+    #[should_panic(expected = "E131: swap amount exceeds half of the pool balance")]
+    fn test_max_in_ratio() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut pool = WeightedPool::new(0, vec![accounts(1), accounts(2)], vec![5000, 5000], 30);
+        let mut amounts = vec![to_yocto("5"), to_yocto("10")];
+        pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        pool.get_return(accounts(1).as_ref(), to_yocto("3"), accounts(2).as_ref());
+    }
+}
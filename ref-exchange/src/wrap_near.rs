@@ -0,0 +1,121 @@
+//! Native NEAR deposits, wrapped into wNEAR on the way in and unwrapped on the way out,
+//! so that NEAR can be traded as wNEAR from the inner account.
+
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, ext_contract, near_bindgen, AccountId, Balance, Promise, PromiseResult};
+
+use crate::utils::{ext_self, GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER, GAS_FOR_RESOLVE_TRANSFER};
+use crate::*;
+
+#[ext_contract(ext_wrap_near)]
+pub trait WrapNear {
+    fn near_deposit(&mut self);
+    fn near_withdraw(&mut self, amount: U128);
+}
+
+impl Contract {
+    fn internal_unwrap_wnear_id(&self) -> AccountId {
+        self.wnear_id.clone().expect(ERR180_WNEAR_NOT_SET)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Wraps attached NEAR into wNEAR and deposits it into the inner account of the sender.
+    /// The sender should be registered and have wNEAR registered, unless wNEAR is whitelisted,
+    /// in which case wNEAR gets registered here, so the sender should have storage for it.
+    #[payable]
+    pub fn deposit_near(&mut self) -> Promise {
+        self.assert_contract_running();
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "{}", ERR35_AT_LEAST_ONE_YOCTO);
+        let wnear_id = self.internal_unwrap_wnear_id();
+        let sender_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&sender_id);
+        if account.get_balance(&wnear_id).is_none() {
+            assert!(self.whitelisted_tokens.contains(&wnear_id), "{}", ERR12_TOKEN_NOT_WHITELISTED);
+            // Register wNEAR before wrapping, so that the wrapped NEAR can't miss the storage in the callback.
+            assert!(account.deposit_with_storage_check(&wnear_id, 0), "{}", ERR11_INSUFFICIENT_STORAGE);
+            self.internal_save_account(&sender_id, account);
+        }
+        ext_wrap_near::near_deposit(&wnear_id, amount, GAS_FOR_BASIC_OP).then(
+            ext_self::exchange_callback_post_near_deposit(
+                sender_id,
+                U128(amount),
+                &env::current_account_id(),
+                0,
+                GAS_FOR_RESOLVE_TRANSFER,
+            ),
+        )
+    }
+
+    /// Unwraps given amount of wNEAR from the inner account of the sender and sends it as native NEAR.
+    /// A zero amount means to withdraw all the wNEAR.
+    #[payable]
+    pub fn withdraw_near(&mut self, amount: U128) -> Promise {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let wnear_id = self.internal_unwrap_wnear_id();
+        let sender_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&sender_id);
+        let mut amount: Balance = amount.into();
+        if amount == 0 {
+            amount = account.get_balance(&wnear_id).expect(ERR21_TOKEN_NOT_REG);
+        }
+        assert!(amount > 0, "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT);
+        account.withdraw(&wnear_id, amount);
+        self.internal_save_account(&sender_id, account);
+        ext_wrap_near::near_withdraw(U128(amount), &wnear_id, 1, GAS_FOR_FT_TRANSFER).then(
+            ext_self::exchange_callback_post_near_withdraw(
+                sender_id,
+                U128(amount),
+                &env::current_account_id(),
+                0,
+                GAS_FOR_RESOLVE_TRANSFER,
+            ),
+        )
+    }
+
+    /// Deposits wrapped NEAR to the sender, or returns the NEAR if wrapping failed.
+    #[private]
+    pub fn exchange_callback_post_near_deposit(&mut self, sender_id: AccountId, amount: U128) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR181_CALLBACK_POST_WRAP_NEAR_INVALID
+        );
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {
+                let wnear_id = self.internal_unwrap_wnear_id();
+                self.internal_deposit_or_lostfound(&wnear_id, &sender_id, amount.0);
+            }
+            PromiseResult::Failed => {
+                log!("Failed to wrap NEAR, refund {} to {}", amount.0, sender_id);
+                Promise::new(sender_id).transfer(amount.0);
+            }
+        };
+    }
+
+    /// Sends unwrapped NEAR to the sender, or returns the wNEAR back to its deposits if unwrapping failed.
+    #[private]
+    pub fn exchange_callback_post_near_withdraw(&mut self, sender_id: AccountId, amount: U128) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR181_CALLBACK_POST_WRAP_NEAR_INVALID
+        );
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {
+                Promise::new(sender_id).transfer(amount.0);
+            }
+            PromiseResult::Failed => {
+                let wnear_id = self.internal_unwrap_wnear_id();
+                self.internal_deposit_or_lostfound(&wnear_id, &sender_id, amount.0);
+            }
+        };
+    }
+}
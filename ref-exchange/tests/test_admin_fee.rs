@@ -63,6 +63,7 @@ fn modify_admin_fee() {
                 token_out: eth(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1
@@ -111,6 +112,7 @@ fn modify_admin_fee() {
                 token_out: eth(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1
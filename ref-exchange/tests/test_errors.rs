@@ -7,7 +7,7 @@
 use near_sdk::json_types::{U128, U64};
 use near_sdk_sim::{init_simulator, call, view, to_yocto, ExecutionResult, runtime};
 
-use ref_exchange::SwapAction;
+use ref_exchange::{Deadline, SwapAction};
 use crate::common::utils::*;
 pub mod common;
 
@@ -150,6 +150,7 @@ fn sim_stable_e63 () {
                 token_out: usdt(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1
@@ -424,6 +425,7 @@ fn sim_stable_e68 () {
                 token_out: usdt(),
                 min_amount_out: U128(2 * ONE_USDT)
             }],
+            None,
             None
         ),
         deposit = 1
@@ -490,6 +492,7 @@ fn sim_stable_e69 () {
                 token_out: usdt(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1
@@ -533,6 +536,7 @@ fn sim_stable_e71 () {
                 token_out: dai(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1
@@ -540,6 +544,52 @@ fn sim_stable_e71 () {
     assert_failure(outcome, "E71: illegal swap with duplicated tokens");
 }
 
+#[test]
+fn sim_stable_e74 () {
+    let mut gc = runtime::GenesisConfig::default();
+    gc.genesis_time = 86400 * 1_000_000_000;
+    let root = init_simulator(Some(gc));
+    let (owner, ex) = setup_exchange(&root, 1600, 400);
+    let token1 = test_token(&root, dai(), vec![ex.account_id()]);
+    let token2 = test_token(&root, usdt(), vec![ex.account_id()]);
+    whitelist_token(&owner, &ex, vec![token1.valid_account_id(), token2.valid_account_id()]);
+    deposit_token(&root, &ex, vec![&token1, &token2], vec![101*ONE_DAI, 101*ONE_USDT]);
+
+    call!(
+        owner,
+        ex.add_stable_swap_pool(
+            vec![token1.valid_account_id(), token2.valid_account_id()], 
+            vec![18, 6],
+            25,
+            10000
+        ),
+        deposit = to_yocto("1")
+    ).assert_success();
+    call!(
+        root,
+        ex.add_stable_liquidity(0, vec![U128(100*ONE_DAI), U128(100*ONE_USDT)], U128(1)),
+        deposit = to_yocto("0.01")
+    )
+    .assert_success();
+
+    let outcome = call!(
+        root,
+        ex.swap(
+            vec![SwapAction {
+                pool_id: 0,
+                token_in: dai(),
+                amount_in: Some(U128(ONE_DAI)),
+                token_out: usdt(),
+                min_amount_out: U128(1)
+            }],
+            None,
+            Some(Deadline::Timestamp(U64(86400 * 1_000_000_000 - 1)))
+        ),
+        deposit = 1
+    );
+    assert_failure(outcome, "E74: deadline of the actions expired");
+}
+
 #[test]
 fn sim_stable_e14 () {
     let root = init_simulator(None);
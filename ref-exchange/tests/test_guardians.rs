@@ -170,6 +170,7 @@ fn guardians_scenario_01() {
                 token_out: eth(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1
@@ -280,6 +281,7 @@ fn guardians_scenario_02() {
                 token_out: eth(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1
@@ -12,7 +12,7 @@
 ///     lp can call [add_liquidity], suggested deposit amount is 0.005, unused part would refund,
 ///   The contract self would be registered by pool creator 
 ///     when [add_simple_pool] and [add_stable_swap_pool], 
-///     suggested deposit amount is 0.01, unused part would refund
+///     suggested deposit amount is 0.05 (it includes price observations of the pool), unused part would refund
 use near_sdk::json_types::{U128};
 use near_sdk_sim::{call, to_yocto};
 
@@ -201,6 +201,7 @@ fn storage_scenario_02() {
                 token_out: usdc(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1
@@ -234,6 +235,7 @@ fn storage_scenario_02() {
                 token_out: usdc(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1
@@ -263,6 +265,7 @@ fn storage_scenario_02() {
                 token_out: usdc(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1
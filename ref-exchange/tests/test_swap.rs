@@ -197,6 +197,7 @@ fn test_swap() {
                 token_out: eth(),
                 min_amount_out: U128(1)
             }],
+            None,
             None
         ),
         deposit = 1